@@ -1,8 +1,21 @@
+mod metrics;
+
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use fsync_core::{spawn_task, RemoteCfg, SyncManager, TaskConfig};
-use fsync_remote_sftp::SftpRemote;
-use std::{fs, path::Path, sync::Arc};
+use clap::{Parser, Subcommand};
+use fsync_core::{
+    audit_task, repair_task, AuditOptions, AuditReport, RemoteAttrRules, RemoteCfg, SyncManager,
+    TaskConfig,
+};
+use fsync_remote_sftp::{ReconnectingSftpRemote, SftpRemote};
+use metrics::Metrics;
+use serde::Serialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 #[derive(Parser)]
 #[command(name = "fsync", version, about = "FSync – directory sync CLI")]
@@ -10,17 +23,101 @@ struct Cli {
     /// Path to config file (TOML / JSON / YAML)
     #[arg(short, long, default_value = "config.yaml")]
     config: String,
+
+    /// Serve Prometheus metrics on this address (e.g. 127.0.0.1:9184) while
+    /// syncing
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compare local and remote trees and print the differences as JSON
+    Audit {
+        /// Only audit the task with this name or id
+        #[arg(long)]
+        task: Option<String>,
+        /// Also compare content checksums of files with matching sizes
+        #[arg(long)]
+        checksums: bool,
+        /// Upload missing or outdated files, then audit again
+        #[arg(long)]
+        repair: bool,
+        /// With --repair, also remove remote files that do not exist locally
+        #[arg(long, requires = "repair")]
+        delete_extra: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct TaskAudit {
+    task: String,
+    /// Number of remote ops applied by `--repair`
+    repaired: usize,
+    report: AuditReport,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    let tasks = load_tasks(&cli.config)?;
+
+    match cli.command {
+        None => run(tasks, cli.metrics_addr).await,
+        Some(Command::Audit {
+            task,
+            checksums,
+            repair,
+            delete_extra,
+        }) => {
+            let tasks = tasks
+                .into_iter()
+                .filter(|cfg| {
+                    task.as_deref()
+                        .is_none_or(|task| cfg.name == task || cfg.id.to_string() == task)
+                })
+                .collect::<Vec<_>>();
+            if tasks.is_empty() {
+                return Err(anyhow!("no task matches `{}`", task.unwrap_or_default()));
+            }
+            let options = AuditOptions { checksums };
+            let mut audits = Vec::new();
+            for cfg in tasks {
+                let remote = connect_remote(&cfg).await?;
+                let mut report = audit_task(&cfg, &remote, options).await?;
+                let mut repaired = 0;
+                if repair && !report.is_clean() {
+                    // Also records the repairs in the task's state store, so
+                    // the next run does not redo them.
+                    repaired = repair_task(&cfg, &remote, &report, delete_extra).await?;
+                    if repaired > 0 {
+                        report = audit_task(&cfg, &remote, options).await?;
+                    }
+                }
+                audits.push(TaskAudit {
+                    task: cfg.name.clone(),
+                    repaired,
+                    report,
+                });
+            }
+            println!("{}", serde_json::to_string_pretty(&audits)?);
+            if audits.iter().any(|audit| !audit.report.is_clean()) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
 
-    let text = fs::read_to_string(&cli.config)
-        .map_err(|e| anyhow!("read config {} failed: {e}", cli.config))?;
+fn load_tasks(config: &str) -> Result<Vec<TaskConfig>> {
+    let text =
+        fs::read_to_string(config).map_err(|e| anyhow!("read config {config} failed: {e}"))?;
 
     // Detect format by extension
-    let ext = Path::new(&cli.config)
+    let ext = Path::new(config)
         .extension()
         .and_then(|s| s.to_str())
         .unwrap_or("");
@@ -33,28 +130,145 @@ async fn main() -> Result<()> {
     if tasks.is_empty() {
         return Err(anyhow!("no tasks defined in config"));
     }
+    // Expand `{user}`, `{hostname}`, ... now so bad variables fail at load time.
+    tasks.iter().map(TaskConfig::resolve_templates).collect()
+}
+
+async fn connect_remote(cfg: &TaskConfig) -> Result<SftpRemote> {
+    match &cfg.remote_cfg {
+        RemoteCfg::Sftp {
+            host,
+            user,
+            password,
+            key: _,
+            fingerprints,
+        } => {
+            let attr_rules = RemoteAttrRules::new(&cfg.remote, &cfg.attributes)?;
+            Ok(
+                SftpRemote::connect(host, user, password.as_deref(), fingerprints.clone())
+                    .await?
+                    .with_attr_rules(attr_rules),
+            )
+        }
+    }
+}
 
-    // Spawn every task
-    let manager = SyncManager::new();
-    for cfg in tasks {
-        match &cfg.remote_cfg {
-            RemoteCfg::Sftp {
-                host,
-                user,
-                password,
-                key: _,
-                fingerprints,
-            } => {
-                let remote =
-                    SftpRemote::connect(host, user, password.as_deref(), fingerprints.clone())
-                        .await?;
-                spawn_task(cfg.clone(), remote);
+/// Remote for a long-running task: it reconnects by itself, so the task
+/// starts offline when the server is down.
+async fn reconnecting_remote(cfg: &TaskConfig) -> Result<ReconnectingSftpRemote> {
+    match &cfg.remote_cfg {
+        RemoteCfg::Sftp {
+            host,
+            user,
+            password,
+            key: _,
+            fingerprints,
+        } => {
+            let attr_rules = RemoteAttrRules::new(&cfg.remote, &cfg.attributes)?;
+            let remote =
+                ReconnectingSftpRemote::new(host, user, password.as_deref(), fingerprints.clone())
+                    .with_attr_rules(attr_rules);
+            if let Err(e) = remote.connect().await {
+                eprintln!("{}: cannot reach {host} ({e}), starting offline", cfg.name);
             }
+            Ok(remote)
+        }
+    }
+}
+
+async fn run(tasks: Vec<TaskConfig>, metrics_addr: Option<SocketAddr>) -> Result<()> {
+    // Spawn every task; the manager keeps the handles alive until exit.
+    let manager = Arc::new(Mutex::new(SyncManager::new()));
+    let metrics = Arc::new(Metrics::default());
+    for cfg in &tasks {
+        let remote = reconnecting_remote(cfg).await?;
+        let mut manager = manager.lock().unwrap();
+        manager.start(cfg.clone(), remote);
+        let id = cfg.id.to_string();
+        if let Some(events) = manager.subscribe_events(&id) {
+            metrics.watch(id, cfg.name.clone(), events);
         }
     }
+    if let Some(addr) = metrics_addr {
+        let listener = metrics::serve(addr, metrics, manager.clone());
+        tokio::spawn(async move {
+            if let Err(e) = listener.await {
+                eprintln!("metrics endpoint on {addr} stopped: {e}");
+            }
+        });
+        println!("Serving metrics on http://{addr}/metrics");
+    }
 
-    println!("FSync running... press Ctrl+C to stop");
-    tokio::signal::ctrl_c().await?;
+    println!("FSync running... press Ctrl+C to stop, type `help` for commands");
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                break;
+            }
+            line = lines.next_line() => {
+                match line? {
+                    Some(line) => {
+                        if let Err(e) = run_command(&mut manager.lock().unwrap(), &tasks, &line) {
+                            eprintln!("{e}");
+                        }
+                    }
+                    // stdin closed (e.g. running as a service): keep syncing
+                    None => {
+                        tokio::signal::ctrl_c().await?;
+                        break;
+                    }
+                }
+            }
+        }
+    }
     println!("Stopping");
+    manager.lock().unwrap().stop_all();
+    Ok(())
+}
+
+const COMMANDS_HELP: &str = "\
+commands:
+  rescan [task]           walk the local tree now
+  resync <task> [path..]  upload paths (default: everything) regardless of state
+  pause [task]            stop sending changes to the remote
+  resume [task]           send the changes collected while paused";
+
+/// Handle one line typed on stdin. `task` is a task name or id; commands that
+/// accept an optional task apply to every task when it is omitted.
+fn run_command(manager: &mut SyncManager, tasks: &[TaskConfig], line: &str) -> Result<()> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(());
+    };
+    let task = words.next();
+    let selected = match task {
+        Some(task) => {
+            let cfg = tasks
+                .iter()
+                .find(|cfg| cfg.name == task || cfg.id.to_string() == task)
+                .ok_or_else(|| anyhow!("no task matches `{task}`"))?;
+            vec![cfg.id.to_string()]
+        }
+        None => tasks.iter().map(|cfg| cfg.id.to_string()).collect(),
+    };
+    match command {
+        "rescan" => selected.iter().for_each(|id| manager.rescan(id)),
+        "pause" => selected.iter().for_each(|id| manager.pause(id)),
+        "resume" => selected.iter().for_each(|id| manager.resume(id)),
+        "resync" => {
+            if task.is_none() {
+                return Err(anyhow!("usage: resync <task> [path..]"));
+            }
+            let mut paths = words.map(PathBuf::from).collect::<Vec<_>>();
+            if paths.is_empty() {
+                paths.push(PathBuf::from("."));
+            }
+            manager.resync(&selected[0], paths);
+        }
+        "help" => println!("{COMMANDS_HELP}"),
+        other => return Err(anyhow!("unknown command `{other}`\n{COMMANDS_HELP}")),
+    }
     Ok(())
 }
@@ -0,0 +1,453 @@
+//! Prometheus metrics served on `--metrics-addr`.
+//!
+//! Counters and the batch latency histogram are fed from each task's event
+//! stream; state, queue depth and last success are read from the manager at
+//! scrape time.
+
+use anyhow::Result;
+use fsync_core::{RemoteOp, RemoteOpStatus, ScanReport, SyncManager, TaskEvent, TaskState};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+/// Upper bounds of the batch duration buckets, in seconds.
+const BATCH_BUCKETS: [f64; 9] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0];
+
+const STATES: [&str; 6] = ["idle", "starting", "running", "paused", "offline", "error"];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Keyed by task id
+    tasks: Mutex<BTreeMap<String, TaskMetrics>>,
+}
+
+#[derive(Default)]
+struct TaskMetrics {
+    name: String,
+    /// Keyed by (op kind, status)
+    ops: BTreeMap<(&'static str, &'static str), u64>,
+    bytes_sent: u64,
+    retries: u64,
+    dropped_events: u64,
+    warnings: u64,
+    batch_counts: [u64; BATCH_BUCKETS.len()],
+    batch_count: u64,
+    batch_sum: f64,
+    last_scan: Option<ScanReport>,
+}
+
+impl Metrics {
+    /// Feed the metrics of task `id` from `events` until the task ends.
+    pub fn watch(
+        self: &Arc<Self>,
+        id: String,
+        name: String,
+        mut events: broadcast::Receiver<TaskEvent>,
+    ) {
+        self.tasks
+            .lock()
+            .unwrap()
+            .entry(id.clone())
+            .or_default()
+            .name = name;
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => metrics.record(&id, &event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        if let Some(task) = metrics.tasks.lock().unwrap().get_mut(&id) {
+                            task.dropped_events += skipped;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn record(&self, id: &str, event: &TaskEvent) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let task = tasks.entry(id.to_string()).or_default();
+        match event {
+            TaskEvent::RemoteOp(log) => {
+                let status = match log.status {
+                    RemoteOpStatus::Applied => "applied",
+                    RemoteOpStatus::Failed => "failed",
+                };
+                *task.ops.entry((op_kind(&log.op), status)).or_default() += 1;
+            }
+            TaskEvent::BatchFinished(report) => {
+                task.bytes_sent += report.bytes_sent;
+                task.retries += report.retries;
+                let secs = report.elapsed.as_secs_f64();
+                for (count, bound) in task.batch_counts.iter_mut().zip(BATCH_BUCKETS) {
+                    if secs <= bound {
+                        *count += 1;
+                    }
+                }
+                task.batch_count += 1;
+                task.batch_sum += secs;
+            }
+            TaskEvent::Scan(report) => task.last_scan = Some(report.clone()),
+            TaskEvent::Warning(_) => task.warnings += 1,
+            TaskEvent::State(_) | TaskEvent::Log(_) | TaskEvent::Progress(_) => {}
+        }
+    }
+
+    /// Render every metric in the Prometheus text format.
+    pub fn render(&self, manager: &SyncManager) -> String {
+        let tasks = self.tasks.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "fsync_task_state",
+            "gauge",
+            "Current task state, 1 for the active state",
+        );
+        for (id, task) in tasks.iter() {
+            let Some(state) = manager.state(id) else {
+                continue;
+            };
+            let active = state_name(&state);
+            for state in STATES {
+                let value = u8::from(state == active);
+                let labels = task_labels(id, task);
+                let _ = writeln!(
+                    out,
+                    "fsync_task_state{{{labels},state=\"{state}\"}} {value}"
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_remote_ops_total",
+            "counter",
+            "Remote ops by kind and status",
+        );
+        for (id, task) in tasks.iter() {
+            for ((kind, status), count) in &task.ops {
+                let labels = task_labels(id, task);
+                let _ = writeln!(
+                    out,
+                    "fsync_remote_ops_total{{{labels},kind=\"{kind}\",status=\"{status}\"}} {count}"
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_bytes_sent_total",
+            "counter",
+            "Bytes uploaded",
+        );
+        for (id, task) in tasks.iter() {
+            let _ = writeln!(
+                out,
+                "fsync_bytes_sent_total{{{}}} {}",
+                task_labels(id, task),
+                task.bytes_sent
+            );
+        }
+
+        header(
+            &mut out,
+            "fsync_retries_total",
+            "counter",
+            "Remote op retries",
+        );
+        for (id, task) in tasks.iter() {
+            let _ = writeln!(
+                out,
+                "fsync_retries_total{{{}}} {}",
+                task_labels(id, task),
+                task.retries
+            );
+        }
+
+        header(
+            &mut out,
+            "fsync_dropped_events_total",
+            "counter",
+            "Task events missed by the metrics collector",
+        );
+        for (id, task) in tasks.iter() {
+            let _ = writeln!(
+                out,
+                "fsync_dropped_events_total{{{}}} {}",
+                task_labels(id, task),
+                task.dropped_events
+            );
+        }
+
+        header(
+            &mut out,
+            "fsync_warnings_total",
+            "counter",
+            "Warnings such as lost watcher events",
+        );
+        for (id, task) in tasks.iter() {
+            let _ = writeln!(
+                out,
+                "fsync_warnings_total{{{}}} {}",
+                task_labels(id, task),
+                task.warnings
+            );
+        }
+
+        header(
+            &mut out,
+            "fsync_batch_duration_seconds",
+            "histogram",
+            "Time to apply one batch of remote ops",
+        );
+        for (id, task) in tasks.iter() {
+            let labels = task_labels(id, task);
+            for (count, bound) in task.batch_counts.iter().zip(BATCH_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "fsync_batch_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "fsync_batch_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                task.batch_count
+            );
+            let _ = writeln!(
+                out,
+                "fsync_batch_duration_seconds_sum{{{labels}}} {}",
+                task.batch_sum
+            );
+            let _ = writeln!(
+                out,
+                "fsync_batch_duration_seconds_count{{{labels}}} {}",
+                task.batch_count
+            );
+        }
+
+        header(
+            &mut out,
+            "fsync_last_scan_duration_seconds",
+            "gauge",
+            "Duration of the last periodic scan",
+        );
+        for (id, task) in tasks.iter() {
+            if let Some(scan) = &task.last_scan {
+                let _ = writeln!(
+                    out,
+                    "fsync_last_scan_duration_seconds{{{},full=\"{}\"}} {}",
+                    task_labels(id, task),
+                    scan.full,
+                    scan.elapsed.as_secs_f64()
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_last_scan_files",
+            "gauge",
+            "Files compared with the cache by the last periodic scan",
+        );
+        for (id, task) in tasks.iter() {
+            if let Some(scan) = &task.last_scan {
+                let _ = writeln!(
+                    out,
+                    "fsync_last_scan_files{{{}}} {}",
+                    task_labels(id, task),
+                    scan.files_checked
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_queue_depth",
+            "gauge",
+            "Local changes waiting to be synced",
+        );
+        for (id, task) in tasks.iter() {
+            if let Some(stats) = manager.stats(id) {
+                let _ = writeln!(
+                    out,
+                    "fsync_queue_depth{{{}}} {}",
+                    task_labels(id, task),
+                    stats.queue_depth
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_outstanding_ops",
+            "gauge",
+            "Remote ops in the outbox waiting for a retry",
+        );
+        for (id, task) in tasks.iter() {
+            if let Some(stats) = manager.stats(id) {
+                let _ = writeln!(
+                    out,
+                    "fsync_outstanding_ops{{{}}} {}",
+                    task_labels(id, task),
+                    stats.outstanding_ops
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "fsync_last_success_timestamp_seconds",
+            "gauge",
+            "Unix time of the last batch that completed without errors",
+        );
+        for (id, task) in tasks.iter() {
+            if let Some(last) = manager.stats(id).and_then(|stats| stats.last_success) {
+                let _ = writeln!(
+                    out,
+                    "fsync_last_success_timestamp_seconds{{{}}} {last}",
+                    task_labels(id, task)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Answer `GET /metrics` on `addr` until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    manager: Arc<Mutex<SyncManager>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        let manager = manager.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &metrics, &manager).await {
+                eprintln!("metrics request failed: {e}");
+            }
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    manager: &Mutex<SyncManager>,
+) -> Result<()> {
+    // Only the request line matters; headers and body are ignored.
+    let mut request = vec![0; 1024];
+    let n = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..n]);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render(&manager.lock().unwrap());
+            ("200 OK", body)
+        }
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn task_labels(id: &str, task: &TaskMetrics) -> String {
+    format!("task=\"{}\",task_id=\"{id}\"", escape_label(&task.name))
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn op_kind(op: &RemoteOp) -> &'static str {
+    match op {
+        RemoteOp::Upload { .. } => "upload",
+        RemoteOp::Remove { .. } => "remove",
+        RemoteOp::Rename { .. } => "rename",
+        RemoteOp::MkDir { .. } => "mkdir",
+    }
+}
+
+fn state_name(state: &TaskState) -> &'static str {
+    match state {
+        TaskState::Idle => "idle",
+        TaskState::Starting(_) => "starting",
+        TaskState::Running => "running",
+        TaskState::Paused => "paused",
+        TaskState::Offline(_) => "offline",
+        TaskState::Error(_) => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fsync_core::{BatchReport, RemoteOpLog};
+    use std::time::Duration;
+
+    #[test]
+    fn renders_counters_and_histogram() {
+        let metrics = Metrics::default();
+        metrics.record(
+            "id-1",
+            &TaskEvent::RemoteOp(RemoteOpLog {
+                status: RemoteOpStatus::Applied,
+                op: RemoteOp::Remove {
+                    remote: "/srv/a".into(),
+                },
+                message: String::new(),
+                error: None,
+            }),
+        );
+        metrics.record(
+            "id-1",
+            &TaskEvent::BatchFinished(BatchReport {
+                applied: 1,
+                failed: 0,
+                skipped: 0,
+                bytes_sent: 42,
+                retries: 2,
+                elapsed: Duration::from_secs(2),
+            }),
+        );
+        metrics.tasks.lock().unwrap().get_mut("id-1").unwrap().name = "docs \"a\"".into();
+
+        let text = metrics.render(&SyncManager::new());
+        let labels = r#"task="docs \"a\"",task_id="id-1""#;
+        assert!(text.contains(&format!(
+            "fsync_remote_ops_total{{{labels},kind=\"remove\",status=\"applied\"}} 1"
+        )));
+        assert!(text.contains(&format!("fsync_bytes_sent_total{{{labels}}} 42")));
+        assert!(text.contains(&format!("fsync_retries_total{{{labels}}} 2")));
+        assert!(text.contains(&format!(
+            "fsync_batch_duration_seconds_bucket{{{labels},le=\"1\"}} 0"
+        )));
+        assert!(text.contains(&format!(
+            "fsync_batch_duration_seconds_bucket{{{labels},le=\"5\"}} 1"
+        )));
+        assert!(text.contains(&format!("fsync_batch_duration_seconds_count{{{labels}}} 1")));
+    }
+}
@@ -0,0 +1,165 @@
+//! StateStore at scale, compared with the row-at-a-time statements it used
+//! to run. Run with `cargo bench -p fsync-core --bench state_store`; set
+//! `FSYNC_BENCH_ROWS` to change the table size (default 1,000,000).
+
+use fsync_core::{FileState, StateStore};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const DIRS: usize = 100;
+
+fn key(i: usize) -> String {
+    format!("d{}/s{}/f{i}.txt", i % DIRS, (i / DIRS) % 100)
+}
+
+fn rows(count: usize, mtime: u64) -> Vec<(String, FileState)> {
+    (0..count)
+        .map(|i| {
+            (
+                key(i),
+                FileState {
+                    mtime,
+                    mtime_ns: Some(mtime * 1_000_000_000),
+                    size: Some(i as u64),
+                    hash: None,
+                },
+            )
+        })
+        .collect()
+}
+
+fn report(name: &str, old: Option<Duration>, new: Duration) {
+    match old {
+        Some(old) => println!(
+            "{name:<28} before {:>9.1} ms   after {:>9.1} ms   x{:.1}",
+            old.as_secs_f64() * 1e3,
+            new.as_secs_f64() * 1e3,
+            old.as_secs_f64() / new.as_secs_f64()
+        ),
+        None => println!("{name:<28} {:>9.1} ms", new.as_secs_f64() * 1e3),
+    }
+}
+
+async fn timed<T>(future: impl std::future::Future<Output = T>) -> (T, Duration) {
+    let started = Instant::now();
+    let value = future.await;
+    (value, started.elapsed())
+}
+
+/// The single-row upserts `put_many` used to run.
+async fn put_rows_one_by_one(pool: &SqlitePool, values: &[(String, FileState)]) {
+    let mut tx = pool.begin().await.unwrap();
+    for (key, state) in values {
+        sqlx::query(
+            r#"
+            INSERT INTO file_states (local_path, mtime, mtime_ns, size, hash, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
+            ON CONFLICT(local_path) DO UPDATE SET
+                mtime = excluded.mtime,
+                mtime_ns = excluded.mtime_ns,
+                size = excluded.size,
+                hash = excluded.hash,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(key)
+        .bind(state.mtime as i64)
+        .bind(state.mtime_ns.map(|ns| ns as i64))
+        .bind(state.size.map(|size| size as i64))
+        .bind(state.hash.as_deref())
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    }
+    tx.commit().await.unwrap();
+}
+
+/// Read every key, then delete the matching ones one at a time, as
+/// `remove_tree` and `cleanup_missing` used to.
+async fn delete_rows_one_by_one(pool: &SqlitePool, stale: impl Fn(&str) -> bool) -> usize {
+    let keys = sqlx::query_as::<_, (String,)>("SELECT local_path FROM file_states")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(key,)| key)
+        .filter(|key| stale(key))
+        .collect::<Vec<_>>();
+    let mut tx = pool.begin().await.unwrap();
+    for key in &keys {
+        sqlx::query("DELETE FROM file_states WHERE local_path = ?1")
+            .bind(key)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+    }
+    tx.commit().await.unwrap();
+    keys.len()
+}
+
+async fn open_pool(dir: &Path) -> SqlitePool {
+    let url = format!("sqlite://{}?mode=rwc", dir.join("state.db").display());
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&url)
+        .await
+        .unwrap()
+}
+
+#[tokio::main]
+async fn main() {
+    let count = std::env::var("FSYNC_BENCH_ROWS")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(1_000_000);
+    let dir = std::env::temp_dir().join(format!("fsync-bench-{}", std::process::id()));
+    let store = StateStore::open(0, &dir).await.unwrap();
+    let pool = open_pool(&dir).await;
+    println!("{count} rows in {}", dir.display());
+
+    let values = rows(count, 1);
+    let (_, old) = timed(put_rows_one_by_one(&pool, &values)).await;
+    store.clear().await.unwrap();
+    let (_, new) = timed(store.put_many(&values)).await;
+    report("put_many", Some(old), new);
+
+    let root = "d7".to_string();
+    let prefix = format!("{root}/");
+    let (removed, old) = timed(delete_rows_one_by_one(&pool, |key| {
+        key.starts_with(&prefix)
+    }))
+    .await;
+    store.put_many(&values).await.unwrap();
+    let (removed_now, new) = timed(store.remove_tree(&root)).await;
+    assert_eq!(removed, removed_now.unwrap());
+    report("remove_tree (1% of rows)", Some(old), new);
+
+    store.put_many(&values).await.unwrap();
+    let live = values
+        .iter()
+        .map(|(key, _)| key.clone())
+        .filter(|key| !key.starts_with("d1/"))
+        .collect::<HashSet<_>>();
+    let (removed, old) = timed(delete_rows_one_by_one(&pool, |key| !live.contains(key))).await;
+    store.put_many(&values).await.unwrap();
+    let (removed_now, new) = timed(store.cleanup_missing(&live)).await;
+    assert_eq!(removed, removed_now.unwrap());
+    report("cleanup_missing (1% stale)", Some(old), new);
+
+    store.put_many(&values).await.unwrap();
+    let mut snapshot = HashMap::new();
+    let token = store.refresh(&mut snapshot, 0).await.unwrap();
+    store.put_many(&rows(1_000, 2)).await.unwrap();
+    let (_, old) = timed(store.load_all()).await;
+    let (_, new) = timed(store.refresh(&mut snapshot, token)).await;
+    report("snapshot after 1k writes", Some(old), new);
+
+    let (_, idle) = timed(store.refresh(&mut snapshot, store.change_token())).await;
+    report("snapshot, nothing written", None, idle);
+
+    pool.close().await;
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,175 @@
+//! Rule-based remote permissions and ownership.
+
+use crate::config::{AttrRule, AttrTarget};
+use crate::filter::compile_pattern;
+use crate::utils::{normalize_posix_path_str, relative_posix_path_str};
+use anyhow::{anyhow, Result};
+use globset::GlobSet;
+
+/// Attributes to set on a remote entry. `None` fields are left untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemoteAttrs {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledAttrRule {
+    matcher: GlobSet,
+    target: AttrTarget,
+    attrs: RemoteAttrs,
+}
+
+/// Attribute rules compiled against a task's remote root.
+#[derive(Debug, Clone)]
+pub struct RemoteAttrRules {
+    root: String,
+    rules: Vec<CompiledAttrRule>,
+}
+
+impl RemoteAttrRules {
+    /// Compile `rules` for remote paths below `remote_root`. Invalid globs and
+    /// modes are reported here rather than on the first upload.
+    pub fn new(remote_root: &str, rules: &[AttrRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let matcher = compile_pattern(&rule.pattern.0)
+                    .map_err(|e| anyhow!("invalid attribute pattern `{}`: {e}", rule.pattern.0))?;
+                let mode = rule.mode.as_deref().map(parse_mode).transpose()?;
+                Ok(CompiledAttrRule {
+                    matcher,
+                    target: rule.target,
+                    attrs: RemoteAttrs {
+                        mode,
+                        uid: rule.uid,
+                        gid: rule.gid,
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            root: normalize_posix_path_str(remote_root),
+            rules,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Resolve the attributes for `remote`, or `None` when no rule applies or
+    /// the path lies outside the task root.
+    pub fn lookup(&self, remote: &str, is_dir: bool) -> Option<RemoteAttrs> {
+        let path = normalize_posix_path_str(remote);
+        let rel = relative_posix_path_str(&path, &self.root)?;
+        let mut resolved = None::<RemoteAttrs>;
+        for rule in &self.rules {
+            let target_ok = match rule.target {
+                AttrTarget::Any => true,
+                AttrTarget::File => !is_dir,
+                AttrTarget::Dir => is_dir,
+            };
+            if !target_ok || !rule.matcher.is_match(rel.as_str()) {
+                continue;
+            }
+            let attrs = resolved.get_or_insert_with(RemoteAttrs::default);
+            attrs.mode = rule.attrs.mode.or(attrs.mode);
+            attrs.uid = rule.attrs.uid.or(attrs.uid);
+            attrs.gid = rule.attrs.gid.or(attrs.gid);
+        }
+        resolved
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim();
+    let digits = digits.strip_prefix("0o").unwrap_or(digits);
+    let value =
+        u32::from_str_radix(digits, 8).map_err(|_| anyhow!("invalid octal mode `{mode}`"))?;
+    if value > 0o7777 {
+        return Err(anyhow!("mode `{mode}` is out of range"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Pattern;
+
+    fn rule(pattern: &str, target: AttrTarget, mode: &str) -> AttrRule {
+        AttrRule {
+            pattern: Pattern(pattern.into()),
+            target,
+            mode: Some(mode.into()),
+            uid: None,
+            gid: None,
+        }
+    }
+
+    #[test]
+    fn later_rules_override_earlier_ones() {
+        let rules = RemoteAttrRules::new(
+            "/srv/app",
+            &[
+                rule("**", AttrTarget::Dir, "2775"),
+                rule("**/*.sh", AttrTarget::File, "0755"),
+                rule("config/", AttrTarget::File, "0640"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.lookup("/srv/app/bin/run.sh", false).unwrap().mode,
+            Some(0o755)
+        );
+        assert_eq!(
+            rules
+                .lookup("/srv/app/config/app.yaml", false)
+                .unwrap()
+                .mode,
+            Some(0o640)
+        );
+        assert_eq!(
+            rules.lookup("/srv/app/config", true).unwrap().mode,
+            Some(0o2775)
+        );
+        assert_eq!(rules.lookup("/srv/app/README.md", false), None);
+    }
+
+    #[test]
+    fn paths_outside_root_are_ignored() {
+        let rules =
+            RemoteAttrRules::new("/srv/app", &[rule("**", AttrTarget::Any, "0700")]).unwrap();
+        assert_eq!(rules.lookup("/srv", true), None);
+        assert_eq!(rules.lookup("/srv/application/a", false), None);
+    }
+
+    #[test]
+    fn owner_fields_merge_with_mode() {
+        let mut owner = rule("**", AttrTarget::Any, "0644");
+        owner.mode = None;
+        owner.gid = Some(1001);
+        let rules = RemoteAttrRules::new(
+            "/srv/app",
+            &[rule("*.txt", AttrTarget::File, "0644"), owner],
+        )
+        .unwrap();
+        assert_eq!(
+            rules.lookup("/srv/app/a.txt", false),
+            Some(RemoteAttrs {
+                mode: Some(0o644),
+                uid: None,
+                gid: Some(1001),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_modes() {
+        assert!(RemoteAttrRules::new("/srv", &[rule("*", AttrTarget::Any, "0999")]).is_err());
+        assert!(RemoteAttrRules::new("/srv", &[rule("*", AttrTarget::Any, "17777")]).is_err());
+    }
+}
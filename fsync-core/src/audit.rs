@@ -0,0 +1,472 @@
+//! Read-only drift audit between a task's local tree and its remote tree.
+
+use crate::config::{ChangeDetection, TaskConfig};
+use crate::detect::{hash_file, LocalStat};
+use crate::filter::{parse_size_filter, PathFilter};
+use crate::remote::{RemoteEntry, RemoteFs, RemoteOp, UPLOAD_TEMP_SUFFIX};
+use crate::storage::StateStore;
+use crate::utils::{
+    as_posix_path, join_posix_path, normalize_posix_path_str, relative_posix_path,
+    relative_posix_path_str,
+};
+use anyhow::{anyhow, Result};
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// Synced locally but absent on the remote
+    MissingRemote,
+    /// Present on the remote but not locally
+    ExtraRemote,
+    SizeMismatch,
+    /// Remote copy is older than the local file
+    MtimeMismatch,
+    ChecksumMismatch,
+}
+
+/// One difference found by [`audit_task`]. `path` is relative to the task root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DriftEntry {
+    pub kind: DriftKind,
+    pub path: String,
+    pub local_size: Option<u64>,
+    pub remote_size: Option<u64>,
+    pub local_mtime: Option<u64>,
+    pub remote_mtime: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuditOptions {
+    /// Also compare content checksums of files whose size matches
+    pub checksums: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditReport {
+    pub local_files: usize,
+    pub remote_files: usize,
+    pub entries: Vec<DriftEntry>,
+    /// Files whose checksum could not be compared, with the error
+    pub checksum_errors: Vec<(String, String)>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remote ops that bring the remote back in line with the local tree.
+    /// Extra remote files are only removed when `remove_extra` is set.
+    pub fn repair_ops(&self, cfg: &TaskConfig, remove_extra: bool) -> Vec<RemoteOp> {
+        repair_ops(&self.entries, &cfg.local, &cfg.remote, remove_extra)
+    }
+}
+
+fn repair_ops(
+    entries: &[DriftEntry],
+    local_root: &Path,
+    remote_root: &str,
+    remove_extra: bool,
+) -> Vec<RemoteOp> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let remote = join_posix_path(remote_root, &entry.path);
+            match entry.kind {
+                DriftKind::ExtraRemote if remove_extra => Some(RemoteOp::Remove { remote }),
+                DriftKind::ExtraRemote => None,
+                _ => Some(RemoteOp::Upload {
+                    local: local_root.join(&entry.path),
+                    remote,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Apply the [`repair_ops`](AuditReport::repair_ops) of `report` one by one
+/// and record each applied op in the task's state store, as the task would
+/// have, so the next start does not upload or remove them again. Stops at
+/// the first failing op. Returns the number of ops applied.
+pub async fn repair_task(
+    cfg: &TaskConfig,
+    remote: &impl RemoteFs,
+    report: &AuditReport,
+    remove_extra: bool,
+) -> Result<usize> {
+    let local_root = cfg
+        .local
+        .canonicalize()
+        .unwrap_or_else(|_| cfg.local.clone());
+    let ops = repair_ops(&report.entries, &local_root, &cfg.remote, remove_extra);
+    if ops.is_empty() {
+        return Ok(0);
+    }
+    let store = StateStore::open(0, cfg.state_dir()).await?;
+    let remote_root = normalize_posix_path_str(&cfg.remote);
+    let hash = cfg.change_detection == ChangeDetection::Hash;
+    let mut applied = 0;
+    for op in ops {
+        match &op {
+            RemoteOp::Upload { local, .. } => {
+                // Stat before the upload so a change made during it is not
+                // recorded as synced.
+                let key = relative_posix_path(local, &local_root)
+                    .ok_or_else(|| anyhow!("{} is outside the task root", local.display()))?;
+                let local = local.clone();
+                let state = tokio::task::spawn_blocking(move || {
+                    let stat = LocalStat::from_metadata(&std::fs::metadata(&local)?)
+                        .ok_or_else(|| anyhow!("no modification time for {}", local.display()))?;
+                    let hash = hash.then(|| hash_file(&local)).transpose()?;
+                    Ok::<_, anyhow::Error>(stat.to_state(hash))
+                })
+                .await??;
+                remote.apply_batch(vec![op]).await?;
+                store.put(key, state).await?;
+            }
+            RemoteOp::Remove { remote: path } => {
+                let key = relative_posix_path_str(&normalize_posix_path_str(path), &remote_root);
+                remote.apply_batch(vec![op.clone()]).await?;
+                if let Some(key) = key {
+                    store.remove_tree(&key).await?;
+                }
+            }
+            RemoteOp::MkDir { .. } | RemoteOp::Rename { .. } => {
+                remote.apply_batch(vec![op]).await?;
+            }
+        }
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// Compare `cfg.local` (through the task's filters) with the remote listing
+/// below `cfg.remote`. Nothing is changed on either side.
+pub async fn audit_task(
+    cfg: &TaskConfig,
+    remote: &impl RemoteFs,
+    options: AuditOptions,
+) -> Result<AuditReport> {
+    let local_root = cfg
+        .local
+        .canonicalize()
+        .unwrap_or_else(|_| cfg.local.clone());
+    let scan_root = local_root.clone();
+    let filter = PathFilter::from_rules(&local_root, &cfg.filters).with_ignore_files(cfg.gitignore);
+    let size_range = parse_size_filter(cfg.size.as_deref());
+    let local =
+        tokio::task::spawn_blocking(move || scan_local(&scan_root, &filter, size_range)).await??;
+
+    let remote_root = normalize_posix_path_str(&cfg.remote);
+    let filter = PathFilter::from_rules(&local_root, &cfg.filters).with_ignore_files(cfg.gitignore);
+    let remote_files = remote
+        .list_tree(&cfg.remote)
+        .await?
+        .into_iter()
+        .filter(|entry| !entry.is_dir && !entry.path.ends_with(UPLOAD_TEMP_SUFFIX))
+        .filter_map(|entry| {
+            let rel =
+                relative_posix_path_str(&normalize_posix_path_str(&entry.path), &remote_root)?;
+            // Remote files outside the sync scope are not drift.
+            let in_scope =
+                local.contains_key(&rel) || remote_only_in_scope(&filter, &local_root, &rel);
+            in_scope.then_some((rel, entry))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut report = AuditReport {
+        local_files: local.len(),
+        remote_files: remote_files.len(),
+        entries: compare_listings(&local, &remote_files),
+        checksum_errors: Vec::new(),
+    };
+
+    if options.checksums {
+        let candidates = local
+            .iter()
+            .filter(|(rel, stat)| {
+                remote_files
+                    .get(*rel)
+                    .is_some_and(|entry| entry.size == stat.size)
+            })
+            .map(|(rel, stat)| (rel.clone(), *stat))
+            .collect::<Vec<_>>();
+        let results = stream::iter(candidates)
+            .map(|(rel, stat)| {
+                let local_path = local_root.join(&rel);
+                let remote_path = remote_files[&rel].path.clone();
+                async move {
+                    let result = match remote.checksum(&remote_path).await {
+                        Ok(remote_hash) => tokio::task::spawn_blocking(move || {
+                            remote_hash.matches_file(&local_path, &hash_file(&local_path)?)
+                        })
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(|same| same),
+                        Err(e) => Err(e),
+                    };
+                    (rel, stat, result)
+                }
+            })
+            .buffer_unordered(cfg.max_parallel_ops.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        for (rel, stat, result) in results {
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    let entry = &remote_files[&rel];
+                    report.entries.retain(|drift| drift.path != rel);
+                    report.entries.push(DriftEntry {
+                        kind: DriftKind::ChecksumMismatch,
+                        path: rel,
+                        local_size: Some(stat.size),
+                        remote_size: Some(entry.size),
+                        local_mtime: Some(stat.mtime_secs()),
+                        remote_mtime: entry.mtime,
+                    });
+                }
+                Err(e) => report.checksum_errors.push((rel, e.to_string())),
+            }
+        }
+        report.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        report.checksum_errors.sort();
+    }
+    Ok(report)
+}
+
+fn scan_local(
+    root: &Path,
+    filter: &PathFilter,
+    (size_min, size_max): (Option<u64>, Option<u64>),
+) -> Result<BTreeMap<String, LocalStat>> {
+    let mut tracked = BTreeMap::new();
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_type().is_dir() || filter.check_dir(entry.path())
+        })
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() || !filter.check(entry.path()) {
+            continue;
+        }
+        let Some(stat) = entry
+            .metadata()
+            .ok()
+            .and_then(|meta| LocalStat::from_metadata(&meta))
+        else {
+            continue;
+        };
+        if size_min.is_some_and(|min| stat.size < min)
+            || size_max.is_some_and(|max| stat.size > max)
+        {
+            continue;
+        }
+        let rel: PathBuf = entry.path().strip_prefix(root)?.to_path_buf();
+        tracked.insert(as_posix_path(&rel), stat);
+    }
+    Ok(tracked)
+}
+
+/// Whether a file that only exists remotely would be synced if it existed
+/// locally. Files present locally but skipped by the size filter are not.
+fn remote_only_in_scope(filter: &PathFilter, local_root: &Path, rel: &str) -> bool {
+    let local_path = local_root.join(rel);
+    !local_path.exists()
+        && filter.check(&local_path)
+        && local_path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != local_root)
+            .all(|dir| filter.check_dir(dir))
+}
+
+/// Size / mtime / presence differences between two listings keyed by
+/// relative path. Remote mtimes newer than the local one are expected, since
+/// uploads do not preserve modification times.
+fn compare_listings(
+    local: &BTreeMap<String, LocalStat>,
+    remote: &BTreeMap<String, RemoteEntry>,
+) -> Vec<DriftEntry> {
+    let mut entries = Vec::new();
+    for (rel, stat) in local {
+        let remote_entry = remote.get(rel);
+        let kind = match remote_entry {
+            None => DriftKind::MissingRemote,
+            Some(entry) if entry.size != stat.size => DriftKind::SizeMismatch,
+            Some(entry) if entry.mtime.is_some_and(|mtime| mtime < stat.mtime_secs()) => {
+                DriftKind::MtimeMismatch
+            }
+            Some(_) => continue,
+        };
+        entries.push(DriftEntry {
+            kind,
+            path: rel.clone(),
+            local_size: Some(stat.size),
+            remote_size: remote_entry.map(|entry| entry.size),
+            local_mtime: Some(stat.mtime_secs()),
+            remote_mtime: remote_entry.and_then(|entry| entry.mtime),
+        });
+    }
+    for (rel, entry) in remote {
+        if !local.contains_key(rel) {
+            entries.push(DriftEntry {
+                kind: DriftKind::ExtraRemote,
+                path: rel.clone(),
+                local_size: None,
+                remote_size: Some(entry.size),
+                local_mtime: None,
+                remote_mtime: entry.mtime,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(size: u64, mtime: u64) -> LocalStat {
+        LocalStat {
+            size,
+            mtime_ns: mtime * 1_000_000_000,
+        }
+    }
+
+    fn remote(path: &str, size: u64, mtime: u64) -> RemoteEntry {
+        RemoteEntry {
+            path: format!("/srv/{path}"),
+            is_dir: false,
+            size,
+            mtime: Some(mtime),
+        }
+    }
+
+    #[test]
+    fn reports_each_kind_of_drift() {
+        let local = BTreeMap::from([
+            ("same".to_string(), local(1, 10)),
+            ("newer_remote".to_string(), local(1, 10)),
+            ("missing".to_string(), local(1, 10)),
+            ("resized".to_string(), local(1, 10)),
+            ("stale".to_string(), local(1, 10)),
+        ]);
+        let remote = BTreeMap::from([
+            ("same".to_string(), remote("same", 1, 10)),
+            ("newer_remote".to_string(), remote("newer_remote", 1, 99)),
+            ("resized".to_string(), remote("resized", 2, 10)),
+            ("stale".to_string(), remote("stale", 1, 5)),
+            ("extra".to_string(), remote("extra", 3, 10)),
+        ]);
+        let kinds = compare_listings(&local, &remote)
+            .into_iter()
+            .map(|entry| (entry.path, entry.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("extra".to_string(), DriftKind::ExtraRemote),
+                ("missing".to_string(), DriftKind::MissingRemote),
+                ("resized".to_string(), DriftKind::SizeMismatch),
+                ("stale".to_string(), DriftKind::MtimeMismatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn repair_removes_extras_only_on_request() {
+        let entries = [
+            DriftEntry {
+                kind: DriftKind::MissingRemote,
+                path: "a/b.txt".into(),
+                local_size: Some(1),
+                remote_size: None,
+                local_mtime: Some(1),
+                remote_mtime: None,
+            },
+            DriftEntry {
+                kind: DriftKind::ExtraRemote,
+                path: "old.txt".into(),
+                local_size: None,
+                remote_size: Some(1),
+                local_mtime: None,
+                remote_mtime: Some(1),
+            },
+        ];
+        let local_root = Path::new("/local");
+        assert_eq!(repair_ops(&entries, local_root, "/srv", false).len(), 1);
+        let ops = repair_ops(&entries, local_root, "/srv/", true);
+        assert!(matches!(
+            &ops[0],
+            RemoteOp::Upload { remote, .. } if remote == "/srv/a/b.txt"
+        ));
+        assert!(matches!(
+            &ops[1],
+            RemoteOp::Remove { remote } if remote == "/srv/old.txt"
+        ));
+    }
+
+    #[test]
+    fn remote_only_files_respect_directory_excludes() {
+        let root = Path::new("/nonexistent-audit-root");
+        let filter = PathFilter::new(root, &[], &[crate::config::Pattern("**/cache/**".into())]);
+        assert!(remote_only_in_scope(&filter, root, "a/b.txt"));
+        assert!(!remote_only_in_scope(&filter, root, "a/cache/b.txt"));
+    }
+
+    #[tokio::test]
+    async fn repair_records_applied_ops_in_the_state_store() {
+        use crate::remote::fake::FakeRemote;
+
+        let dir = std::env::temp_dir().join(format!("fsync-repair-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("local");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.txt"), "aaa").unwrap();
+        std::fs::write(root.join("sub/b.txt"), "bbbb").unwrap();
+        let cfg: TaskConfig = serde_json::from_value(serde_json::json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "repair",
+            "local": root,
+            "remote": "/srv",
+            "cache_dir": dir.join("cache"),
+            "remote_cfg": { "type": "sftp", "host": "h", "user": "u", "password": null, "key": null },
+        }))
+        .unwrap();
+        let store = StateStore::open(0, cfg.state_dir()).await.unwrap();
+        store
+            .put("old/extra.txt".into(), local(1, 1).to_state(None))
+            .await
+            .unwrap();
+        let remote = FakeRemote::with_files([
+            remote("sub/b.txt", 1, u64::MAX),
+            remote("old/extra.txt", 1, u64::MAX),
+        ]);
+
+        let report = audit_task(&cfg, &remote, AuditOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(report.entries.len(), 3);
+        let applied = repair_task(&cfg, &remote, &report, true).await.unwrap();
+
+        assert_eq!(applied, 3);
+        assert_eq!(remote.paths(), ["/srv/a.txt", "/srv/sub/b.txt"]);
+        let mut keys = store
+            .load_all()
+            .await
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["a.txt", "sub/b.txt"]);
+        assert_eq!(store.get("sub/b.txt").await.unwrap().unwrap().size, Some(4));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
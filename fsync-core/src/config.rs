@@ -1,13 +1,53 @@
-use serde::{Deserialize, Serialize};
+use crate::filter::legacy_filter_rules;
+use crate::template::{expand_template, TemplateContext};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Glob pattern (wrapper type for clarity)
 /// For now we store as plain String and defer compilation to `globset::Pattern` during runtime.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Pattern(pub String);
 
+/// Which remote entries an [`AttrRule`] applies to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttrTarget {
+    #[default]
+    Any,
+    File,
+    Dir,
+}
+
+/// Remote ownership / permission rule. `pattern` uses the same glob semantics
+/// as include / exclude patterns and is matched against the path relative to
+/// the task root. When several rules match, later rules override earlier ones
+/// field by field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttrRule {
+    pub pattern: Pattern,
+    #[serde(default)]
+    pub target: AttrTarget,
+    /// Octal permission bits, e.g. "0755" or "2775"
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+}
+
+/// Upload priority rule. Files matching `pattern` (relative to the task root)
+/// are uploaded before lower-priority files; the last matching rule wins and
+/// unmatched files have priority 0.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityRule {
+    pub pattern: Pattern,
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum RemoteCfg {
     /// SFTP remote endpoint
@@ -22,7 +62,67 @@ pub enum RemoteCfg {
     // Future variants: Http { ... }, Grpc { ... }
 }
 
+/// How files without recorded state (e.g. on a task's first run) are synced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialSync {
+    /// Upload every untracked file
+    #[default]
+    Upload,
+    /// Adopt remote files with the same size and an mtime at least as new as
+    /// the local one, upload the rest
+    Adopt,
+    /// Adopt remote files with the same size and content checksum. Every
+    /// same-size file is read in full on both sides: backends hash on the
+    /// server when they can, otherwise the remote copy is downloaded, so the
+    /// first run can cost as much transfer as uploading everything
+    AdoptChecksum,
+}
+
+/// What happens on the remote when a synced local path is deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionPolicy {
+    /// Remove the remote copy, including deletions made while the task was
+    /// not running
+    #[default]
+    Propagate,
+    /// Leave the remote copy in place and only forget the local state
+    Keep,
+}
+
+/// How a task decides whether a local file differs from what was last synced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeDetection {
+    /// Compare the modification time
+    #[default]
+    Mtime,
+    /// Compare size and modification time
+    MtimeSize,
+    /// Like `mtime_size`, but when only the mtime changed compare content
+    /// hashes before uploading
+    Hash,
+}
+
+/// How a task learns about local changes between scans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatcherMode {
+    /// `poll` on network and FUSE filesystems, `native` elsewhere
+    #[default]
+    Auto,
+    /// Operating system change notifications (inotify, FSEvents, ...)
+    Native,
+    /// Compare the tree against its last listing every `poll_interval_ms`
+    Poll,
+    /// Native notifications backed by polling, for mounts that report only
+    /// some changes
+    Hybrid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct TaskConfig {
     pub id: Uuid,
     pub name: String,
@@ -30,15 +130,32 @@ pub struct TaskConfig {
     pub remote: String,
     #[serde(default)]
     pub cache_dir: Option<PathBuf>,
+    /// Ordered filter rules, the last match wins; see
+    /// [`PathFilter`](crate::PathFilter) for the syntax
     #[serde(default)]
-    pub include: Vec<Pattern>,
+    pub filters: Vec<Pattern>,
+    /// Skip what `.gitignore` files and `.git/info/exclude` ignore, on top
+    /// of `.fsyncignore` files, which always apply
     #[serde(default)]
-    pub exclude: Vec<Pattern>,
+    pub gitignore: bool,
+    /// Interval between scan passes, which only read the directories that
+    /// changed; slow passes stretch it. Values below the default make the
+    /// scanner poll the tree almost continuously
     #[serde(default = "TaskConfig::default_scan_ms")]
     pub scan_ms: u64,
+    /// Interval between scan passes that check every file, not only those
+    /// in changed directories (0 disables them after the first one)
+    #[serde(default = "TaskConfig::default_full_scan_secs")]
+    pub full_scan_secs: u64,
     /// Optional size filter in the form of "..", "..n", "n..", or "m..n" (bytes)
     #[serde(default)]
     pub size: Option<String>,
+    #[serde(default)]
+    pub change_detection: ChangeDetection,
+    #[serde(default)]
+    pub deletion_policy: DeletionPolicy,
+    #[serde(default)]
+    pub initial_sync: InitialSync,
     /// Max retry attempts for remote operations
     #[serde(default = "TaskConfig::default_retry_max")]
     pub retry_max: u32,
@@ -47,20 +164,248 @@ pub struct TaskConfig {
     pub retry_backoff_ms: u64,
     #[serde(default = "TaskConfig::default_debounce_ms")]
     pub debounce_ms: u64,
+    #[serde(default)]
+    pub watcher_mode: WatcherMode,
+    /// Interval between polls when the watcher polls the tree. Each poll
+    /// stats every file under the root, excluded ones too
+    #[serde(default = "TaskConfig::default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Maximum number of independent remote ops run concurrently
+    #[serde(default = "TaskConfig::default_max_parallel_ops")]
+    pub max_parallel_ops: usize,
+    /// Remote `*.fsync.tmp` files older than this many seconds are removed
+    /// when the task starts (0 disables the sweep)
+    #[serde(default = "TaskConfig::default_stale_temp_secs")]
+    pub stale_temp_secs: u64,
+    /// Glob priorities for ordering uploads, see [`PriorityRule`]
+    #[serde(default)]
+    pub priorities: Vec<PriorityRule>,
+    /// Remote mode / owner rules applied after uploads and mkdirs
+    #[serde(default)]
+    pub attributes: Vec<AttrRule>,
     pub remote_cfg: RemoteCfg,
 }
 
+impl Serialize for TaskConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TaskConfig::serialize(self, serializer)
+    }
+}
+
+/// Also accepts the `include` / `exclude` lists of older configs and folds
+/// them into `filters`, ahead of any rules already there.
+impl<'de> Deserialize<'de> for TaskConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct WithLegacyFilters {
+            #[serde(flatten, with = "TaskConfig")]
+            cfg: TaskConfig,
+            #[serde(default)]
+            include: Vec<Pattern>,
+            #[serde(default)]
+            exclude: Vec<Pattern>,
+        }
+
+        let WithLegacyFilters {
+            mut cfg,
+            include,
+            exclude,
+        } = WithLegacyFilters::deserialize(deserializer)?;
+        if !include.is_empty() || !exclude.is_empty() {
+            let mut filters = legacy_filter_rules(&include, &exclude);
+            filters.append(&mut cfg.filters);
+            cfg.filters = filters;
+        }
+        Ok(cfg)
+    }
+}
+
 impl TaskConfig {
-    fn default_debounce_ms() -> u64 {
+    /// Return a copy with template variables (`{user}`, `{hostname}`,
+    /// `{task_name}`, `{date}`, `{env:NAME}`, ...) expanded in the remote path
+    /// and the remote host, user and key path. Passwords are taken literally,
+    /// braces included. Call this when the config is loaded so that unknown
+    /// or unresolved variables are reported up front.
+    pub fn resolve_templates(&self) -> Result<TaskConfig> {
+        let ctx = TemplateContext {
+            task_name: self.name.clone(),
+            task_id: self.id.to_string(),
+        };
+        let expand = |field: &str, value: &str| {
+            expand_template(value, &ctx)
+                .map_err(|e| anyhow!("task `{}`: invalid {field}: {e}", self.name))
+        };
+
+        let mut cfg = self.clone();
+        cfg.remote = expand("remote path", &self.remote)?;
+        cfg.remote_cfg = match &self.remote_cfg {
+            RemoteCfg::Sftp {
+                host,
+                user,
+                password,
+                key,
+                fingerprints,
+            } => RemoteCfg::Sftp {
+                host: expand("host", host)?,
+                user: expand("user", user)?,
+                password: password.clone(),
+                key: key
+                    .as_ref()
+                    .map(|key| expand("key path", &key.to_string_lossy()).map(PathBuf::from))
+                    .transpose()?,
+                fingerprints: fingerprints.clone(),
+            },
+        };
+        Ok(cfg)
+    }
+
+    /// Check that `new` can replace this config on a running task. Filters,
+    /// size range, change detection, deletion policy and the timing / retry
+    /// tunables apply in place; everything that identifies the task or its
+    /// remote connection needs a restart.
+    pub fn check_live_update(&self, new: &TaskConfig) -> Result<()> {
+        let fixed = [
+            ("task id", self.id != new.id),
+            ("local path", self.local != new.local),
+            ("remote path", self.remote != new.remote),
+            ("remote profile", self.remote_cfg != new.remote_cfg),
+            ("cache directory", self.cache_dir != new.cache_dir),
+            ("remote attributes", self.attributes != new.attributes),
+            ("watcher mode", self.watcher_mode != new.watcher_mode),
+            (
+                "poll interval",
+                self.poll_interval_ms != new.poll_interval_ms,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| field)
+        .collect::<Vec<_>>();
+        if fixed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "task `{}`: changing the {} requires restarting the task",
+                self.name,
+                fixed.join(", ")
+            ))
+        }
+    }
+
+    /// Directory of the task's state store, `cache/<id>` unless configured.
+    pub fn state_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("cache/{}", self.id)))
+    }
+
+    // Defaults of the optional fields, public for front ends that build
+    // configs field by field.
+    pub fn default_debounce_ms() -> u64 {
         150
     }
-    fn default_scan_ms() -> u64 {
-        300
+    pub fn default_poll_interval_ms() -> u64 {
+        30_000
+    }
+    pub fn default_scan_ms() -> u64 {
+        30_000
+    }
+    pub fn default_full_scan_secs() -> u64 {
+        600
     }
-    fn default_retry_max() -> u32 {
+    pub fn default_retry_max() -> u32 {
         3
     }
-    fn default_retry_backoff_ms() -> u64 {
+    pub fn default_retry_backoff_ms() -> u64 {
         500
     }
+    pub fn default_max_parallel_ops() -> usize {
+        4
+    }
+    pub fn default_stale_temp_secs() -> u64 {
+        3_600
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TaskConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "name": "docs",
+            "local": "/data/docs",
+            "remote": "/srv/docs",
+            "remote_cfg": { "type": "sftp", "host": "h", "user": "u", "password": null, "key": null },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn live_update_names_every_field_that_needs_a_restart() {
+        let old = config();
+        let mut new = old.clone();
+        new.local = "/data/other".into();
+        new.remote = "/srv/other".into();
+        new.remote_cfg = RemoteCfg::Sftp {
+            host: "elsewhere".into(),
+            user: "u".into(),
+            password: None,
+            key: None,
+            fingerprints: None,
+        };
+        new.cache_dir = Some("/var/cache/docs".into());
+        new.attributes = vec![AttrRule {
+            pattern: Pattern("bin/".into()),
+            target: AttrTarget::Dir,
+            mode: Some("0755".into()),
+            uid: None,
+            gid: None,
+        }];
+        new.watcher_mode = WatcherMode::Poll;
+        new.poll_interval_ms += 1;
+
+        let error = old.check_live_update(&new).unwrap_err().to_string();
+        for field in [
+            "local path",
+            "remote path",
+            "remote profile",
+            "cache directory",
+            "remote attributes",
+            "watcher mode",
+            "poll interval",
+        ] {
+            assert!(error.contains(field), "{field} missing from: {error}");
+        }
+        assert!(!error.contains("task id"), "{error}");
+
+        let mut local_only = old.clone();
+        local_only.local = "/data/other".into();
+        let error = old.check_live_update(&local_only).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "task `docs`: changing the local path requires restarting the task"
+        );
+    }
+
+    #[test]
+    fn live_update_accepts_filters_sizes_and_timing() {
+        let old = config();
+        let mut new = old.clone();
+        new.name = "renamed".into();
+        new.filters = vec![Pattern("*.tmp".into()), Pattern("!keep.tmp".into())];
+        new.gitignore = true;
+        new.size = Some("..1048576".into());
+        new.scan_ms += 1;
+        new.full_scan_secs = 0;
+        new.debounce_ms += 1;
+        new.retry_max += 1;
+        new.retry_backoff_ms += 1;
+        new.max_parallel_ops += 1;
+        new.change_detection = ChangeDetection::Hash;
+        new.deletion_policy = DeletionPolicy::Keep;
+
+        old.check_live_update(&new).unwrap();
+    }
 }
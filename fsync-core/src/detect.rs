@@ -0,0 +1,201 @@
+//! Local change detection against the recorded [`FileState`].
+
+use crate::config::ChangeDetection;
+use crate::storage::FileState;
+use anyhow::Result;
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Size and modification time of a local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LocalStat {
+    pub size: u64,
+    pub mtime_ns: u64,
+}
+
+impl LocalStat {
+    pub fn from_metadata(meta: &std::fs::Metadata) -> Option<Self> {
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: meta.len(),
+            mtime_ns: u64::try_from(mtime.as_nanos()).ok()?,
+        })
+    }
+
+    pub fn mtime_secs(&self) -> u64 {
+        self.mtime_ns / 1_000_000_000
+    }
+
+    /// State to record once this version of the file has been synced.
+    pub fn to_state(self, hash: Option<String>) -> FileState {
+        FileState {
+            mtime: self.mtime_secs(),
+            mtime_ns: Some(self.mtime_ns),
+            size: Some(self.size),
+            hash,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    Unchanged,
+    Changed,
+    /// Only the mtime moved; compare the content hash with the recorded one.
+    NeedsHash,
+}
+
+pub(crate) fn detect_change(
+    mode: ChangeDetection,
+    stat: &LocalStat,
+    last: Option<&FileState>,
+) -> Change {
+    let Some(last) = last else {
+        return Change::Changed;
+    };
+    // Rows from before nanosecond tracking only carry whole seconds.
+    let mtime_same = match last.mtime_ns {
+        Some(ns) => ns == stat.mtime_ns,
+        None => last.mtime == stat.mtime_secs(),
+    };
+    let size_changed = last.size.is_some_and(|size| size != stat.size);
+    match mode {
+        ChangeDetection::Mtime if mtime_same => Change::Unchanged,
+        ChangeDetection::MtimeSize | ChangeDetection::Hash if size_changed => Change::Changed,
+        ChangeDetection::MtimeSize | ChangeDetection::Hash if mtime_same => Change::Unchanged,
+        ChangeDetection::Hash if last.hash.is_some() => Change::NeedsHash,
+        _ => Change::Changed,
+    }
+}
+
+/// Hex BLAKE3 digest of a file's content.
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0_u8; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hex SHA-256 digest of a file's content, for remotes that cannot hash
+/// with BLAKE3.
+pub(crate) fn hash_file_sha256(path: &Path) -> Result<String> {
+    use sha2::Digest;
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = sha2::Sha256::new();
+    let mut buf = vec![0_u8; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000_000_000;
+
+    fn stat(size: u64, mtime_ns: u64) -> LocalStat {
+        LocalStat { size, mtime_ns }
+    }
+
+    fn recorded(size: u64, mtime_ns: u64, hash: Option<&str>) -> FileState {
+        stat(size, mtime_ns).to_state(hash.map(str::to_string))
+    }
+
+    #[test]
+    fn mtime_mode_sees_sub_second_edits() {
+        let last = recorded(10, 5 * SEC + 100, None);
+        assert_eq!(
+            detect_change(
+                ChangeDetection::Mtime,
+                &stat(10, 5 * SEC + 100),
+                Some(&last)
+            ),
+            Change::Unchanged
+        );
+        assert_eq!(
+            detect_change(
+                ChangeDetection::Mtime,
+                &stat(10, 5 * SEC + 200),
+                Some(&last)
+            ),
+            Change::Changed
+        );
+    }
+
+    #[test]
+    fn legacy_rows_compare_whole_seconds() {
+        let last = FileState {
+            mtime: 5,
+            ..FileState::default()
+        };
+        assert_eq!(
+            detect_change(ChangeDetection::Hash, &stat(10, 5 * SEC + 7), Some(&last)),
+            Change::Unchanged
+        );
+        assert_eq!(
+            detect_change(ChangeDetection::Hash, &stat(10, 6 * SEC), Some(&last)),
+            Change::Changed
+        );
+    }
+
+    #[test]
+    fn size_mode_catches_same_mtime_size_change() {
+        let last = recorded(10, 5 * SEC, None);
+        assert_eq!(
+            detect_change(ChangeDetection::MtimeSize, &stat(11, 5 * SEC), Some(&last)),
+            Change::Changed
+        );
+        assert_eq!(
+            detect_change(ChangeDetection::Mtime, &stat(11, 5 * SEC), Some(&last)),
+            Change::Unchanged
+        );
+    }
+
+    #[test]
+    fn hash_mode_hashes_only_touched_files() {
+        let last = recorded(10, 5 * SEC, Some("abc"));
+        assert_eq!(
+            detect_change(ChangeDetection::Hash, &stat(10, 9 * SEC), Some(&last)),
+            Change::NeedsHash
+        );
+        assert_eq!(
+            detect_change(ChangeDetection::Hash, &stat(12, 9 * SEC), Some(&last)),
+            Change::Changed
+        );
+        assert_eq!(
+            detect_change(ChangeDetection::Hash, &stat(10, 9 * SEC), None),
+            Change::Changed
+        );
+    }
+
+    #[test]
+    fn hashes_file_content() {
+        let dir = std::env::temp_dir().join(format!("fsync-detect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            blake3::hash(b"hello").to_hex().to_string()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,86 +1,597 @@
+use crate::config::Pattern;
+use crate::ignore::{IgnoreMatch, IgnoreTree};
 use crate::utils::{normalize_key_path, relative_posix_path_str};
 use globset::{Glob, GlobSet, GlobSetBuilder};
-use std::path::Path;
+use regex_automata::meta::Regex;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use walkdir::WalkDir;
 
-/// Runtime filter compiled from include / exclude pattern lists.
+/// Prefix of rules written as a regular expression.
+const REGEX_RULE_PREFIX: &str = "re:";
+
+/// Runtime filter compiled from an ordered rule list, optionally combined
+/// with the ignore files found in the tree.
+///
+/// Each rule is a glob matched against the path relative to the root. A
+/// plain rule excludes what it matches and a rule starting with `!` keeps
+/// it; the last matching rule wins and unmatched paths are kept. A trailing
+/// `/` makes the rule match directories (and everything below them) but
+/// not files of that name; without a further `/` it also matches at any
+/// depth. Rules starting with `re:` are regular expressions instead of
+/// globs, searched in the relative path.
 #[derive(Debug, Clone)]
 pub struct PathFilter {
     root: String,
-    include: GlobSet,
-    exclude: GlobSet,
+    root_dir: PathBuf,
+    /// Position in the rule list and text of each rule that compiled
+    rules: Vec<(usize, String)>,
+    /// Whether each rule keeps what it matches
+    keep: Vec<bool>,
+    /// Rule index of each glob or regex of the rules that keep, with the
+    /// literal start of the glob and whether that is the whole glob; `None`
+    /// for regexes
+    keep_prefixes: Vec<(usize, Option<(String, bool)>)>,
+    globs: GlobSet,
+    glob_rules: Vec<RuleGlob>,
+    regexes: Vec<(usize, Arc<Regex>)>,
+    /// Shared by clones, so a reloaded ignore file applies to all of them
+    ignore: Option<Arc<IgnoreTree>>,
+    /// Size range of [`parse_size_filter`], only used by
+    /// [`explain`](Self::explain) and [`preview`](Self::preview); `check`
+    /// leaves sizes to callers, which have the metadata at hand
+    size_range: (Option<u64>, Option<u64>),
+}
+
+/// One glob a rule expanded to.
+#[derive(Debug, Clone)]
+struct RuleGlob {
+    rule: usize,
+    /// Matches directories only
+    dir_only: bool,
+    glob: String,
+}
+
+/// Rule index of a match, with the index of the glob in `glob_rules` that
+/// matched or `None` for a regex.
+type RuleMatch = (usize, Option<usize>);
+
+/// Whether [`PathFilter::explain`] syncs a path, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterExplanation {
+    /// Path relative to the root, `/`-separated
+    pub path: String,
+    pub synced: bool,
+    pub reason: FilterReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterReason {
+    /// No rule matched, and unmatched paths are synced
+    NoRule,
+    /// The last matching rule; `index` is its position in the rule list and
+    /// `matched` the glob it expanded to, or the regex, that matched
+    Rule {
+        index: usize,
+        rule: String,
+        matched: String,
+    },
+    /// A rule of an ignore file; `line` is 1-based
+    IgnoreFile {
+        file: PathBuf,
+        line: usize,
+        rule: String,
+    },
+    /// The file size is outside the size filter
+    Size {
+        size: u64,
+        min: Option<u64>,
+        max: Option<u64>,
+    },
+    /// A directory above the path is not walked into, for `reason`
+    Directory {
+        dir: String,
+        reason: Box<FilterReason>,
+    },
+}
+
+/// What a filter syncs of the tree below its root.
+#[derive(Debug, Clone, Default)]
+pub struct FilterPreview {
+    /// Files synced
+    pub synced: usize,
+    /// Files left out, not counting those in skipped directories
+    pub excluded: usize,
+    /// Directories not walked into
+    pub skipped_dirs: usize,
+    pub synced_samples: Vec<String>,
+    /// Excluded files and skipped directories
+    pub excluded_samples: Vec<FilterExplanation>,
+    /// The walk stopped before the end of the tree
+    pub truncated: bool,
 }
 
 impl PathFilter {
-    /// Build a filter from lists. Empty include list means "include all".
-    pub fn new<P: AsRef<Path>>(
-        root: P,
-        include: &[crate::config::Pattern],
-        exclude: &[crate::config::Pattern],
-    ) -> Self {
-        let mut inc_builder = GlobSetBuilder::new();
-        let mut exc_builder = GlobSetBuilder::new();
-        for pat in include {
-            add_pattern(&mut inc_builder, &pat.0);
-        }
-        for pat in exclude {
-            add_pattern(&mut exc_builder, &pat.0);
+    /// Build a filter from include / exclude lists as older configs had
+    /// them: an empty include list means "include all".
+    pub fn new<P: AsRef<Path>>(root: P, include: &[Pattern], exclude: &[Pattern]) -> Self {
+        Self::from_rules(root, &legacy_filter_rules(include, exclude))
+    }
+
+    /// Build a filter from an ordered rule list, see [`PathFilter`]. Rules
+    /// that do not compile are skipped.
+    pub fn from_rules<P: AsRef<Path>>(root: P, rules: &[Pattern]) -> Self {
+        let mut compiled = Vec::with_capacity(rules.len());
+        let mut keep = Vec::with_capacity(rules.len());
+        let mut builder = GlobSetBuilder::new();
+        let mut glob_rules = Vec::new();
+        let mut regexes = Vec::new();
+        let mut keep_prefixes = Vec::new();
+        for (position, rule) in rules.iter().enumerate() {
+            let (keeps, body) = split_rule(&rule.0);
+            let index = keep.len();
+            if let Some(regex) = body.strip_prefix(REGEX_RULE_PREFIX) {
+                let Ok(regex) = Regex::new(regex) else {
+                    tracing::debug!(rule = %rule.0, "skipping invalid filter regex");
+                    continue;
+                };
+                regexes.push((index, Arc::new(regex)));
+                if keeps {
+                    keep_prefixes.push((index, None));
+                }
+            } else {
+                let globs = expand_rule(body)
+                    .into_iter()
+                    .filter_map(|(glob, dir_only)| Some((Glob::new(&glob).ok()?, dir_only)))
+                    .collect::<Vec<_>>();
+                if globs.is_empty() {
+                    tracing::debug!(rule = %rule.0, "skipping invalid filter rule");
+                    continue;
+                }
+                for (glob, dir_only) in globs {
+                    if keeps {
+                        keep_prefixes.push((index, Some(literal_prefix(glob.glob()))));
+                    }
+                    glob_rules.push(RuleGlob {
+                        rule: index,
+                        dir_only,
+                        glob: glob.glob().to_string(),
+                    });
+                    builder.add(glob);
+                }
+            }
+            compiled.push((position, rule.0.trim().to_string()));
+            keep.push(keeps);
         }
         Self {
             root: normalize_key_path(root.as_ref()),
-            include: inc_builder
-                .build()
-                .unwrap_or_else(|_| GlobSetBuilder::new().build().unwrap()),
-            exclude: exc_builder
+            root_dir: root.as_ref().to_path_buf(),
+            rules: compiled,
+            keep,
+            keep_prefixes,
+            globs: builder
                 .build()
                 .unwrap_or_else(|_| GlobSetBuilder::new().build().unwrap()),
+            glob_rules,
+            regexes,
+            ignore: None,
+            size_range: (None, None),
         }
     }
 
+    /// Also skip what `.fsyncignore` files below the root ignore, and with
+    /// `gitignore` what `.gitignore` files and `.git/info/exclude` ignore.
+    pub fn with_ignore_files(mut self, gitignore: bool) -> Self {
+        self.ignore = Some(Arc::new(IgnoreTree::new(self.root_dir.clone(), gitignore)));
+        self
+    }
+
+    /// Report files outside the size range of a task's `size` setting in
+    /// [`explain`](Self::explain) and [`preview`](Self::preview).
+    pub fn with_size_filter(mut self, size: Option<&str>) -> Self {
+        self.size_range = parse_size_filter(size);
+        self
+    }
+
     /// Determine whether a given path should be synced.
     pub fn check<P: AsRef<Path>>(&self, path: P) -> bool {
-        let path = self.match_path(path.as_ref());
-        let included = self.include.len() == 0 || self.include.is_match(path.as_str());
-        let excluded = self.exclude.is_match(path.as_str());
-        included && !excluded
+        let (path, inside) = self.match_path(path.as_ref());
+        let kept = self
+            .last_match(&path, false)
+            .is_none_or(|(rule, _)| self.keep[rule]);
+        kept && !self.ignored(&path, inside, false)
     }
 
     /// Determine whether a directory subtree should be traversed or created.
     ///
-    /// Include patterns are intentionally not applied here: a directory can fail
-    /// an include rule while still containing files that pass it. Exclude
-    /// patterns do apply to the directory itself and to synthetic descendants so
-    /// patterns such as `**/__pycache__/**` prune the `__pycache__` directory.
+    /// A directory is pruned only when the last rule matching it excludes and
+    /// no rule after that one keeps anything: a later `!` rule may keep files
+    /// below it. Rules are matched against the directory itself and against
+    /// a synthetic descendant, so patterns such as `**/__pycache__/**` prune
+    /// the `__pycache__` directory.
     pub fn check_dir<P: AsRef<Path>>(&self, path: P) -> bool {
-        let path = self.match_path(path.as_ref());
-        !self.excludes_dir(&path)
+        let (path, inside) = self.match_path(path.as_ref());
+        !self.excludes_dir(&path) && !self.ignored(&path, inside, true)
+    }
+
+    /// Tell whether `path` is synced and what decided it: the last rule
+    /// matching it, an ignore file, the size filter, or a directory above
+    /// it that is not walked into. The path is looked up on disk to tell
+    /// directories from files and to get the file size.
+    pub fn explain<P: AsRef<Path>>(&self, path: P) -> FilterExplanation {
+        let meta = std::fs::metadata(path.as_ref()).ok();
+        let is_dir = meta.as_ref().is_some_and(|meta| meta.is_dir());
+        let size = meta.filter(|meta| meta.is_file()).map(|meta| meta.len());
+        let (path, inside) = self.match_path(path.as_ref());
+        let (synced, reason) = self.reason(&path, inside, is_dir, size);
+        FilterExplanation {
+            path,
+            synced,
+            reason,
+        }
     }
 
-    fn match_path(&self, path: &Path) -> String {
+    /// Walk the tree below the root as a sync would, counting what is
+    /// synced and keeping up to `samples` example paths of each kind. Stops
+    /// after `max_entries` entries.
+    pub fn preview(&self, samples: usize, max_entries: usize) -> FilterPreview {
+        let mut preview = FilterPreview::default();
+        let mut walk = WalkDir::new(&self.root_dir).into_iter();
+        let mut seen = 0;
+        while let Some(entry) = walk.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if entry.depth() == 0 {
+                continue;
+            }
+            if seen == max_entries {
+                preview.truncated = true;
+                break;
+            }
+            seen += 1;
+            let is_dir = entry.file_type().is_dir();
+            let kept = if is_dir {
+                self.check_dir(entry.path())
+            } else if entry.file_type().is_file() {
+                let size = entry.metadata().map(|meta| meta.len()).unwrap_or(0);
+                self.check(entry.path()) && self.size_fits(size)
+            } else {
+                continue;
+            };
+            if is_dir {
+                if kept {
+                    continue;
+                }
+                walk.skip_current_dir();
+                preview.skipped_dirs += 1;
+            } else if kept {
+                preview.synced += 1;
+                if preview.synced_samples.len() < samples {
+                    let (path, _) = self.match_path(entry.path());
+                    preview.synced_samples.push(path);
+                }
+                continue;
+            } else {
+                preview.excluded += 1;
+            }
+            if preview.excluded_samples.len() < samples {
+                preview.excluded_samples.push(self.explain(entry.path()));
+            }
+        }
+        preview
+    }
+
+    /// If `path` is an ignore file this filter reads, forget its rules so
+    /// they are read again, and return the directory they apply to. Paths
+    /// below it may have changed verdict.
+    pub fn reload_ignore_file<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let ignore = self.ignore.as_ref()?;
+        let (rel, inside) = self.match_path(path.as_ref());
+        if !inside {
+            return None;
+        }
+        ignore.reload(path.as_ref(), &rel)
+    }
+
+    /// Forget the rules of ignore files that changed on disk since they were
+    /// read, for changes no watcher reported. Returns whether any did.
+    pub(crate) fn reload_changed_ignore_files(&self) -> bool {
+        self.ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.reload_changed())
+    }
+
+    /// Path relative to the root, or the whole path when it is outside.
+    fn match_path(&self, path: &Path) -> (String, bool) {
         let path = normalize_key_path(path);
-        relative_posix_path_str(&path, &self.root).unwrap_or(path)
+        match relative_posix_path_str(&path, &self.root) {
+            Some(rel) => (rel, true),
+            None => (path, false),
+        }
     }
 
-    fn excludes_dir(&self, path: &str) -> bool {
-        if self.exclude.is_match(path) {
-            return true;
+    /// Only paths inside the root have ignore files above them.
+    fn ignored(&self, path: &str, inside: bool, is_dir: bool) -> bool {
+        inside
+            && self
+                .ignore
+                .as_ref()
+                .is_some_and(|ignore| ignore.is_ignored(path, is_dir))
+    }
+
+    fn size_fits(&self, size: u64) -> bool {
+        let (min, max) = self.size_range;
+        min.is_none_or(|min| size >= min) && max.is_none_or(|max| size <= max)
+    }
+
+    fn reason(
+        &self,
+        path: &str,
+        inside: bool,
+        is_dir: bool,
+        size: Option<u64>,
+    ) -> (bool, FilterReason) {
+        if inside {
+            let skipped = path
+                .match_indices('/')
+                .map(|(end, _)| &path[..end])
+                .find_map(|dir| Some((dir, self.dir_exclusion(dir)?)));
+            if let Some((dir, reason)) = skipped {
+                let reason = FilterReason::Directory {
+                    dir: dir.to_string(),
+                    reason: Box::new(reason),
+                };
+                return (false, reason);
+            }
+        }
+        let found = if is_dir {
+            self.dir_match(path)
+        } else {
+            self.last_match(path, false)
+        };
+        if let Some(found) = found
+            .filter(|(rule, _)| !self.keep[*rule] && (!is_dir || !self.may_keep_below(path, *rule)))
+        {
+            return (false, self.rule_reason(found));
+        }
+        if let Some(found) = self.ignore_source(path, inside, is_dir) {
+            return (false, found.into());
+        }
+        if let Some(size) = size.filter(|size| !self.size_fits(*size)) {
+            let (min, max) = self.size_range;
+            return (false, FilterReason::Size { size, min, max });
+        }
+        (
+            true,
+            found.map_or(FilterReason::NoRule, |found| self.rule_reason(found)),
+        )
+    }
+
+    /// Why directory `dir` is not walked into, ignoring the directories
+    /// above it.
+    fn dir_exclusion(&self, dir: &str) -> Option<FilterReason> {
+        if let Some(found) = self
+            .dir_match(dir)
+            .filter(|(rule, _)| !self.keep[*rule] && !self.may_keep_below(dir, *rule))
+        {
+            return Some(self.rule_reason(found));
+        }
+        self.ignore_source(dir, true, true).map(Into::into)
+    }
+
+    /// The ignore file rule that ignores `path` itself.
+    fn ignore_source(&self, path: &str, inside: bool, is_dir: bool) -> Option<IgnoreMatch> {
+        if !inside {
+            return None;
         }
+        self.ignore
+            .as_ref()?
+            .source(path, is_dir)
+            .filter(|found| !found.negated)
+    }
+
+    fn rule_reason(&self, (rule, glob): RuleMatch) -> FilterReason {
+        let (index, text) = &self.rules[rule];
+        let matched = match glob {
+            Some(glob) => self.glob_rules[glob].glob.clone(),
+            None => split_rule(text)
+                .1
+                .trim_start_matches(REGEX_RULE_PREFIX)
+                .to_string(),
+        };
+        FilterReason::Rule {
+            index: *index,
+            rule: text.clone(),
+            matched,
+        }
+    }
+
+    /// The last rule matching `path`.
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<RuleMatch> {
+        let glob = self
+            .globs
+            .matches(path)
+            .into_iter()
+            .filter(|glob| is_dir || !self.glob_rules[*glob].dir_only)
+            .map(|glob| (self.glob_rules[glob].rule, Some(glob)))
+            .max_by_key(|(rule, _)| *rule);
+        let regex = self
+            .regexes
+            .iter()
+            .rev()
+            .find(|(_, regex)| regex.is_match(path))
+            .map(|(rule, _)| (*rule, None));
+        glob.into_iter().chain(regex).max_by_key(|(rule, _)| *rule)
+    }
 
+    /// The last rule matching directory `path`, itself or a synthetic
+    /// descendant; on a tie the match of the directory itself.
+    fn dir_match(&self, path: &str) -> Option<RuleMatch> {
         let path = path.trim_end_matches('/');
-        self.exclude.is_match(format!("{path}/").as_str())
-            || self
-                .exclude
-                .is_match(format!("{path}/__fsync_probe__").as_str())
+        [
+            self.last_match(path, true),
+            self.last_match(&format!("{path}/"), true),
+            self.last_match(&format!("{path}/__fsync_probe__"), false),
+        ]
+        .into_iter()
+        .rev()
+        .flatten()
+        .max_by_key(|(rule, _)| *rule)
+    }
+
+    fn excludes_dir(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        self.dir_match(path)
+            .is_some_and(|(rule, _)| !self.keep[rule] && !self.may_keep_below(path, rule))
+    }
+
+    /// Whether a rule after `after` that keeps could match a path below
+    /// directory `dir`.
+    fn may_keep_below(&self, dir: &str, after: usize) -> bool {
+        let dir = format!("{dir}/");
+        self.keep_prefixes
+            .iter()
+            .filter(|(rule, _)| *rule > after)
+            .any(|(_, prefix)| match prefix {
+                Some((literal, true)) => literal.starts_with(&dir),
+                Some((literal, false)) => literal.starts_with(&dir) || dir.starts_with(literal),
+                None => true,
+            })
     }
 }
 
-fn add_pattern(builder: &mut GlobSetBuilder, pattern: &str) {
-    for pattern in expand_pattern(pattern) {
-        if let Ok(glob) = Glob::new(&pattern) {
-            builder.add(glob);
+impl From<IgnoreMatch> for FilterReason {
+    fn from(found: IgnoreMatch) -> Self {
+        FilterReason::IgnoreFile {
+            file: found.file,
+            line: found.line,
+            rule: found.rule,
+        }
+    }
+}
+
+impl fmt::Display for FilterReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterReason::NoRule => write!(f, "no rule matches"),
+            FilterReason::Rule {
+                index,
+                rule,
+                matched,
+            } => {
+                write!(f, "rule {} `{rule}`", index + 1)?;
+                if split_rule(rule).1 != matched {
+                    write!(f, " (as `{matched}`)")?;
+                }
+                Ok(())
+            }
+            FilterReason::IgnoreFile { file, line, rule } => {
+                write!(f, "`{rule}` in {}:{line}", file.display())
+            }
+            FilterReason::Size { size, min, max } => {
+                write!(f, "size {size} is outside ")?;
+                match (min, max) {
+                    (Some(min), Some(max)) => write!(f, "{min}..{max}"),
+                    (Some(min), None) => write!(f, "{min}.."),
+                    (None, Some(max)) => write!(f, "..{max}"),
+                    (None, None) => write!(f, ".."),
+                }
+            }
+            FilterReason::Directory { dir, reason } => write!(f, "{dir}/ is skipped: {reason}"),
+        }
+    }
+}
+
+impl fmt::Display for FilterExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.synced { "synced" } else { "excluded" };
+        write!(f, "{} is {verdict}: {}", self.path, self.reason)
+    }
+}
+
+/// Ordered rules equivalent to the include / exclude lists of older
+/// configs: everything is excluded unless an include matches, then the
+/// excludes apply.
+pub fn legacy_filter_rules(include: &[Pattern], exclude: &[Pattern]) -> Vec<Pattern> {
+    let mut rules = Vec::with_capacity(include.len() + exclude.len() + 1);
+    if !include.is_empty() {
+        rules.push(Pattern("**".into()));
+        rules.extend(
+            include
+                .iter()
+                .map(|pattern| Pattern(format!("!{}", pattern.0))),
+        );
+    }
+    rules.extend(exclude.iter().cloned());
+    rules
+}
+
+/// Bounds of a `size` setting: `min..max` with either side optional, or a
+/// single minimum. Unparsable bounds are ignored.
+pub(crate) fn parse_size_filter(input: Option<&str>) -> (Option<u64>, Option<u64>) {
+    if let Some(s) = input {
+        let s = s.trim();
+        if s.is_empty() {
+            return (None, None);
+        }
+        if let Some((a, b)) = s.split_once("..") {
+            let min = if a.is_empty() {
+                None
+            } else {
+                a.parse::<u64>().ok()
+            };
+            let max = if b.is_empty() {
+                None
+            } else {
+                b.parse::<u64>().ok()
+            };
+            return (min, max);
         }
+        if let Ok(n) = s.parse::<u64>() {
+            return (Some(n), None);
+        }
+    }
+    (None, None)
+}
+
+/// Start of `glob` up to its first wildcard, and whether that is all of it.
+fn literal_prefix(glob: &str) -> (String, bool) {
+    let end = glob.find(['*', '?', '[', '{', '\\']).unwrap_or(glob.len());
+    (glob[..end].to_string(), end == glob.len())
+}
+
+/// Whether the rule keeps what it matches, and the rule without its `!`.
+fn split_rule(rule: &str) -> (bool, &str) {
+    let rule = rule.trim();
+    match rule.strip_prefix('!') {
+        Some(body) => (true, body.trim_start()),
+        None => (false, rule),
     }
 }
 
+/// Compile a single pattern with the same expansion rules `PathFilter` uses.
+pub(crate) fn compile_pattern(pattern: &str) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in expand_pattern(pattern) {
+        builder.add(Glob::new(&pattern)?);
+    }
+    builder.build()
+}
+
+/// Globs of one filter rule, each with whether it only matches directories.
+fn expand_rule(pattern: &str) -> Vec<(String, bool)> {
+    let dir_only = pattern.trim().ends_with('/');
+    expand_pattern(pattern)
+        .into_iter()
+        .map(|glob| {
+            let below = glob.ends_with("/**");
+            (glob, dir_only && !below)
+        })
+        .collect()
+}
+
 fn expand_pattern(pattern: &str) -> Vec<String> {
     let pattern = pattern.trim().replace('\\', "/");
     if pattern.is_empty() {
@@ -225,4 +736,193 @@ mod tests {
         assert!(filter.check("D:/Workspace/mix/project/other/foo/bar/a.txt"));
         assert!(filter.check_dir("D:/Workspace/mix/project/other/foo/bar"));
     }
+
+    #[test]
+    fn ordered_rules_last_match_wins() {
+        let rules = [
+            Pattern("data/**".into()),
+            Pattern("!data/schema/*.json".into()),
+            Pattern("build/".into()),
+            Pattern(r"re:(^|/)~\$[^/]*$".into()),
+            Pattern("*.tmp".into()),
+            Pattern("!keep.tmp".into()),
+        ];
+        let filter = PathFilter::from_rules("/work/project", &rules);
+
+        assert!(!filter.check("/work/project/data/raw/a.csv"));
+        assert!(filter.check("/work/project/data/schema/users.json"));
+        assert!(!filter.check("/work/project/data/schema/users.csv"));
+        // a later `!` rule may keep files below, so the walk goes on
+        assert!(filter.check_dir("/work/project/data"));
+        assert!(filter.check_dir("/work/project/data/schema"));
+        assert!(!filter.check_dir("/work/project/data/raw"));
+
+        assert!(!filter.check_dir("/work/project/build"));
+        assert!(!filter.check_dir("/work/project/app/build"));
+        assert!(!filter.check("/work/project/app/build/out.o"));
+        assert!(filter.check("/work/project/docs/build"));
+
+        assert!(!filter.check("/work/project/docs/~$report.docx"));
+        assert!(filter.check("/work/project/docs/report~$.docx"));
+        assert!(!filter.check("/work/project/scratch.tmp"));
+        assert!(filter.check("/work/project/keep.tmp"));
+        assert!(filter.check("/work/project/src/main.rs"));
+    }
+
+    #[test]
+    fn legacy_lists_keep_their_meaning() {
+        let include = [Pattern("**/*.rs".into())];
+        let exclude = [Pattern("tests/".into())];
+        let rules = legacy_filter_rules(&include, &exclude);
+        assert_eq!(
+            rules.iter().map(|rule| rule.0.as_str()).collect::<Vec<_>>(),
+            ["**", "!**/*.rs", "tests/"]
+        );
+
+        let cfg: crate::TaskConfig = serde_json::from_value(serde_json::json!({
+            "id": "6f0c7a3c-2f68-4a44-a3b9-7a1f5a6c9d10",
+            "name": "legacy", "local": "/work/project", "remote": "/srv",
+            "include": ["**/*.rs"], "exclude": ["tests/"], "filters": ["!tests/keep.rs"],
+            "remote_cfg": {"type": "sftp", "host": "h", "user": "u"}
+        }))
+        .unwrap();
+        assert_eq!(cfg.filters.len(), 4);
+        let saved = serde_json::to_value(&cfg).unwrap();
+        assert!(saved.get("include").is_none());
+
+        let filter = PathFilter::from_rules("/work/project", &cfg.filters);
+        assert!(filter.check("/work/project/src/lib.rs"));
+        assert!(!filter.check("/work/project/README.md"));
+        assert!(!filter.check("/work/project/tests/main.rs"));
+        assert!(filter.check("/work/project/tests/keep.rs"));
+        assert!(filter.check_dir("/work/project/src"));
+        assert!(filter.check_dir("/work/project/tests"));
+
+        let without_keep = PathFilter::new("/work/project", &include, &exclude);
+        assert!(!without_keep.check_dir("/work/project/tests"));
+    }
+
+    #[test]
+    fn ignore_files_combine_with_patterns() {
+        let root = std::env::temp_dir().join(format!(
+            "fsync-filter-ignore-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(root.join("web/node_modules")).unwrap();
+        std::fs::write(root.join(".gitignore"), "node_modules/\n").unwrap();
+        std::fs::write(root.join("web/.fsyncignore"), "*.map\n").unwrap();
+        let exclude = vec![Pattern("**/*.bak".into())];
+
+        let filter = PathFilter::new(&root, &[], &exclude).with_ignore_files(true);
+        assert!(filter.check(root.join("web/app.js")));
+        assert!(!filter.check(root.join("web/app.js.map")));
+        assert!(!filter.check(root.join("web/app.js.bak")));
+        assert!(!filter.check_dir(root.join("web/node_modules")));
+        assert!(!filter.check(root.join("web/node_modules/lib/index.js")));
+
+        let fsync_only = PathFilter::new(&root, &[], &exclude).with_ignore_files(false);
+        assert!(fsync_only.check_dir(root.join("web/node_modules")));
+        assert!(!fsync_only.check(root.join("web/app.js.map")));
+
+        std::fs::write(root.join("web/.fsyncignore"), "").unwrap();
+        assert_eq!(
+            filter.reload_ignore_file(root.join("web/.fsyncignore")),
+            Some(root.join("web"))
+        );
+        assert!(filter.check(root.join("web/app.js.map")));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn explain_names_the_deciding_rule() {
+        let root = std::env::temp_dir().join(format!(
+            "fsync-filter-explain-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(root.join("build/out")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join(".fsyncignore"), "# local\n*.log\n").unwrap();
+        std::fs::write(root.join("build/out/app.o"), "o").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/debug.log"), "log").unwrap();
+        std::fs::write(root.join("src/big.rs"), vec![b' '; 64]).unwrap();
+        std::fs::write(root.join("notes.tmp"), "tmp").unwrap();
+        std::fs::write(root.join("keep.tmp"), "tmp").unwrap();
+        let rules = [
+            Pattern("build/".into()),
+            Pattern("not[valid".into()),
+            Pattern("*.tmp".into()),
+            Pattern("!keep.tmp".into()),
+        ];
+        let filter = PathFilter::from_rules(&root, &rules)
+            .with_ignore_files(false)
+            .with_size_filter(Some("..32"));
+
+        let explained = filter.explain(root.join("build/out/app.o"));
+        assert_eq!(explained.path, "build/out/app.o");
+        assert!(!explained.synced);
+        let FilterReason::Directory { dir, reason } = &explained.reason else {
+            panic!("unexpected reason {:?}", explained.reason);
+        };
+        assert_eq!(dir, "build");
+        assert_eq!(
+            **reason,
+            FilterReason::Rule {
+                index: 0,
+                rule: "build/".into(),
+                matched: "build".into(),
+            }
+        );
+
+        let explained = filter.explain(root.join("notes.tmp"));
+        assert!(!explained.synced);
+        assert_eq!(
+            explained.to_string(),
+            "notes.tmp is excluded: rule 3 `*.tmp`"
+        );
+        let explained = filter.explain(root.join("keep.tmp"));
+        assert!(explained.synced);
+        assert_eq!(
+            explained.to_string(),
+            "keep.tmp is synced: rule 4 `!keep.tmp`"
+        );
+
+        assert_eq!(
+            filter.explain(root.join("src/debug.log")).reason,
+            FilterReason::IgnoreFile {
+                file: root.join(".fsyncignore"),
+                line: 2,
+                rule: "*.log".into(),
+            }
+        );
+        assert_eq!(
+            filter.explain(root.join("src/big.rs")).reason,
+            FilterReason::Size {
+                size: 64,
+                min: None,
+                max: Some(32),
+            }
+        );
+        let explained = filter.explain(root.join("src/main.rs"));
+        assert!(explained.synced);
+        assert_eq!(explained.reason, FilterReason::NoRule);
+
+        let preview = filter.preview(10, 1_000);
+        assert_eq!(preview.skipped_dirs, 1);
+        assert_eq!(preview.excluded, 3);
+        assert_eq!(preview.synced, 3);
+        assert_eq!(preview.excluded_samples.len(), 4);
+        assert!(!preview.truncated);
+        let mut synced = preview.synced_samples.clone();
+        synced.sort();
+        assert_eq!(synced, [".fsyncignore", "keep.tmp", "src/main.rs"]);
+        assert!(filter.preview(1, 2).truncated);
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
@@ -0,0 +1,267 @@
+//! Dependency graph over planned remote ops.
+//!
+//! The planner emits ops in a valid sequential order. Two ops are ordered in
+//! the graph only when the paths they touch are equal or nested, which covers
+//! mkdir before children, rename before writes under the new path and a parent
+//! remove after child moves. Everything else may run concurrently.
+
+use crate::remote::RemoteOp;
+use crate::utils::normalize_posix_path_str;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeState {
+    Waiting,
+    Running,
+    Done,
+    Failed,
+    Skipped,
+}
+
+/// Schedules the ops of one batch. Ready ops are handed out in planner order,
+/// so upload priorities still apply among independent ops.
+#[derive(Debug)]
+pub(crate) struct OpScheduler {
+    dependents: Vec<Vec<usize>>,
+    remaining: Vec<usize>,
+    states: Vec<NodeState>,
+    ready: BinaryHeap<Reverse<usize>>,
+}
+
+impl OpScheduler {
+    pub fn new<'a>(ops: impl IntoIterator<Item = &'a RemoteOp>) -> Self {
+        let deps = op_dependencies(ops);
+        let mut dependents = vec![Vec::new(); deps.len()];
+        let mut remaining = vec![0; deps.len()];
+        for (node, prerequisites) in deps.iter().enumerate() {
+            remaining[node] = prerequisites.len();
+            for &prerequisite in prerequisites {
+                dependents[prerequisite].push(node);
+            }
+        }
+        let ready = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count == 0)
+            .map(|(node, _)| Reverse(node))
+            .collect();
+        Self {
+            states: vec![NodeState::Waiting; deps.len()],
+            dependents,
+            remaining,
+            ready,
+        }
+    }
+
+    /// Next op whose prerequisites have all succeeded.
+    pub fn next_ready(&mut self) -> Option<usize> {
+        let Reverse(node) = self.ready.pop()?;
+        self.states[node] = NodeState::Running;
+        Some(node)
+    }
+
+    pub fn complete(&mut self, node: usize) {
+        self.states[node] = NodeState::Done;
+        for &dependent in &self.dependents[node] {
+            self.remaining[dependent] -= 1;
+            if self.remaining[dependent] == 0 && self.states[dependent] == NodeState::Waiting {
+                self.ready.push(Reverse(dependent));
+            }
+        }
+    }
+
+    /// Mark `node` failed and return every op that can no longer run because
+    /// it depends on it, directly or transitively.
+    pub fn fail(&mut self, node: usize) -> Vec<usize> {
+        self.states[node] = NodeState::Failed;
+        let mut skipped = Vec::new();
+        let mut stack = self.dependents[node].clone();
+        while let Some(dependent) = stack.pop() {
+            if self.states[dependent] != NodeState::Waiting {
+                continue;
+            }
+            self.states[dependent] = NodeState::Skipped;
+            skipped.push(dependent);
+            stack.extend_from_slice(&self.dependents[dependent]);
+        }
+        skipped.sort_unstable();
+        skipped
+    }
+}
+
+/// Prerequisites of every op, as indices into `ops`.
+fn op_dependencies<'a>(ops: impl IntoIterator<Item = &'a RemoteOp>) -> Vec<Vec<usize>> {
+    // Last op that touched exactly this path.
+    let mut last_at: HashMap<String, usize> = HashMap::new();
+    // Ops that touched something strictly below this path since the last op
+    // on the path itself.
+    let mut below: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut deps = Vec::new();
+
+    for (node, op) in ops.into_iter().enumerate() {
+        let paths = touched_paths(op);
+        let mut prerequisites = Vec::new();
+        for path in &paths {
+            for ancestor in path_and_ancestors(path) {
+                if let Some(&prev) = last_at.get(ancestor) {
+                    prerequisites.push(prev);
+                }
+            }
+            if let Some(nested) = below.get(path) {
+                prerequisites.extend_from_slice(nested);
+            }
+        }
+        for path in &paths {
+            last_at.insert(path.clone(), node);
+            below.remove(path);
+            for ancestor in path_and_ancestors(path).skip(1) {
+                below.entry(ancestor.to_string()).or_default().push(node);
+            }
+        }
+        prerequisites.sort_unstable();
+        prerequisites.dedup();
+        deps.push(prerequisites);
+    }
+    deps
+}
+
+fn touched_paths(op: &RemoteOp) -> Vec<String> {
+    let normalize = |path: &str| {
+        let path = normalize_posix_path_str(path);
+        let trimmed = path.trim_end_matches('/');
+        if trimmed.is_empty() {
+            path
+        } else {
+            trimmed.to_string()
+        }
+    };
+    match op {
+        RemoteOp::Upload { remote, .. }
+        | RemoteOp::Remove { remote }
+        | RemoteOp::MkDir { remote } => vec![normalize(remote)],
+        RemoteOp::Rename { from, to } => vec![normalize(from), normalize(to)],
+    }
+}
+
+/// `a/b/c`, `a/b`, `a` (or `/a/b`, `/a`, `/`).
+fn path_and_ancestors(path: &str) -> impl Iterator<Item = &str> {
+    let mut next = Some(path);
+    std::iter::from_fn(move || {
+        let current = next?;
+        next = match current.rfind('/') {
+            Some(0) if current.len() > 1 => Some("/"),
+            Some(0) | None => None,
+            Some(idx) => Some(&current[..idx]),
+        };
+        Some(current)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn upload(remote: &str) -> RemoteOp {
+        RemoteOp::Upload {
+            local: PathBuf::from("/local/file"),
+            remote: remote.into(),
+        }
+    }
+
+    fn mkdir(remote: &str) -> RemoteOp {
+        RemoteOp::MkDir {
+            remote: remote.into(),
+        }
+    }
+
+    fn remove(remote: &str) -> RemoteOp {
+        RemoteOp::Remove {
+            remote: remote.into(),
+        }
+    }
+
+    fn rename(from: &str, to: &str) -> RemoteOp {
+        RemoteOp::Rename {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn lists_path_and_ancestors() {
+        assert_eq!(
+            path_and_ancestors("/srv/a/b").collect::<Vec<_>>(),
+            vec!["/srv/a/b", "/srv/a", "/srv", "/"]
+        );
+        assert_eq!(
+            path_and_ancestors("a/b").collect::<Vec<_>>(),
+            vec!["a/b", "a"]
+        );
+    }
+
+    #[test]
+    fn mkdir_precedes_children_and_siblings_are_independent() {
+        let deps = op_dependencies(&[
+            mkdir("/srv/a"),
+            upload("/srv/a/1"),
+            upload("/srv/a/2"),
+            upload("/srv/b/3"),
+        ]);
+        assert_eq!(deps, vec![vec![], vec![0], vec![0], vec![]]);
+    }
+
+    #[test]
+    fn rename_precedes_writes_under_new_path() {
+        let deps = op_dependencies(&[
+            upload("/srv/other"),
+            rename("/srv/old", "/srv/new"),
+            upload("/srv/new/file"),
+        ]);
+        assert_eq!(deps, vec![vec![], vec![], vec![1]]);
+    }
+
+    #[test]
+    fn parent_remove_waits_for_child_moves() {
+        let deps = op_dependencies(&[
+            rename("/srv/dir/keep", "/srv/kept"),
+            upload("/srv/unrelated"),
+            remove("/srv/dir"),
+        ]);
+        assert_eq!(deps, vec![vec![], vec![], vec![0]]);
+    }
+
+    #[test]
+    fn same_path_ops_stay_ordered() {
+        let deps = op_dependencies(&[upload("/srv/f"), remove("/srv/f"), upload("/srv/f")]);
+        assert_eq!(deps, vec![vec![], vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn failure_skips_dependents_only() {
+        let ops = [
+            mkdir("/srv/a"),
+            upload("/srv/a/1"),
+            upload("/srv/b"),
+            upload("/srv/a/1/x"),
+        ];
+        let mut scheduler = OpScheduler::new(&ops);
+        assert_eq!(scheduler.next_ready(), Some(0));
+        assert_eq!(scheduler.next_ready(), Some(2));
+        assert_eq!(scheduler.next_ready(), None);
+        assert_eq!(scheduler.fail(0), vec![1, 3]);
+        scheduler.complete(2);
+        assert_eq!(scheduler.next_ready(), None);
+    }
+
+    #[test]
+    fn ready_ops_follow_planner_order() {
+        let ops = [mkdir("/srv/a"), upload("/srv/z"), upload("/srv/a/1")];
+        let mut scheduler = OpScheduler::new(&ops);
+        assert_eq!(scheduler.next_ready(), Some(0));
+        scheduler.complete(0);
+        assert_eq!(scheduler.next_ready(), Some(1));
+        assert_eq!(scheduler.next_ready(), Some(2));
+    }
+}
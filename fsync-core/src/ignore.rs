@@ -0,0 +1,347 @@
+//! Ignore files read from the synced tree.
+//!
+//! `.fsyncignore` files apply in every task; `.gitignore` files and
+//! `.git/info/exclude` only when the task opts in. All of them follow git's
+//! rules: a pattern without a slash matches at any depth below its file, a
+//! leading or inner slash anchors it to the file's directory, a trailing
+//! slash matches directories only and `!` re-includes. Deeper files override
+//! shallower ones, `.fsyncignore` overrides `.gitignore` in the same
+//! directory, and nothing below an ignored directory can be re-included.
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+const FSYNC_IGNORE: &str = ".fsyncignore";
+const GIT_IGNORE: &str = ".gitignore";
+/// Relative to the task root
+const GIT_EXCLUDE: [&str; 3] = [".git", "info", "exclude"];
+
+/// Rules of the ignore files below a task root, read per directory on first
+/// use and kept until the files change.
+#[derive(Debug)]
+pub(crate) struct IgnoreTree {
+    root: PathBuf,
+    gitignore: bool,
+    /// Keyed by directory relative to the root, `""` for the root itself
+    dirs: RwLock<HashMap<String, Arc<Vec<IgnoreFile>>>>,
+    /// Whether each directory looked at so far is ignored, keyed like `dirs`
+    verdicts: RwLock<HashMap<String, bool>>,
+}
+
+/// One ignore file; missing files are kept too so their creation is seen.
+#[derive(Debug)]
+struct IgnoreFile {
+    path: PathBuf,
+    mtime: Option<SystemTime>,
+    rules: Vec<IgnoreRule>,
+    globs: GlobSet,
+}
+
+#[derive(Debug)]
+struct IgnoreRule {
+    negated: bool,
+    dir_only: bool,
+    /// 1-based line in the file
+    line: usize,
+    text: String,
+}
+
+/// The ignore file rule that decided a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IgnoreMatch {
+    pub file: PathBuf,
+    pub line: usize,
+    pub rule: String,
+    /// The rule re-includes the path
+    pub negated: bool,
+}
+
+impl IgnoreTree {
+    pub fn new(root: PathBuf, gitignore: bool) -> Self {
+        Self {
+            root,
+            gitignore,
+            dirs: RwLock::new(HashMap::new()),
+            verdicts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `rel`, a `/`-separated path below the root, is ignored by its
+    /// own rules or because a directory above it is.
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        if rel.is_empty() {
+            return false;
+        }
+        if is_dir {
+            return self.dir_ignored(rel);
+        }
+        let parent_ignored = rel
+            .rsplit_once('/')
+            .is_some_and(|(parent, _)| self.dir_ignored(parent));
+        parent_ignored || self.decide(rel, false) == Some(true)
+    }
+
+    /// Verdict of the last rule matching `rel` itself, ignoring the
+    /// directories above it; `None` when no rule matches.
+    fn decide(&self, rel: &str, is_dir: bool) -> Option<bool> {
+        self.source(rel, is_dir).map(|found| !found.negated)
+    }
+
+    /// The last rule matching `rel` itself, ignoring the directories above
+    /// it.
+    pub fn source(&self, rel: &str, is_dir: bool) -> Option<IgnoreMatch> {
+        let mut dir = rel.rsplit_once('/').map_or("", |(parent, _)| parent);
+        loop {
+            let sub = if dir.is_empty() {
+                rel
+            } else {
+                &rel[dir.len() + 1..]
+            };
+            let files = self.rules_in(dir);
+            for file in files.iter().rev() {
+                let found = file
+                    .globs
+                    .matches(sub)
+                    .into_iter()
+                    .rev()
+                    .map(|index| &file.rules[index])
+                    .find(|rule| is_dir || !rule.dir_only);
+                if let Some(rule) = found {
+                    return Some(IgnoreMatch {
+                        file: file.path.clone(),
+                        line: rule.line,
+                        rule: rule.text.clone(),
+                        negated: rule.negated,
+                    });
+                }
+            }
+            if dir.is_empty() {
+                return None;
+            }
+            dir = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+        }
+    }
+
+    fn dir_ignored(&self, rel: &str) -> bool {
+        if let Some(verdict) = self.verdicts.read().unwrap().get(rel) {
+            return *verdict;
+        }
+        let verdict = self.is_ignored(rel.rsplit_once('/').map_or("", |(parent, _)| parent), true)
+            || self.decide(rel, true) == Some(true);
+        self.verdicts
+            .write()
+            .unwrap()
+            .insert(rel.to_string(), verdict);
+        verdict
+    }
+
+    /// If `path` is an ignore file, forget the rules of its directory and
+    /// return that directory; it is read again on next use.
+    pub fn reload(&self, path: &Path, rel: &str) -> Option<PathBuf> {
+        let (dir, name) = rel.rsplit_once('/').unwrap_or(("", rel));
+        let (dir, applies_to) = if name == FSYNC_IGNORE || (self.gitignore && name == GIT_IGNORE) {
+            (dir, path.parent()?.to_path_buf())
+        } else if self.gitignore && rel == GIT_EXCLUDE.join("/") {
+            ("", self.root.clone())
+        } else {
+            return None;
+        };
+        self.dirs.write().unwrap().remove(dir);
+        self.verdicts.write().unwrap().clear();
+        Some(applies_to)
+    }
+
+    /// Forget the rules of every ignore file created, changed or removed
+    /// since it was read. Returns whether there was any.
+    pub fn reload_changed(&self) -> bool {
+        let mut dirs = self.dirs.write().unwrap();
+        let before = dirs.len();
+        dirs.retain(|_, files| files.iter().all(|file| file.mtime == modified(&file.path)));
+        let changed = dirs.len() != before;
+        drop(dirs);
+        if changed {
+            self.verdicts.write().unwrap().clear();
+        }
+        changed
+    }
+
+    fn rules_in(&self, dir: &str) -> Arc<Vec<IgnoreFile>> {
+        if let Some(files) = self.dirs.read().unwrap().get(dir) {
+            return files.clone();
+        }
+        let base = dir
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.root.clone(), |path, part| path.join(part));
+        let mut files = Vec::new();
+        if self.gitignore && dir.is_empty() {
+            let exclude = GIT_EXCLUDE
+                .iter()
+                .fold(base.clone(), |path, part| path.join(part));
+            files.push(IgnoreFile::read(exclude));
+        }
+        if self.gitignore {
+            files.push(IgnoreFile::read(base.join(GIT_IGNORE)));
+        }
+        files.push(IgnoreFile::read(base.join(FSYNC_IGNORE)));
+        let files = Arc::new(files);
+        self.dirs
+            .write()
+            .unwrap()
+            .insert(dir.to_string(), files.clone());
+        files
+    }
+}
+
+impl IgnoreFile {
+    fn read(path: PathBuf) -> Self {
+        let mtime = modified(&path);
+        let text = mtime
+            .and_then(|_| std::fs::read_to_string(&path).ok())
+            .unwrap_or_default();
+        let mut rules = Vec::new();
+        let mut builder = GlobSetBuilder::new();
+        for (index, line) in text.lines().enumerate() {
+            let Some((glob, rule)) = parse_rule(line, index + 1) else {
+                continue;
+            };
+            match GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .case_insensitive(cfg!(windows))
+                .build()
+            {
+                Ok(glob) => {
+                    builder.add(glob);
+                    rules.push(rule);
+                }
+                Err(e) => {
+                    tracing::debug!(path = %path.display(), line = index + 1, "bad ignore rule: {e}");
+                }
+            }
+        }
+        let globs = builder
+            .build()
+            .unwrap_or_else(|_| GlobSetBuilder::new().build().unwrap());
+        if !rules.is_empty() {
+            tracing::debug!(path = %path.display(), rules = rules.len(), "read ignore file");
+        }
+        Self {
+            path,
+            mtime,
+            rules,
+            globs,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Glob for one line of an ignore file, matched against paths relative to
+/// the file's directory.
+fn parse_rule(line: &str, number: usize) -> Option<(String, IgnoreRule)> {
+    let line = line.trim_end_matches('\r');
+    // Trailing spaces are dropped unless escaped with a backslash.
+    let mut end = line.len();
+    while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+        end -= 1;
+    }
+    let line = &line[..end];
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, body) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let dir_only = body.ends_with('/');
+    let body = body.trim_end_matches('/');
+    let anchored = body.contains('/');
+    let body = body.trim_start_matches('/');
+    if body.is_empty() {
+        return None;
+    }
+    let glob = if anchored {
+        body.to_string()
+    } else {
+        format!("**/{body}")
+    };
+    let rule = IgnoreRule {
+        negated,
+        dir_only,
+        line: number,
+        text: line.to_string(),
+    };
+    Some((glob, rule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn nested_ignore_files_follow_git_rules() {
+        let root = std::env::temp_dir().join(format!(
+            "fsync-ignore-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("app/build")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.local\n").unwrap();
+        fs::write(
+            root.join(".gitignore"),
+            "# comment\n*.log\n!keep.log\n/target/\nbuild/\ndocs/*.pdf\n\\#hash\n",
+        )
+        .unwrap();
+        fs::write(root.join("app/.gitignore"), "!*.log\nsecret.txt\n").unwrap();
+        fs::write(root.join("app/.fsyncignore"), "*.tmp\n!secret.txt\n").unwrap();
+
+        let tree = IgnoreTree::new(root.clone(), true);
+        assert!(tree.is_ignored("debug.log", false));
+        assert!(!tree.is_ignored("keep.log", false));
+        assert!(!tree.is_ignored("app/debug.log", false));
+        assert!(tree.is_ignored("settings.local", false));
+        assert!(tree.is_ignored("target", true));
+        assert!(!tree.is_ignored("src/target", true));
+        assert!(!tree.is_ignored("target", false));
+        assert!(tree.is_ignored("app/build/out.bin", false));
+        assert!(tree.is_ignored("docs/manual.pdf", false));
+        assert!(!tree.is_ignored("docs/old/manual.pdf", false));
+        assert!(tree.is_ignored("#hash", false));
+        assert!(tree.is_ignored("app/scratch.tmp", false));
+        assert!(!tree.is_ignored("scratch.tmp", false));
+        assert!(!tree.is_ignored("app/secret.txt", false));
+
+        assert_eq!(tree.decide("app/secret.txt", false), Some(false));
+        assert_eq!(tree.decide("app/main.rs", false), None);
+        let found = tree.source("docs/manual.pdf", false).unwrap();
+        assert_eq!(found.file, root.join(".gitignore"));
+        assert_eq!((found.line, found.rule.as_str()), (6, "docs/*.pdf"));
+
+        let without_git = IgnoreTree::new(root.clone(), false);
+        assert!(!without_git.is_ignored("debug.log", false));
+        assert!(without_git.is_ignored("app/scratch.tmp", false));
+
+        fs::write(root.join("app/.fsyncignore"), "*.tmp\n").unwrap();
+        assert!(tree
+            .reload(&root.join("app/.fsyncignore"), "app/.fsyncignore")
+            .is_some());
+        assert!(tree.is_ignored("app/secret.txt", false));
+        assert!(tree
+            .reload(&root.join("app/main.rs"), "app/main.rs")
+            .is_none());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
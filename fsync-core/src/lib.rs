@@ -1,24 +1,44 @@
 //! Core library for FSync – file/directory synchronisation engine.
 
+mod attrs;
+mod audit;
 mod config;
 mod convert;
+mod detect;
 mod file_op;
 mod filter;
+mod graph;
+mod ignore;
 mod manager;
+mod priority;
+mod progress;
 mod remote;
+mod scan;
+mod stats;
 mod storage;
 mod task;
+mod template;
 mod utils;
+mod watch;
 
-pub use config::{Pattern, RemoteCfg, TaskConfig};
+pub use attrs::{RemoteAttrRules, RemoteAttrs};
+pub use audit::{audit_task, repair_task, AuditOptions, AuditReport, DriftEntry, DriftKind};
+pub use config::{
+    AttrRule, AttrTarget, ChangeDetection, DeletionPolicy, InitialSync, Pattern, PriorityRule,
+    RemoteCfg, TaskConfig, WatcherMode,
+};
 pub use file_op::{event_to_ops, FsEvent};
-pub use filter::PathFilter;
+pub use filter::{legacy_filter_rules, FilterExplanation, FilterPreview, FilterReason, PathFilter};
 pub use manager::SyncManager;
-pub use remote::{RemoteFs, RemoteOp};
-pub use storage::StateStore;
+pub use progress::{OpProgress, SyncProgress, TransferProgress};
+pub use remote::{RemoteChecksum, RemoteEntry, RemoteFs, RemoteOp, UPLOAD_TEMP_SUFFIX};
+pub use scan::ScanReport;
+pub use stats::TaskStats;
+pub use storage::{FileState, StateStore};
 pub use task::{
-    spawn_task, RemoteOpLog, RemoteOpStatus, SyncTaskHandle, TaskCommand, TaskEvent,
-    TaskEventHandler, TaskLog, TaskState,
+    spawn_task, BatchReport, RemoteOpLog, RemoteOpStatus, SyncTaskHandle, TaskCommand, TaskEvent,
+    TaskEventHandler, TaskLog, TaskState, TaskWarning,
 };
+pub use template::{expand_template, TemplateContext};
 
 pub use tracing::{debug, error, info, warn};
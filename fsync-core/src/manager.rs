@@ -1,9 +1,12 @@
 use crate::{
     config::TaskConfig,
     remote::RemoteFs,
-    task::{spawn_task, SyncTaskHandle},
+    stats::TaskStats,
+    task::{spawn_task, SyncTaskHandle, TaskEvent, TaskState},
 };
 use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
 
 pub struct SyncManager {
     tasks: HashMap<String, SyncTaskHandle>, // key by id string
@@ -31,6 +34,49 @@ impl SyncManager {
         }
     }
 
+    pub fn pause(&mut self, id: &str) {
+        if let Some(h) = self.tasks.get(id) {
+            h.pause();
+        }
+    }
+
+    pub fn resume(&mut self, id: &str) {
+        if let Some(h) = self.tasks.get(id) {
+            h.resume();
+        }
+    }
+
+    pub fn rescan(&mut self, id: &str) {
+        if let Some(h) = self.tasks.get(id) {
+            h.rescan();
+        }
+    }
+
+    pub fn resync(&mut self, id: &str, paths: Vec<PathBuf>) {
+        if let Some(h) = self.tasks.get(id) {
+            h.resync(paths);
+        }
+    }
+
+    pub fn state(&self, id: &str) -> Option<TaskState> {
+        self.tasks.get(id).map(|h| h.state().clone())
+    }
+
+    pub fn subscribe_events(&self, id: &str) -> Option<broadcast::Receiver<TaskEvent>> {
+        self.tasks.get(id).map(|h| h.subscribe_events())
+    }
+
+    pub fn stats(&self, id: &str) -> Option<TaskStats> {
+        self.tasks.get(id).map(|h| h.stats())
+    }
+
+    /// Counters summed over all tasks.
+    pub fn total_stats(&self) -> TaskStats {
+        self.tasks
+            .values()
+            .fold(TaskStats::default(), |total, h| total.merged(&h.stats()))
+    }
+
     pub fn stop_all(&mut self) {
         for (_, h) in &self.tasks {
             let _ = h.stop();
@@ -0,0 +1,175 @@
+//! Upload ordering: configured glob priorities first, then smaller files, then
+//! the most recently modified ones.
+
+use crate::config::PriorityRule;
+use crate::filter::compile_pattern;
+use crate::utils::{normalize_key_path, relative_posix_path_str};
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// Sort key of a queued upload. `Ord` puts the upload that should run first
+/// at the front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct UploadRank {
+    pub priority: i32,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl Ord for UploadRank {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then(self.size.cmp(&other.size))
+            .then(other.mtime.cmp(&self.mtime))
+    }
+}
+
+impl PartialOrd for UploadRank {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Priority rules compiled against a task's local root.
+#[derive(Debug, Clone)]
+pub(crate) struct UploadPriorities {
+    root: String,
+    rules: Vec<(GlobSet, i32)>,
+}
+
+impl UploadPriorities {
+    /// Invalid patterns are skipped with a warning, like include / exclude
+    /// patterns in [`crate::PathFilter`].
+    pub fn new<P: AsRef<Path>>(root: P, rules: &[PriorityRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match compile_pattern(&rule.pattern.0) {
+                Ok(matcher) => Some((matcher, rule.priority)),
+                Err(e) => {
+                    crate::warn!("invalid priority pattern `{}`: {e}", rule.pattern.0);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            root: normalize_key_path(root.as_ref()),
+            rules,
+        }
+    }
+
+    pub fn priority(&self, path: &Path) -> i32 {
+        if self.rules.is_empty() {
+            return 0;
+        }
+        let path = normalize_key_path(path);
+        let rel = relative_posix_path_str(&path, &self.root).unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(rel.as_str()))
+            .map_or(0, |(_, priority)| *priority)
+    }
+
+    pub fn rank(&self, path: &Path, size: u64, mtime: u64) -> UploadRank {
+        UploadRank {
+            priority: self.priority(path),
+            size,
+            mtime,
+        }
+    }
+}
+
+/// Stable-sort every contiguous run of ranked items, leaving unranked items
+/// (removes, renames, mkdirs) where they are so structural ordering holds.
+pub(crate) fn sort_ranked_runs<T>(items: &mut [T], rank: impl Fn(&T) -> Option<UploadRank>) {
+    let mut start = 0;
+    while start < items.len() {
+        if rank(&items[start]).is_none() {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < items.len() && rank(&items[end]).is_some() {
+            end += 1;
+        }
+        items[start..end].sort_by_key(|item| rank(item));
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Pattern;
+
+    fn rule(pattern: &str, priority: i32) -> PriorityRule {
+        PriorityRule {
+            pattern: Pattern(pattern.into()),
+            priority,
+        }
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let priorities = UploadPriorities::new(
+            "/work",
+            &[rule("src/", 10), rule("**/*.lock", -5), rule("data/", -10)],
+        );
+        assert_eq!(priorities.priority(Path::new("/work/src/main.rs")), 10);
+        assert_eq!(priorities.priority(Path::new("/work/src/Cargo.lock")), -5);
+        assert_eq!(priorities.priority(Path::new("/work/data/big.bin")), -10);
+        assert_eq!(priorities.priority(Path::new("/work/README.md")), 0);
+    }
+
+    #[test]
+    fn ranks_by_priority_then_size_then_recency() {
+        let mut ranks = [
+            UploadRank {
+                priority: 0,
+                size: 10,
+                mtime: 1,
+            },
+            UploadRank {
+                priority: 0,
+                size: 10,
+                mtime: 5,
+            },
+            UploadRank {
+                priority: 0,
+                size: 1,
+                mtime: 1,
+            },
+            UploadRank {
+                priority: 3,
+                size: 900,
+                mtime: 1,
+            },
+        ];
+        ranks.sort();
+        assert_eq!(
+            ranks
+                .iter()
+                .map(|r| (r.priority, r.size, r.mtime))
+                .collect::<Vec<_>>(),
+            vec![(3, 900, 1), (0, 1, 1), (0, 10, 5), (0, 10, 1)]
+        );
+    }
+
+    #[test]
+    fn unranked_items_keep_their_position() {
+        let rank = |size| {
+            Some(UploadRank {
+                priority: 0,
+                size,
+                mtime: 0,
+            })
+        };
+        let mut items = vec![rank(5), rank(1), None, rank(9), rank(2)];
+        sort_ranked_runs(&mut items, |item| *item);
+        assert_eq!(items, vec![rank(1), rank(5), None, rank(2), rank(9)]);
+    }
+}
@@ -0,0 +1,236 @@
+//! Byte-level transfer progress of remote op batches.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two progress events of the same batch.
+pub(crate) const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Byte counter a backend advances while it transfers one op.
+#[derive(Debug, Clone, Default)]
+pub struct TransferProgress(Arc<AtomicU64>);
+
+impl TransferProgress {
+    pub fn add(&self, bytes: u64) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Start over, e.g. when an upload is retried from the beginning.
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Progress of one op in flight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpProgress {
+    pub remote: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Snapshot of the batch currently being applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProgress {
+    pub ops_done: usize,
+    pub ops_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Time since the batch started
+    pub elapsed: Duration,
+    pub in_flight: Vec<OpProgress>,
+}
+
+impl SyncProgress {
+    pub fn is_finished(&self) -> bool {
+        self.ops_done >= self.ops_total
+    }
+
+    pub fn bytes_remaining(&self) -> u64 {
+        self.bytes_total.saturating_sub(self.bytes_done)
+    }
+
+    /// Fraction of bytes transferred, or of ops done for batches without
+    /// uploads.
+    pub fn fraction(&self) -> f32 {
+        if self.bytes_total > 0 {
+            self.bytes_done as f32 / self.bytes_total as f32
+        } else if self.ops_total > 0 {
+            self.ops_done as f32 / self.ops_total as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// Remaining time at the average rate so far; `None` until some bytes
+    /// have been transferred.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.bytes_done == 0 || self.elapsed.is_zero() {
+            return None;
+        }
+        let rate = self.bytes_done as f64 / self.elapsed.as_secs_f64();
+        Some(Duration::from_secs_f64(
+            self.bytes_remaining() as f64 / rate,
+        ))
+    }
+}
+
+/// Book-keeping for one batch, keyed by op index.
+pub(crate) struct BatchProgress {
+    started: Instant,
+    last_emit: Option<Instant>,
+    ops_total: usize,
+    ops_done: usize,
+    bytes_total: u64,
+    bytes_done: u64,
+    /// Upload size of every op, 0 for ops that move no data
+    sizes: Vec<u64>,
+    in_flight: HashMap<usize, (String, TransferProgress)>,
+}
+
+impl BatchProgress {
+    pub fn new(sizes: Vec<u64>) -> Self {
+        Self {
+            started: Instant::now(),
+            last_emit: None,
+            ops_total: sizes.len(),
+            ops_done: 0,
+            bytes_total: sizes.iter().sum(),
+            bytes_done: 0,
+            sizes,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, node: usize, remote: String) -> TransferProgress {
+        let progress = TransferProgress::default();
+        self.in_flight.insert(node, (remote, progress.clone()));
+        progress
+    }
+
+    /// Count `node` as done and return the bytes it sent, as reported by the
+    /// backend; an op that completed without reporting any counts its planned
+    /// size. Bytes of ops that did not transfer (failed or skipped) are
+    /// dropped from the total so the remaining bytes stay meaningful.
+    pub fn finish(&mut self, node: usize, transferred: bool) -> u64 {
+        let reported = self
+            .in_flight
+            .remove(&node)
+            .map(|(_, progress)| progress.bytes())
+            .unwrap_or_default();
+        self.ops_done += 1;
+        if transferred {
+            self.bytes_done += self.sizes[node];
+            if reported > 0 {
+                reported
+            } else {
+                self.sizes[node]
+            }
+        } else {
+            self.bytes_total -= self.sizes[node];
+            reported
+        }
+    }
+
+    /// Whether enough time passed since the last event to send another.
+    pub fn should_emit(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_emit
+            .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+        {
+            return false;
+        }
+        self.last_emit = Some(now);
+        true
+    }
+
+    pub fn snapshot(&self) -> SyncProgress {
+        let mut in_flight = self
+            .in_flight
+            .iter()
+            .map(|(&node, (remote, progress))| {
+                let bytes_total = self.sizes[node];
+                (
+                    node,
+                    OpProgress {
+                        remote: remote.clone(),
+                        bytes_done: progress.bytes().min(bytes_total),
+                        bytes_total,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        in_flight.sort_by_key(|(node, _)| *node);
+        let in_flight_bytes = in_flight.iter().map(|(_, op)| op.bytes_done).sum::<u64>();
+        SyncProgress {
+            ops_done: self.ops_done,
+            ops_total: self.ops_total,
+            bytes_done: self.bytes_done + in_flight_bytes,
+            bytes_total: self.bytes_total,
+            elapsed: self.started.elapsed(),
+            in_flight: in_flight.into_iter().map(|(_, op)| op).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_in_flight_and_dropped_bytes() {
+        let mut batch = BatchProgress::new(vec![100, 0, 50]);
+        let upload = batch.start(0, "/srv/a".into());
+        upload.add(40);
+        let snapshot = batch.snapshot();
+        assert_eq!(snapshot.bytes_done, 40);
+        assert_eq!(snapshot.bytes_total, 150);
+        assert_eq!(snapshot.in_flight[0].bytes_done, 40);
+
+        upload.add(70);
+        assert_eq!(batch.finish(0, true), 110);
+        assert_eq!(batch.finish(1, true), 0);
+        assert_eq!(batch.finish(2, false), 0);
+        let snapshot = batch.snapshot();
+        assert!(snapshot.is_finished());
+        assert_eq!((snapshot.bytes_done, snapshot.bytes_total), (100, 100));
+        assert!(snapshot.in_flight.is_empty());
+    }
+
+    #[test]
+    fn eta_follows_average_rate() {
+        let progress = SyncProgress {
+            ops_done: 0,
+            ops_total: 1,
+            bytes_done: 25,
+            bytes_total: 100,
+            elapsed: Duration::from_secs(5),
+            in_flight: Vec::new(),
+        };
+        assert_eq!(progress.eta(), Some(Duration::from_secs(15)));
+        assert_eq!(progress.fraction(), 0.25);
+    }
+
+    #[test]
+    fn throttles_events() {
+        let mut batch = BatchProgress::new(vec![1]);
+        assert!(batch.should_emit());
+        assert!(!batch.should_emit());
+    }
+
+    #[test]
+    fn unreported_transfers_count_their_planned_size() {
+        let mut batch = BatchProgress::new(vec![100, 50]);
+        batch.start(0, "/srv/a".into());
+        batch.start(1, "/srv/b".into()).add(20);
+        assert_eq!(batch.finish(0, true), 100);
+        assert_eq!(batch.finish(1, false), 20);
+    }
+}
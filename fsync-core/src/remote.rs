@@ -1,11 +1,14 @@
+use crate::detect::hash_file_sha256;
+use crate::progress::TransferProgress;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio_util::sync::CancellationToken;
 
 /// Single remote operation derived from local FS event.
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RemoteOp {
     Upload { local: PathBuf, remote: String },
     Remove { remote: String },
@@ -13,6 +16,42 @@ pub enum RemoteOp {
     Rename { from: String, to: String },
 }
 
+/// Suffix of the temporary files backends upload into before renaming them
+/// into place. Leftovers are swept at task start.
+pub const UPLOAD_TEMP_SUFFIX: &str = ".fsync.tmp";
+
+/// Content digest of a remote file, in whichever algorithm the backend could
+/// compute it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteChecksum {
+    /// Hex BLAKE3 digest
+    Blake3(String),
+    /// Hex SHA-256 digest
+    Sha256(String),
+}
+
+impl RemoteChecksum {
+    /// Whether the local file at `path`, whose BLAKE3 digest is `blake3`, has
+    /// the same content. Reads the file again for SHA-256 digests.
+    pub(crate) fn matches_file(&self, path: &Path, blake3: &str) -> Result<bool> {
+        match self {
+            RemoteChecksum::Blake3(hex) => Ok(hex.eq_ignore_ascii_case(blake3)),
+            RemoteChecksum::Sha256(hex) => Ok(hex.eq_ignore_ascii_case(&hash_file_sha256(path)?)),
+        }
+    }
+}
+
+/// Entry returned by remote listings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// Full remote path
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, when known
+    pub mtime: Option<u64>,
+}
+
 #[async_trait]
 pub trait RemoteFs: Send + Sync + 'static {
     async fn apply_batch(&self, ops: Vec<RemoteOp>) -> Result<()>;
@@ -26,5 +65,147 @@ pub trait RemoteFs: Send + Sync + 'static {
         }
         self.apply_batch(ops).await
     }
+    /// Apply a single op, adding uploaded bytes to `progress` as they are
+    /// written. Backends without byte-level reporting just apply the op.
+    async fn apply_op(
+        &self,
+        op: RemoteOp,
+        cancel: CancellationToken,
+        progress: TransferProgress,
+    ) -> Result<()> {
+        let _ = progress;
+        self.apply_batch_cancelled(vec![op], cancel).await
+    }
     async fn ping(&self) -> Result<()>;
+    /// List the direct children of `remote`. A missing directory lists as empty.
+    async fn list_dir(&self, remote: &str) -> Result<Vec<RemoteEntry>> {
+        Err(anyhow!("remote listing is not supported ({remote})"))
+    }
+    /// Digest of the remote file's content.
+    async fn checksum(&self, remote: &str) -> Result<RemoteChecksum> {
+        Err(anyhow!("remote checksums are not supported ({remote})"))
+    }
+    /// Recursively list everything below `root` (excluding `root` itself).
+    async fn list_tree(&self, root: &str) -> Result<Vec<RemoteEntry>> {
+        let mut entries = Vec::new();
+        let mut pending = vec![root.to_string()];
+        while let Some(dir) = pending.pop() {
+            for entry in self.list_dir(&dir).await? {
+                if entry.is_dir {
+                    pending.push(entry.path.clone());
+                }
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// In-memory [`RemoteFs`] for tests.
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// In-memory remote; while offline every call fails. Clones share their
+    /// state, so a test can keep one while a task runs on another.
+    #[derive(Clone, Default)]
+    pub(crate) struct FakeRemote {
+        pub(crate) files: Arc<Mutex<BTreeMap<String, RemoteEntry>>>,
+        /// Digests served by `checksum`; other paths fail
+        pub(crate) checksums: Arc<Mutex<HashMap<String, RemoteChecksum>>>,
+        /// Remote path of every upload, in order
+        pub(crate) uploads: Arc<Mutex<Vec<String>>>,
+        offline: Arc<AtomicBool>,
+    }
+
+    impl FakeRemote {
+        pub(crate) fn with_files(entries: impl IntoIterator<Item = RemoteEntry>) -> Self {
+            let remote = Self::default();
+            remote
+                .files
+                .lock()
+                .unwrap()
+                .extend(entries.into_iter().map(|entry| (entry.path.clone(), entry)));
+            remote
+        }
+
+        pub(crate) fn set_online(&self, online: bool) {
+            self.offline.store(!online, Ordering::SeqCst);
+        }
+
+        fn check_online(&self) -> Result<()> {
+            if self.offline.load(Ordering::SeqCst) {
+                return Err(anyhow!("connection refused"));
+            }
+            Ok(())
+        }
+
+        pub(crate) fn paths(&self) -> Vec<String> {
+            self.files.lock().unwrap().keys().cloned().collect()
+        }
+    }
+
+    #[async_trait]
+    impl RemoteFs for FakeRemote {
+        async fn apply_batch(&self, ops: Vec<RemoteOp>) -> Result<()> {
+            self.check_online()?;
+            let mut files = self.files.lock().unwrap();
+            for op in &ops {
+                match op {
+                    RemoteOp::Upload { local, remote } => {
+                        let size = std::fs::metadata(local)?.len();
+                        let entry = RemoteEntry {
+                            path: remote.clone(),
+                            is_dir: false,
+                            size,
+                            mtime: Some(
+                                SystemTime::now()
+                                    .duration_since(UNIX_EPOCH)
+                                    .unwrap()
+                                    .as_secs(),
+                            ),
+                        };
+                        files.insert(remote.clone(), entry);
+                        self.uploads.lock().unwrap().push(remote.clone());
+                    }
+                    RemoteOp::Remove { remote } => {
+                        files.remove(remote);
+                    }
+                    RemoteOp::MkDir { .. } | RemoteOp::Rename { .. } => {}
+                }
+            }
+            Ok(())
+        }
+
+        async fn ping(&self) -> Result<()> {
+            self.check_online()
+        }
+
+        async fn checksum(&self, remote: &str) -> Result<RemoteChecksum> {
+            self.check_online()?;
+            self.checksums
+                .lock()
+                .unwrap()
+                .get(remote)
+                .cloned()
+                .ok_or_else(|| anyhow!("cannot hash {remote}"))
+        }
+
+        async fn list_tree(&self, root: &str) -> Result<Vec<RemoteEntry>> {
+            self.check_online()?;
+            let prefix = format!("{}/", root.trim_end_matches('/'));
+            Ok(self
+                .files
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|entry| entry.path.starts_with(&prefix))
+                .cloned()
+                .collect())
+        }
+    }
 }
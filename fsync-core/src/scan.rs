@@ -0,0 +1,407 @@
+//! Incremental tree scanner.
+//!
+//! A directory's mtime moves when an entry is added, removed or renamed in
+//! it, but not when a file inside is rewritten. Incremental passes therefore
+//! read only the directories whose mtime moved and descend into the others
+//! from their cached listing; full passes check every file again for the
+//! in-place edits the watcher missed.
+//!
+//! The startup scan uses [`spawn_parallel_walk`] instead, which reads the
+//! tree on several threads and streams what it finds.
+
+use crate::filter::PathFilter;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+
+/// Directories modified this recently are read again on the next pass: a
+/// change landing in the same mtime tick would otherwise go unnoticed.
+const RACY_MTIME_WINDOW: Duration = Duration::from_secs(2);
+
+/// Files sent per message by the parallel walk.
+const WALK_CHUNK: usize = 256;
+
+/// Messages buffered between the walk and its consumer; a slow consumer
+/// holds the walk back instead of piling up paths.
+const WALK_CHANNEL_CHUNKS: usize = 64;
+
+const WALK_MAX_THREADS: usize = 8;
+
+/// Outcome of one pass of the periodic scanner.
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    /// Every file was checked, not only those in changed directories
+    pub full: bool,
+    /// Directories visited
+    pub dirs: usize,
+    /// Directories whose entries were read
+    pub dirs_listed: usize,
+    /// Files compared with the cache
+    pub files_checked: usize,
+    /// Files queued for upload
+    pub queued: usize,
+    pub elapsed: Duration,
+}
+
+/// Passes requested from outside the periodic scanner. Requests made while
+/// the scanner is not running are kept until it starts.
+#[derive(Default)]
+pub(crate) struct ScanTrigger {
+    wake: Notify,
+    wanted: Mutex<ScanWanted>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ScanWanted {
+    pub full: bool,
+    /// Paths whose directories must be read again even if their mtime did
+    /// not move
+    pub dirty: Vec<PathBuf>,
+}
+
+impl ScanTrigger {
+    /// Request a full pass.
+    pub fn full(&self) {
+        self.wanted.lock().unwrap().full = true;
+        self.wake.notify_one();
+    }
+
+    /// Request a pass that reads the directories holding `paths` and
+    /// everything below them.
+    pub fn dirty(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.wanted.lock().unwrap().dirty.extend(paths);
+        self.wake.notify_one();
+    }
+
+    /// Wait for the next request.
+    pub async fn wait(&self) -> ScanWanted {
+        self.wake.notified().await;
+        std::mem::take(&mut *self.wanted.lock().unwrap())
+    }
+}
+
+/// Files to check after one pass over the directory tree.
+#[derive(Debug, Default)]
+pub(crate) struct DirScan {
+    pub files: Vec<PathBuf>,
+    pub dirs: usize,
+    pub dirs_listed: usize,
+}
+
+struct CachedDir {
+    /// `None` when the directory must be read on the next pass
+    mtime: Option<SystemTime>,
+    subdirs: Vec<PathBuf>,
+}
+
+/// Directory mtimes and sub-directory listings kept between passes.
+pub(crate) struct DirScanner {
+    root: PathBuf,
+    dirs: HashMap<PathBuf, CachedDir>,
+}
+
+impl DirScanner {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Read the directories holding `paths`, and every directory below
+    /// them, on the next pass.
+    pub fn invalidate(&mut self, paths: &[PathBuf]) {
+        for (dir, cached) in &mut self.dirs {
+            if paths
+                .iter()
+                .any(|path| dir.starts_with(path) || path.parent() == Some(dir.as_path()))
+            {
+                cached.mtime = None;
+            }
+        }
+    }
+
+    /// Walk the tree and return the files of every directory read. With
+    /// `full` every directory is read. Returns `None` when cancelled.
+    pub fn scan(
+        &mut self,
+        filter: &PathFilter,
+        full: bool,
+        cancel: &CancellationToken,
+    ) -> Option<DirScan> {
+        let started = SystemTime::now();
+        let mut visited = HashMap::with_capacity(self.dirs.len());
+        let mut scan = DirScan::default();
+        let mut stack = vec![self.root.clone()];
+        while let Some(dir) = stack.pop() {
+            if cancel.is_cancelled() {
+                // keep what is known; the next pass starts over
+                self.dirs.extend(visited);
+                return None;
+            }
+            scan.dirs += 1;
+            let mtime = std::fs::metadata(&dir)
+                .and_then(|meta| meta.modified())
+                .ok();
+            let cached = self.dirs.remove(&dir);
+            if let Some(cached) =
+                cached.filter(|cached| !full && cached.mtime.is_some() && cached.mtime == mtime)
+            {
+                stack.extend(cached.subdirs.iter().cloned());
+                visited.insert(dir, cached);
+                continue;
+            }
+
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            scan.dirs_listed += 1;
+            let mut subdirs = Vec::new();
+            for entry in entries.flatten() {
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if file_type.is_dir() {
+                    if filter.check_dir(&path) {
+                        subdirs.push(path);
+                    }
+                } else if file_type.is_file() && filter.check(&path) {
+                    scan.files.push(path);
+                }
+            }
+            stack.extend(subdirs.iter().cloned());
+            let settled = mtime.filter(|mtime| {
+                started
+                    .duration_since(*mtime)
+                    .is_ok_and(|age| age >= RACY_MTIME_WINDOW)
+            });
+            visited.insert(
+                dir,
+                CachedDir {
+                    mtime: settled,
+                    subdirs,
+                },
+            );
+        }
+        self.dirs = visited;
+        Some(scan)
+    }
+}
+
+/// File found by the parallel walk, with the metadata read while listing.
+pub(crate) struct WalkedFile {
+    pub path: PathBuf,
+    pub meta: std::fs::Metadata,
+}
+
+/// Walk `root` on a pool of threads and stream the files `filter` admits,
+/// in no particular order. The receiver closes once the walk is done or
+/// `cancel` fires.
+pub(crate) fn spawn_parallel_walk(
+    root: PathBuf,
+    filter: Arc<PathFilter>,
+    cancel: CancellationToken,
+) -> mpsc::Receiver<Vec<WalkedFile>> {
+    let (found_tx, found_rx) = mpsc::channel(WALK_CHANNEL_CHUNKS);
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(WALK_MAX_THREADS);
+    let queue = Arc::new(WalkQueue {
+        state: Mutex::new(WalkState {
+            dirs: vec![root],
+            busy: 0,
+            stopped: false,
+        }),
+        wake: Condvar::new(),
+    });
+    for _ in 0..threads {
+        let queue = queue.clone();
+        let filter = filter.clone();
+        let found_tx = found_tx.clone();
+        let cancel = cancel.clone();
+        std::thread::spawn(move || walk_worker(&queue, &filter, &found_tx, &cancel));
+    }
+    found_rx
+}
+
+/// Directories waiting to be read, shared by the walk threads.
+struct WalkQueue {
+    state: Mutex<WalkState>,
+    wake: Condvar,
+}
+
+struct WalkState {
+    dirs: Vec<PathBuf>,
+    /// Directories taken but not finished; their sub-directories may still
+    /// show up
+    busy: usize,
+    stopped: bool,
+}
+
+impl WalkQueue {
+    /// Next directory to read, or `None` once the walk is over.
+    fn take(&self) -> Option<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if let Some(dir) = state.dirs.pop() {
+                state.busy += 1;
+                return Some(dir);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self.wake.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self, subdirs: Vec<PathBuf>) {
+        let mut state = self.state.lock().unwrap();
+        state.busy -= 1;
+        state.dirs.extend(subdirs);
+        drop(state);
+        self.wake.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.wake.notify_all();
+    }
+}
+
+fn walk_worker(
+    queue: &WalkQueue,
+    filter: &PathFilter,
+    found_tx: &mpsc::Sender<Vec<WalkedFile>>,
+    cancel: &CancellationToken,
+) {
+    let mut found = Vec::with_capacity(WALK_CHUNK);
+    while let Some(dir) = queue.take() {
+        if cancel.is_cancelled() {
+            queue.stop();
+            return;
+        }
+        let mut subdirs = Vec::new();
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if filter.check_dir(&path) {
+                    subdirs.push(path);
+                }
+            } else if file_type.is_file() && filter.check(&path) {
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                found.push(WalkedFile { path, meta });
+                if found.len() >= WALK_CHUNK
+                    && found_tx.blocking_send(std::mem::take(&mut found)).is_err()
+                {
+                    // nobody listens anymore
+                    queue.stop();
+                    return;
+                }
+            }
+        }
+        queue.finish(subdirs);
+    }
+    if !found.is_empty() {
+        let _ = found_tx.blocking_send(found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::UNIX_EPOCH;
+
+    fn age_dir(path: &std::path::Path) {
+        let old = SystemTime::now() - Duration::from_secs(60);
+        fs::File::open(path).unwrap().set_modified(old).unwrap();
+    }
+
+    #[test]
+    fn incremental_pass_reads_only_changed_directories() {
+        let root = std::env::temp_dir().join(format!(
+            "fsync-scan-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(root.join("a/deep")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a/deep/one.txt"), b"1").unwrap();
+        fs::write(root.join("b/two.txt"), b"2").unwrap();
+        for dir in ["", "a", "a/deep", "b"] {
+            age_dir(&root.join(dir));
+        }
+        let filter = PathFilter::new(&root, &[], &[]);
+        let cancel = CancellationToken::new();
+        let mut scanner = DirScanner::new(root.clone());
+
+        let first = scanner.scan(&filter, false, &cancel).unwrap();
+        assert_eq!(
+            (first.dirs, first.dirs_listed, first.files.len()),
+            (4, 4, 2)
+        );
+
+        let idle = scanner.scan(&filter, false, &cancel).unwrap();
+        assert_eq!((idle.dirs, idle.dirs_listed), (4, 0));
+        assert!(idle.files.is_empty());
+
+        fs::write(root.join("a/deep/three.txt"), b"3").unwrap();
+        let changed = scanner.scan(&filter, false, &cancel).unwrap();
+        assert_eq!(changed.dirs_listed, 1);
+        assert_eq!(changed.files.len(), 2);
+
+        scanner.invalidate(&[root.join("a")]);
+        let dirty = scanner.scan(&filter, false, &cancel).unwrap();
+        assert_eq!((dirty.dirs_listed, dirty.files.len()), (3, 2));
+
+        let full = scanner.scan(&filter, true, &cancel).unwrap();
+        assert_eq!((full.dirs_listed, full.files.len()), (4, 3));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parallel_walk_streams_every_admitted_file() {
+        let root = std::env::temp_dir().join(format!(
+            "fsync-walk-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        for dir in 0..20 {
+            let dir = root.join(format!("d{dir}/nested"));
+            fs::create_dir_all(&dir).unwrap();
+            for file in 0..30 {
+                fs::write(dir.join(format!("f{file}.txt")), b"x").unwrap();
+            }
+            fs::write(dir.join("skip.log"), b"x").unwrap();
+        }
+        let exclude = [crate::config::Pattern("**/*.log".into())];
+        let filter = Arc::new(PathFilter::new(&root, &[], &exclude));
+        let mut found_rx = spawn_parallel_walk(root.clone(), filter, CancellationToken::new());
+
+        let mut found = Vec::new();
+        while let Some(chunk) = found_rx.blocking_recv() {
+            found.extend(chunk.into_iter().map(|file| file.path));
+        }
+        found.sort();
+        found.dedup();
+        assert_eq!(found.len(), 20 * 30);
+        assert!(found.iter().all(|path| path.extension().unwrap() == "txt"));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
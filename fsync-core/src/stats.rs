@@ -0,0 +1,189 @@
+//! Per-task transfer statistics.
+
+use crate::remote::RemoteOp;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Counters of one task, or totals over several tasks / runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskStats {
+    pub files_uploaded: u64,
+    pub files_removed: u64,
+    pub files_renamed: u64,
+    pub bytes_sent: u64,
+    /// Remote ops that failed after all retries
+    pub failures: u64,
+    pub retries: u64,
+    /// Time spent applying remote ops, in milliseconds
+    pub transfer_ms: u64,
+    /// Unix seconds of the last batch that completed without errors
+    pub last_success: Option<u64>,
+    /// Local changes waiting to be synced
+    pub queue_depth: u64,
+    /// Remote ops in the outbox, waiting for a retry
+    pub outstanding_ops: u64,
+}
+
+impl TaskStats {
+    /// Average upload rate in bytes per second while transferring.
+    pub fn throughput(&self) -> Option<f64> {
+        (self.transfer_ms > 0).then(|| self.bytes_sent as f64 * 1_000.0 / self.transfer_ms as f64)
+    }
+
+    /// Time since the last successful sync.
+    pub fn since_last_success(&self) -> Option<Duration> {
+        let last = self.last_success?;
+        Some(Duration::from_secs(unix_now().saturating_sub(last)))
+    }
+
+    /// Sum of two sets of counters; the later success time wins.
+    pub fn merged(&self, other: &TaskStats) -> TaskStats {
+        TaskStats {
+            files_uploaded: self.files_uploaded + other.files_uploaded,
+            files_removed: self.files_removed + other.files_removed,
+            files_renamed: self.files_renamed + other.files_renamed,
+            bytes_sent: self.bytes_sent + other.bytes_sent,
+            failures: self.failures + other.failures,
+            retries: self.retries + other.retries,
+            transfer_ms: self.transfer_ms + other.transfer_ms,
+            last_success: self.last_success.max(other.last_success),
+            queue_depth: self.queue_depth + other.queue_depth,
+            outstanding_ops: self.outstanding_ops + other.outstanding_ops,
+        }
+    }
+}
+
+/// Live counters shared between a running task and its handle.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    files_uploaded: AtomicU64,
+    files_removed: AtomicU64,
+    files_renamed: AtomicU64,
+    bytes_sent: AtomicU64,
+    failures: AtomicU64,
+    retries: AtomicU64,
+    transfer_ms: AtomicU64,
+    /// 0 until the first success
+    last_success: AtomicU64,
+    queue_depth: AtomicU64,
+    outstanding_ops: AtomicU64,
+}
+
+impl StatsCounters {
+    pub fn record_applied(&self, op: &RemoteOp, bytes: u64) {
+        let counter = match op {
+            RemoteOp::Upload { .. } => {
+                self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+                &self.files_uploaded
+            }
+            RemoteOp::Remove { .. } => &self.files_removed,
+            RemoteOp::Rename { .. } => &self.files_renamed,
+            RemoteOp::MkDir { .. } => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_transfer_time(&self, elapsed: Duration) {
+        self.transfer_ms
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn mark_success(&self) {
+        self.last_success.store(unix_now(), Ordering::Relaxed);
+    }
+
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_outstanding_ops(&self, count: usize) {
+        self.outstanding_ops.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TaskStats {
+        let last_success = self.last_success.load(Ordering::Relaxed);
+        TaskStats {
+            files_uploaded: self.files_uploaded.load(Ordering::Relaxed),
+            files_removed: self.files_removed.load(Ordering::Relaxed),
+            files_renamed: self.files_renamed.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            transfer_ms: self.transfer_ms.load(Ordering::Relaxed),
+            last_success: (last_success > 0).then_some(last_success),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            outstanding_ops: self.outstanding_ops.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn counts_ops_by_kind() {
+        let counters = StatsCounters::default();
+        counters.record_applied(
+            &RemoteOp::Upload {
+                local: PathBuf::from("/l/a"),
+                remote: "/r/a".into(),
+            },
+            10,
+        );
+        counters.record_applied(
+            &RemoteOp::MkDir {
+                remote: "/r".into(),
+            },
+            0,
+        );
+        counters.record_applied(
+            &RemoteOp::Remove {
+                remote: "/r/b".into(),
+            },
+            0,
+        );
+        counters.add_transfer_time(Duration::from_millis(500));
+        let stats = counters.snapshot();
+        assert_eq!((stats.files_uploaded, stats.files_removed), (1, 1));
+        assert_eq!(stats.bytes_sent, 10);
+        assert_eq!(stats.throughput(), Some(20.0));
+        assert_eq!(stats.last_success, None);
+    }
+
+    #[test]
+    fn merge_sums_counters_and_keeps_latest_success() {
+        let a = TaskStats {
+            files_uploaded: 2,
+            last_success: Some(10),
+            ..TaskStats::default()
+        };
+        let b = TaskStats {
+            files_uploaded: 3,
+            retries: 1,
+            last_success: Some(20),
+            ..TaskStats::default()
+        };
+        let total = a.merged(&b);
+        assert_eq!(total.files_uploaded, 5);
+        assert_eq!(total.retries, 1);
+        assert_eq!(total.last_success, Some(20));
+    }
+}
@@ -1,17 +1,67 @@
-//! SQLite-backed timestamp storage for per-task sync state.
+//! SQLite-backed storage for per-task sync state.
 
 use crate::utils::{display_posix_path, normalize_posix_path_str};
 use anyhow::Result;
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqlitePoolOptions, AssertSqlSafe, QueryBuilder, Sqlite, SqlitePool};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 const STATE_DB_FILE: &str = "state.db";
 
+/// Rows per multi-row statement; 5 bound values each stays well below
+/// SQLite's limit on statement variables.
+const ROWS_PER_STATEMENT: usize = 1_000;
+
+/// `local_path` comparisons match how keys are normalized.
+const KEY_COLLATION: &str = if cfg!(windows) { "NOCASE" } else { "BINARY" };
+
+/// Recorded state of a synced file. Rows written before sizes and hashes were
+/// tracked only carry `mtime`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    /// Modification time in whole seconds since the Unix epoch
+    pub mtime: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime_ns: Option<u64>,
+    pub size: Option<u64>,
+    /// Hex BLAKE3 digest of the uploaded content
+    pub hash: Option<String>,
+}
+
+type StateRow = (i64, Option<i64>, Option<i64>, Option<String>);
+
+/// Remote op waiting in the outbox. The payload is opaque to the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OutboxRow {
+    pub id: i64,
+    pub payload: String,
+    /// Failed retry rounds so far
+    pub attempts: u32,
+}
+
+fn file_state((mtime, mtime_ns, size, hash): StateRow) -> FileState {
+    FileState {
+        mtime: mtime as u64,
+        mtime_ns: mtime_ns.map(|ns| ns as u64),
+        size: size.map(|size| size as u64),
+        hash,
+    }
+}
+
+/// Each write stamps its rows with a new generation, so a reader holding a
+/// snapshot can fetch only the rows written since.
 #[derive(Clone)]
 pub struct StateStore {
     pool: SqlitePool,
+    /// Generation of the last write
+    generation: Arc<AtomicU64>,
+    /// Generation of the last write that removed rows
+    removed_at: Arc<AtomicU64>,
 }
 
 impl StateStore {
@@ -43,133 +93,389 @@ impl StateStore {
         )
         .execute(&pool)
         .await?;
+        migrate_file_states(&pool).await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS file_states_generation ON file_states (generation)",
+        )
+        .execute(&pool)
+        .await?;
+        let (generation,) =
+            sqlx::query_as::<_, (i64,)>("SELECT COALESCE(MAX(generation), 0) FROM file_states")
+                .fetch_one(&pool)
+                .await?;
+        // Remote ops that were planned but not confirmed, with the remote
+        // path they write to so newer ops on that path can replace them.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS outbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                target TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_ms INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
 
         tracing::info!(db_path = %display_posix_path(&db_path), "sqlite state store opened");
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            generation: Arc::new(AtomicU64::new(generation as u64)),
+            removed_at: Arc::new(AtomicU64::new(0)),
+        })
     }
 
-    pub async fn get_u64(&self, key: &String) -> Result<Option<u64>> {
-        let row =
-            sqlx::query_as::<_, (i64,)>("SELECT mtime FROM file_states WHERE local_path = ?1")
-                .bind(key)
-                .fetch_optional(&self.pool)
-                .await?;
-        Ok(row.map(|(mtime,)| mtime as u64))
+    /// Token to pass to [`StateStore::refresh`] for a snapshot taken now.
+    pub fn change_token(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
     }
 
-    pub async fn load_all_u64(&self) -> Result<HashMap<String, u64>> {
-        let rows = sqlx::query_as::<_, (String, i64)>("SELECT local_path, mtime FROM file_states")
-            .fetch_all(&self.pool)
-            .await?;
-        Ok(rows
-            .into_iter()
-            .map(|(key, mtime)| (key, mtime as u64))
-            .collect())
+    /// Bring `snapshot`, loaded at change token `since`, up to date and
+    /// return the new token. Only rows written after `since` are read, unless
+    /// rows were removed in between or `since` is 0; then the whole table is
+    /// reloaded.
+    pub async fn refresh(
+        &self,
+        snapshot: &mut HashMap<String, FileState>,
+        since: u64,
+    ) -> Result<u64> {
+        let token = self.change_token();
+        if token == since && since != 0 {
+            return Ok(token);
+        }
+        if since == 0 || self.removed_at.load(Ordering::SeqCst) > since {
+            *snapshot = self.load_all().await?;
+            return Ok(token);
+        }
+        let rows = sqlx::query_as::<_, (String, i64, Option<i64>, Option<i64>, Option<String>)>(
+            "SELECT local_path, mtime, mtime_ns, size, hash FROM file_states WHERE generation > ?1",
+        )
+        .bind(since as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        snapshot.extend(rows.into_iter().map(|(key, mtime, mtime_ns, size, hash)| {
+            (key, file_state((mtime, mtime_ns, size, hash)))
+        }));
+        Ok(token)
     }
 
-    pub async fn put_u64(&self, key: String, val: u64) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO file_states (local_path, mtime, updated_at)
-            VALUES (?1, ?2, CURRENT_TIMESTAMP)
-            ON CONFLICT(local_path) DO UPDATE SET
-                mtime = excluded.mtime,
-                updated_at = CURRENT_TIMESTAMP
-            "#,
+    fn next_generation(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn mark_removed(&self) {
+        let generation = self.next_generation();
+        self.removed_at.fetch_max(generation, Ordering::SeqCst);
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<FileState>> {
+        let row = sqlx::query_as::<_, StateRow>(
+            "SELECT mtime, mtime_ns, size, hash FROM file_states WHERE local_path = ?1",
         )
         .bind(key)
-        .bind(i64::try_from(val)?)
-        .execute(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
-        Ok(())
+        Ok(row.map(file_state))
+    }
+
+    pub async fn load_all(&self) -> Result<HashMap<String, FileState>> {
+        let rows = sqlx::query_as::<_, (String, i64, Option<i64>, Option<i64>, Option<String>)>(
+            "SELECT local_path, mtime, mtime_ns, size, hash FROM file_states",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(key, mtime, mtime_ns, size, hash)| {
+                (key, file_state((mtime, mtime_ns, size, hash)))
+            })
+            .collect())
+    }
+
+    pub async fn put(&self, key: String, state: FileState) -> Result<()> {
+        self.put_many(&[(key, state)]).await
     }
 
-    pub async fn put_many_u64(&self, values: &[(String, u64)]) -> Result<()> {
+    /// Upsert `values` with multi-row statements in one transaction.
+    pub async fn put_many(&self, values: &[(String, FileState)]) -> Result<()> {
         if values.is_empty() {
             return Ok(());
         }
+        let rows = values
+            .iter()
+            .map(|(key, state)| {
+                Ok((
+                    key.as_str(),
+                    i64::try_from(state.mtime)?,
+                    state.mtime_ns.map(i64::try_from).transpose()?,
+                    state.size.map(i64::try_from).transpose()?,
+                    state.hash.as_deref(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let mut tx = self.pool.begin().await?;
-        for (key, val) in values {
-            sqlx::query(
+        // Taken while holding the only connection, so generations commit in
+        // order and a reader never gets a token ahead of the rows it can see.
+        let generation = self.next_generation() as i64;
+        for chunk in rows.chunks(ROWS_PER_STATEMENT) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO file_states (local_path, mtime, mtime_ns, size, hash, generation, updated_at) ",
+            );
+            query.push_values(chunk, |mut row, (key, mtime, mtime_ns, size, hash)| {
+                row.push_bind(*key)
+                    .push_bind(*mtime)
+                    .push_bind(*mtime_ns)
+                    .push_bind(*size)
+                    .push_bind(*hash)
+                    .push_bind(generation)
+                    .push("CURRENT_TIMESTAMP");
+            });
+            query.push(
                 r#"
-                INSERT INTO file_states (local_path, mtime, updated_at)
-                VALUES (?1, ?2, CURRENT_TIMESTAMP)
                 ON CONFLICT(local_path) DO UPDATE SET
                     mtime = excluded.mtime,
+                    mtime_ns = excluded.mtime_ns,
+                    size = excluded.size,
+                    hash = excluded.hash,
+                    generation = excluded.generation,
                     updated_at = CURRENT_TIMESTAMP
                 "#,
-            )
-            .bind(key)
-            .bind(i64::try_from(*val)?)
-            .execute(&mut *tx)
-            .await?;
+            );
+            query.build().execute(&mut *tx).await?;
         }
         tx.commit().await?;
         Ok(())
     }
 
-    pub async fn remove_u64(&self, key: &String) -> Result<()> {
-        sqlx::query("DELETE FROM file_states WHERE local_path = ?1")
+    pub async fn remove(&self, key: &str) -> Result<()> {
+        let result = sqlx::query("DELETE FROM file_states WHERE local_path = ?1")
             .bind(key)
             .execute(&self.pool)
             .await?;
+        if result.rows_affected() > 0 {
+            self.mark_removed();
+        }
         Ok(())
     }
 
+    /// Remove `root` and every key below it with one range delete.
     pub async fn remove_tree(&self, root: &String) -> Result<usize> {
-        let rows = sqlx::query_as::<_, (String,)>("SELECT local_path FROM file_states")
-            .fetch_all(&self.pool)
+        let root = normalize_posix_path_str(root);
+        // '0' follows '/', so the range covers exactly the keys under `root/`.
+        let sql = format!(
+            "DELETE FROM file_states WHERE local_path = ?1 COLLATE {KEY_COLLATION} \
+             OR (local_path >= ?2 COLLATE {KEY_COLLATION} AND local_path < ?3 COLLATE {KEY_COLLATION})"
+        );
+        let result = sqlx::query(AssertSqlSafe(sql))
+            .bind(&root)
+            .bind(format!("{root}/"))
+            .bind(format!("{root}0"))
+            .execute(&self.pool)
             .await?;
-        let stale = rows
-            .into_iter()
-            .map(|(key,)| key)
-            .filter(|key| path_is_self_or_child(key, root))
-            .collect::<Vec<_>>();
+        let removed = result.rows_affected() as usize;
+        if removed > 0 {
+            self.mark_removed();
+        }
+        Ok(removed)
+    }
+
+    pub async fn clear(&self) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM file_states")
+            .execute(&self.pool)
+            .await?;
+        self.mark_removed();
+        Ok(result.rows_affected() as usize)
+    }
 
+    /// Remove every row whose key is not in `live_keys`. Keys are streamed
+    /// rather than loaded at once, and the stale ones deleted with multi-row
+    /// statements.
+    pub async fn cleanup_missing(&self, live_keys: &HashSet<String>) -> Result<usize> {
+        let mut stale = Vec::new();
+        let mut keys =
+            sqlx::query_as::<_, (String,)>("SELECT local_path FROM file_states").fetch(&self.pool);
+        while let Some((key,)) = keys.try_next().await? {
+            if !live_keys.contains(&key) {
+                stale.push(key);
+            }
+        }
+        drop(keys);
         if stale.is_empty() {
             return Ok(0);
         }
 
         let mut tx = self.pool.begin().await?;
-        for key in &stale {
-            sqlx::query("DELETE FROM file_states WHERE local_path = ?1")
-                .bind(key)
-                .execute(&mut *tx)
-                .await?;
+        for chunk in stale.chunks(ROWS_PER_STATEMENT) {
+            let mut query =
+                QueryBuilder::<Sqlite>::new("DELETE FROM file_states WHERE local_path IN (");
+            let mut keys = query.separated(", ");
+            for key in chunk {
+                keys.push_bind(key.as_str());
+            }
+            query.push(")");
+            query.build().execute(&mut *tx).await?;
         }
         tx.commit().await?;
+        self.mark_removed();
         Ok(stale.len())
     }
 
-    pub async fn cleanup_missing(&self, live_keys: &HashSet<String>) -> Result<usize> {
-        let rows = sqlx::query_as::<_, (String,)>("SELECT local_path FROM file_states")
-            .fetch_all(&self.pool)
-            .await?;
-        let stale = rows
-            .into_iter()
-            .map(|(key,)| key)
-            .filter(|key| !live_keys.contains(key))
-            .collect::<Vec<_>>();
+    pub async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
 
-        if stale.is_empty() {
-            return Ok(0);
+    /// Queue `(target, payload)` entries, due immediately. Returns their ids
+    /// in order.
+    pub(crate) async fn outbox_push(&self, entries: &[(String, String)]) -> Result<Vec<i64>> {
+        let mut ids = Vec::with_capacity(entries.len());
+        let mut tx = self.pool.begin().await?;
+        for chunk in entries.chunks(ROWS_PER_STATEMENT) {
+            let mut query = QueryBuilder::<Sqlite>::new("INSERT INTO outbox (target, payload) ");
+            query.push_values(chunk, |mut row, (target, payload)| {
+                row.push_bind(target.as_str()).push_bind(payload.as_str());
+            });
+            query.push(" RETURNING id");
+            let mut chunk_ids = query.build_query_as::<(i64,)>().fetch_all(&mut *tx).await?;
+            // AUTOINCREMENT ids grow in insertion order; RETURNING rows have none.
+            chunk_ids.sort_unstable();
+            ids.extend(chunk_ids.into_iter().map(|(id,)| id));
         }
+        tx.commit().await?;
+        Ok(ids)
+    }
 
+    /// Drop queued entries writing to any of `targets`.
+    pub(crate) async fn outbox_supersede(&self, targets: &[String]) -> Result<usize> {
+        let mut removed = 0;
         let mut tx = self.pool.begin().await?;
-        for key in &stale {
-            sqlx::query("DELETE FROM file_states WHERE local_path = ?1")
-                .bind(key)
-                .execute(&mut *tx)
-                .await?;
+        for chunk in targets.chunks(ROWS_PER_STATEMENT) {
+            let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM outbox WHERE target IN (");
+            let mut binds = query.separated(", ");
+            for target in chunk {
+                binds.push_bind(target.as_str());
+            }
+            query.push(")");
+            removed += query.build().execute(&mut *tx).await?.rows_affected() as usize;
         }
         tx.commit().await?;
-        Ok(stale.len())
+        Ok(removed)
     }
 
-    pub async fn flush(&self) -> Result<()> {
+    /// Entries whose next attempt is at or before `now_ms`, oldest first.
+    pub(crate) async fn outbox_due(&self, now_ms: u64) -> Result<Vec<OutboxRow>> {
+        let rows = sqlx::query_as::<_, (i64, String, i64)>(
+            "SELECT id, payload, attempts FROM outbox WHERE next_attempt_ms <= ?1 ORDER BY id",
+        )
+        .bind(i64::try_from(now_ms)?)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|(id, payload, attempts)| {
+                Ok(OutboxRow {
+                    id,
+                    payload,
+                    attempts: attempts.try_into()?,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) async fn outbox_remove(&self, ids: &[i64]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        for chunk in ids.chunks(ROWS_PER_STATEMENT) {
+            let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM outbox WHERE id IN (");
+            let mut binds = query.separated(", ");
+            for id in chunk {
+                binds.push_bind(*id);
+            }
+            query.push(")");
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
+
+    /// Push an entry back to `next_attempt_ms`. `error` is kept when `None`.
+    pub(crate) async fn outbox_defer(
+        &self,
+        id: i64,
+        attempts: u32,
+        next_attempt_ms: u64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE outbox
+            SET attempts = ?2, next_attempt_ms = ?3, last_error = COALESCE(?4, last_error)
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(i64::from(attempts))
+        .bind(i64::try_from(next_attempt_ms)?)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Make every entry due now, e.g. after a restart.
+    pub(crate) async fn outbox_retry_now(&self) -> Result<usize> {
+        let result = sqlx::query("UPDATE outbox SET next_attempt_ms = 0")
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() as usize)
+    }
+
+    /// Earliest next attempt, `None` when the outbox is empty.
+    pub(crate) async fn outbox_next_attempt(&self) -> Result<Option<u64>> {
+        let (next,) =
+            sqlx::query_as::<_, (Option<i64>,)>("SELECT MIN(next_attempt_ms) FROM outbox")
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(next.map(|next| next.max(0) as u64))
+    }
+
+    pub(crate) async fn outbox_len(&self) -> Result<usize> {
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM outbox")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as usize)
+    }
+}
+
+/// Add the columns introduced after the first release to existing databases.
+async fn migrate_file_states(pool: &SqlitePool) -> Result<()> {
+    let columns =
+        sqlx::query_as::<_, (String,)>("SELECT name FROM pragma_table_info('file_states')")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|(name,)| name)
+            .collect::<HashSet<_>>();
+    for (column, ty) in [
+        ("mtime_ns", "INTEGER"),
+        ("size", "INTEGER"),
+        ("hash", "TEXT"),
+        ("generation", "INTEGER NOT NULL DEFAULT 0"),
+    ] {
+        if !columns.contains(column) {
+            sqlx::query(AssertSqlSafe(format!(
+                "ALTER TABLE file_states ADD COLUMN {column} {ty}"
+            )))
+            .execute(pool)
+            .await?;
+        }
+    }
+    Ok(())
 }
 
 fn sqlite_url(path: &Path) -> String {
@@ -177,21 +483,69 @@ fn sqlite_url(path: &Path) -> String {
     format!("sqlite://{path}?mode=rwc")
 }
 
-fn path_is_self_or_child(path: &str, root: &str) -> bool {
-    let path = normalize_state_path(path);
-    let root = normalize_state_path(root);
-    path == root
-        || path
-            .strip_prefix(&root)
-            .map(|rest| rest.starts_with('/'))
-            .unwrap_or(false)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn outbox_defers_and_supersedes_entries() {
+        let dir = std::env::temp_dir().join(format!("fsync-outbox-{}", uuid::Uuid::new_v4()));
+        let store = StateStore::open(0, &dir).await.unwrap();
+        let ids = store
+            .outbox_push(&[
+                ("/r/a".into(), "a".into()),
+                ("/r/b".into(), "b".into()),
+                ("/r/c".into(), "c".into()),
+            ])
+            .await
+            .unwrap();
+        store
+            .outbox_defer(ids[0], 1, 5_000, Some("timeout"))
+            .await
+            .unwrap();
+        assert_eq!(store.outbox_supersede(&["/r/b".into()]).await.unwrap(), 1);
+
+        let due = store.outbox_due(1_000).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].payload, "c");
+        assert_eq!(store.outbox_next_attempt().await.unwrap(), Some(0));
+
+        store.outbox_remove(&[ids[2]]).await.unwrap();
+        assert_eq!(store.outbox_next_attempt().await.unwrap(), Some(5_000));
+        store.outbox_retry_now().await.unwrap();
+        let due = store.outbox_due(0).await.unwrap();
+        assert_eq!((due[0].id, due[0].attempts), (ids[0], 1));
+        assert_eq!(store.outbox_len().await.unwrap(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn prefix_removal_and_refresh() {
+        let dir = std::env::temp_dir().join(format!("fsync-store-{}", uuid::Uuid::new_v4()));
+        let store = StateStore::open(0, &dir).await.unwrap();
+        let state = |mtime| FileState {
+            mtime,
+            ..FileState::default()
+        };
+        let keys = ["a", "a/b", "a/b/c", "a.txt", "ab", "b"];
+        let rows = keys
+            .iter()
+            .map(|key| (key.to_string(), state(1)))
+            .collect::<Vec<_>>();
+        store.put_many(&rows).await.unwrap();
+        let mut snapshot = HashMap::new();
+        let token = store.refresh(&mut snapshot, 0).await.unwrap();
+        assert_eq!(snapshot.len(), keys.len());
+
+        store.put("b".into(), state(2)).await.unwrap();
+        let token = store.refresh(&mut snapshot, token).await.unwrap();
+        assert_eq!(snapshot["b"], state(2));
 
-fn normalize_state_path(path: &str) -> String {
-    let path = normalize_posix_path_str(path);
-    if cfg!(windows) {
-        path.to_ascii_lowercase()
-    } else {
-        path
+        assert_eq!(store.remove_tree(&"a".to_string()).await.unwrap(), 3);
+        let live = ["ab".to_string()].into_iter().collect();
+        assert_eq!(store.cleanup_missing(&live).await.unwrap(), 2);
+        store.refresh(&mut snapshot, token).await.unwrap();
+        assert_eq!(snapshot.keys().collect::<Vec<_>>(), ["ab"]);
+        let _ = fs::remove_dir_all(dir);
     }
 }
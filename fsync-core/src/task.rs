@@ -1,21 +1,32 @@
 use crate::convert::collapse_ops;
 use crate::{
-    config::TaskConfig,
+    config::{ChangeDetection, DeletionPolicy, InitialSync, TaskConfig, WatcherMode},
+    detect::{detect_change, hash_file, Change, LocalStat},
     file_op::{event_to_ops, FsEvent},
-    filter::PathFilter,
-    remote::{RemoteFs, RemoteOp},
+    filter::{parse_size_filter, PathFilter},
+    graph::OpScheduler,
+    priority::{sort_ranked_runs, UploadPriorities, UploadRank},
+    progress::{BatchProgress, SyncProgress, TransferProgress, PROGRESS_INTERVAL},
+    remote::{RemoteEntry, RemoteFs, RemoteOp, UPLOAD_TEMP_SUFFIX},
+    scan::{spawn_parallel_walk, DirScanner, ScanReport, ScanTrigger, WalkedFile},
+    stats::{StatsCounters, TaskStats},
+    storage::FileState,
     utils::{display_posix_path, join_posix_path, normalize_key_path, relative_posix_path},
+    watch::{resolve_mode, TaskWatcher, WatchIssue},
     StateStore,
 };
 use anyhow::{anyhow, Result};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::HashSet;
-use std::path::Path;
-use std::time::UNIX_EPOCH;
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use notify::{EventHandler, EventKind};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::path::{Component, Path};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
     fmt,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
     time::Duration,
 };
 use tokio::sync::watch::Ref;
@@ -24,11 +35,43 @@ use tokio::time::{sleep, Sleep};
 use tokio_util::sync::CancellationToken;
 use walkdir::WalkDir;
 
+/// Number of initial-sync files flushed per main-loop turn.
+const INITIAL_SYNC_CHUNK: usize = 64;
+
+/// While paused, the pending batch is collapsed each time it grows by this
+/// many events so a long pause does not hold every raw watcher event.
+const PAUSED_COMPACT_EVENTS: usize = 4_096;
+
+/// Upper bound of the delay between two retry rounds of an outbox entry.
+const OUTBOX_MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// A scan pass is followed by a pause at least this many times its duration.
+const SCAN_IDLE_FACTOR: u32 = 10;
+
+/// Upper bound of the delay between two pings of an unreachable remote.
+const OFFLINE_MAX_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Offline reason while untracked files wait for a remote listing.
+const REMOTE_LISTING_FAILED: &str = "the remote tree could not be listed";
+
+/// Interval between checks for a removed task root to come back.
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The same kind of watcher warning is sent at most once per interval; the
+/// rescans it asks for still run every time.
+const WATCH_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Filter read by the watcher callback, swapped on config updates.
+type SharedFilter = Arc<RwLock<Arc<PathFilter>>>;
+
 /// Public handle returned to callers for controlling a running sync task.
 pub struct SyncTaskHandle {
-    cfg: TaskConfig,
+    cfg: Mutex<TaskConfig>,
     ctrl_tx: mpsc::Sender<TaskCommand>,
     state_rx: watch::Receiver<TaskState>,
+    progress_rx: watch::Receiver<Option<SyncProgress>>,
+    stats: Arc<StatsCounters>,
+    event_tx: broadcast::Sender<TaskEvent>,
     log_tx: broadcast::Sender<TaskLog>,
     initial_log_rx: Mutex<Option<broadcast::Receiver<TaskLog>>>,
     stop_token: CancellationToken,
@@ -46,18 +89,69 @@ impl fmt::Debug for SyncTaskHandle {
 }
 
 impl SyncTaskHandle {
-    pub fn config(&self) -> &TaskConfig {
-        &self.cfg
+    pub fn config(&self) -> TaskConfig {
+        self.cfg.lock().unwrap().clone()
+    }
+
+    /// Apply `cfg` to the running task without restarting it. Fails when a
+    /// field that needs a restart changed (see
+    /// [`TaskConfig::check_live_update`]).
+    pub fn update_config(&self, cfg: TaskConfig) -> Result<()> {
+        let mut current = self.cfg.lock().unwrap();
+        current.check_live_update(&cfg)?;
+        self.ctrl_tx
+            .try_send(TaskCommand::UpdateConfig(Box::new(cfg.clone())))
+            .map_err(|e| anyhow!("task `{}` did not accept the update: {e}", cfg.name))?;
+        *current = cfg;
+        Ok(())
     }
 
     pub fn stop(&self) {
         self.stop_token.cancel();
         let _ = self.ctrl_tx.try_send(TaskCommand::Stop);
     }
+
+    /// Stop sending changes to the remote. Local changes keep being collected
+    /// and are flushed on [`resume`](Self::resume).
+    pub fn pause(&self) {
+        let _ = self.ctrl_tx.try_send(TaskCommand::Pause);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.ctrl_tx.try_send(TaskCommand::Resume);
+    }
+
+    /// Walk the whole local tree now instead of waiting for the next scan.
+    pub fn rescan(&self) {
+        let _ = self.ctrl_tx.try_send(TaskCommand::Rescan);
+    }
+
+    /// Upload the given files or subtrees again, ignoring the recorded state.
+    /// Relative paths are resolved against the task's local root.
+    pub fn resync(&self, paths: Vec<PathBuf>) {
+        let _ = self.ctrl_tx.try_send(TaskCommand::Resync { paths });
+    }
+
     pub fn state(&self) -> Ref<'_, TaskState> {
         self.state_rx.borrow()
     }
 
+    /// Progress of the batch being applied, or of the last one.
+    pub fn progress(&self) -> Option<SyncProgress> {
+        self.progress_rx.borrow().clone()
+    }
+
+    /// Counters since the task was spawned.
+    pub fn stats(&self) -> TaskStats {
+        self.stats.snapshot()
+    }
+
+    /// Every event the task emits from now on. Slow receivers lose the
+    /// oldest events, see [`broadcast::error::RecvError::Lagged`].
+    pub fn subscribe_events(&self) -> broadcast::Receiver<TaskEvent> {
+        self.event_tx.subscribe()
+    }
+
     pub fn subscribe_logs(&self) -> broadcast::Receiver<TaskLog> {
         self.initial_log_rx
             .lock()
@@ -77,6 +171,11 @@ impl Drop for SyncTaskHandle {
 #[derive(Debug, Clone)]
 pub enum TaskCommand {
     Stop,
+    Pause,
+    Resume,
+    Rescan,
+    Resync { paths: Vec<PathBuf> },
+    UpdateConfig(Box<TaskConfig>),
 }
 
 #[derive(Debug, Clone)]
@@ -84,6 +183,10 @@ pub enum TaskState {
     Idle,
     Starting(String),
     Running,
+    /// Watching and collecting changes without sending them
+    Paused,
+    /// The remote is unreachable; changes are collected until a ping succeeds
+    Offline(String),
     Error(String),
 }
 
@@ -112,37 +215,221 @@ pub enum TaskEvent {
     State(TaskState),
     Log(TaskLog),
     RemoteOp(RemoteOpLog),
+    /// Batch transfer progress, sent at most every few hundred milliseconds
+    /// and once more when the batch is done
+    Progress(SyncProgress),
+    BatchFinished(BatchReport),
+    /// Sent after each pass of the periodic scanner
+    Scan(ScanReport),
+    /// A problem the task works around but the user may want to fix
+    Warning(TaskWarning),
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskWarning {
+    pub message: String,
+    /// What the user can do about it
+    pub remediation: String,
+}
+
+/// Outcome of one batch of remote ops.
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    pub applied: usize,
+    pub failed: usize,
+    /// Ops not attempted because an op they depend on failed
+    pub skipped: usize,
+    pub bytes_sent: u64,
+    pub retries: u64,
+    pub elapsed: Duration,
 }
 
 pub trait TaskEventHandler: Send + Sync + 'static {
     fn emit(&self, event: TaskEvent);
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 enum StateUpdate {
-    Put(String, u64),
+    Put(String, FileState),
     RemoveTree(String),
 }
 
+/// Stored as the outbox payload, so it survives restarts.
+#[derive(Serialize, Deserialize)]
 struct PlannedRemoteOp {
     op: RemoteOp,
     state_updates: Vec<StateUpdate>,
+    /// Set for uploads so they can be reordered by priority
+    rank: Option<UploadRank>,
+}
+
+/// Ping schedule while the remote is unreachable.
+struct OfflineProbe {
+    /// Error of the last ping
+    reason: String,
+    delay: Duration,
+    timer: std::pin::Pin<Box<Sleep>>,
+}
+
+impl OfflineProbe {
+    fn new(reason: String, delay: Duration) -> Self {
+        Self {
+            reason,
+            delay,
+            timer: Box::pin(sleep(delay)),
+        }
+    }
+
+    /// Schedule the next ping after another failed one.
+    fn back_off(&mut self, reason: String) {
+        self.reason = reason;
+        self.delay = (self.delay * 2).min(OFFLINE_MAX_PROBE_INTERVAL);
+        self.timer = Box::pin(sleep(self.delay));
+    }
+}
+
+/// Startup walk in progress. The walk threads stream files in; the main loop
+/// compares them with the cache snapshot and queues the changed ones.
+struct InitialScan {
+    found: mpsc::Receiver<Vec<WalkedFile>>,
+    state_snapshot: HashMap<String, FileState>,
+    live_cache_keys: HashSet<String>,
+    /// Whether the cache still holds absolute keys of an old version
+    legacy_keys: bool,
+    /// Remote files by path, listed when the first untracked file shows up
+    remote_listing: Option<HashMap<String, RemoteEntry>>,
+    /// Untracked files held back while the remote is unreachable; they are
+    /// compared with its listing once it answers
+    deferred: Vec<(UploadRank, PathBuf, LocalStat)>,
+    /// The walk is over, only `deferred` still waits for the remote
+    walk_done: bool,
+    files: usize,
+    candidates: usize,
+    migrated: usize,
+    adopted: usize,
+    checksum_errors: usize,
+    progress_at: Option<std::time::Instant>,
+}
+
+impl InitialScan {
+    fn new(
+        found: mpsc::Receiver<Vec<WalkedFile>>,
+        state_snapshot: HashMap<String, FileState>,
+    ) -> Self {
+        let legacy_keys = state_snapshot
+            .keys()
+            .any(|key| Path::new(key).is_absolute());
+        Self {
+            found,
+            state_snapshot,
+            live_cache_keys: HashSet::new(),
+            legacy_keys,
+            remote_listing: None,
+            deferred: Vec::new(),
+            walk_done: false,
+            files: 0,
+            candidates: 0,
+            migrated: 0,
+            adopted: 0,
+            checksum_errors: 0,
+            progress_at: None,
+        }
+    }
+
+    /// Whether enough time passed since the last progress report.
+    fn progress_due(&mut self) -> bool {
+        let now = std::time::Instant::now();
+        if self
+            .progress_at
+            .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+        {
+            return false;
+        }
+        self.progress_at = Some(now);
+        true
+    }
+
+    fn progress_label(&self) -> String {
+        format!(
+            "Scanning local tree: {} file(s), {} to sync",
+            self.files, self.candidates
+        )
+    }
+}
+
+/// Startup sync work left for the main loop. Uploads come out by rank across
+/// every walk chunk queued so far, then the removals found once the walk ends.
+#[derive(Default)]
+struct Backlog {
+    uploads: BinaryHeap<Reverse<(UploadRank, PathBuf)>>,
+    removals: VecDeque<FsEvent>,
+}
+
+impl Backlog {
+    fn len(&self) -> usize {
+        self.uploads.len() + self.removals.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.uploads.is_empty() && self.removals.is_empty()
+    }
+
+    fn queue_uploads(&mut self, uploads: Vec<(UploadRank, PathBuf)>) {
+        self.uploads.extend(uploads.into_iter().map(Reverse));
+    }
+
+    /// Take up to `max` events, the best ranked uploads first.
+    fn next_chunk(&mut self, max: usize) -> Vec<FsEvent> {
+        let mut chunk = Vec::with_capacity(max.min(self.len()));
+        while chunk.len() < max {
+            let Some(Reverse((_, path))) = self.uploads.pop() else {
+                break;
+            };
+            chunk.push(FsEvent::Modify(path));
+        }
+        let rest = max - chunk.len();
+        chunk.extend(self.removals.drain(..rest.min(self.removals.len())));
+        chunk
+    }
+}
+
+/// Outbox entry backing one op of a running batch.
+#[derive(Clone, Copy)]
+struct OutboxSlot {
+    id: i64,
+    /// Failed retry rounds so far
+    attempts: u32,
 }
 
 #[derive(Clone)]
 struct BroadcastTaskEventHandler {
     state_tx: watch::Sender<TaskState>,
+    progress_tx: watch::Sender<Option<SyncProgress>>,
+    event_tx: broadcast::Sender<TaskEvent>,
     log_tx: broadcast::Sender<TaskLog>,
 }
 
 impl BroadcastTaskEventHandler {
-    fn new(state_tx: watch::Sender<TaskState>, log_tx: broadcast::Sender<TaskLog>) -> Self {
-        Self { state_tx, log_tx }
+    fn new(
+        state_tx: watch::Sender<TaskState>,
+        progress_tx: watch::Sender<Option<SyncProgress>>,
+        event_tx: broadcast::Sender<TaskEvent>,
+        log_tx: broadcast::Sender<TaskLog>,
+    ) -> Self {
+        Self {
+            state_tx,
+            progress_tx,
+            event_tx,
+            log_tx,
+        }
     }
 }
 
 impl TaskEventHandler for BroadcastTaskEventHandler {
     fn emit(&self, event: TaskEvent) {
+        if self.event_tx.receiver_count() > 0 {
+            let _ = self.event_tx.send(event.clone());
+        }
         match event {
             TaskEvent::State(state) => {
                 let _ = self.log_tx.send(TaskLog {
@@ -161,6 +448,16 @@ impl TaskEventHandler for BroadcastTaskEventHandler {
                     remote_op: Some(log),
                 });
             }
+            TaskEvent::Progress(progress) => {
+                let _ = self.progress_tx.send(Some(progress));
+            }
+            TaskEvent::Warning(warning) => {
+                let _ = self.log_tx.send(TaskLog {
+                    message: format!("Warning: {} ({})", warning.message, warning.remediation),
+                    remote_op: None,
+                });
+            }
+            TaskEvent::BatchFinished(_) | TaskEvent::Scan(_) => {}
         }
     }
 }
@@ -168,8 +465,10 @@ impl TaskEventHandler for BroadcastTaskEventHandler {
 pub(crate) struct SyncTask {
     cfg: TaskConfig,
     filter: Arc<PathFilter>,
+    priorities: UploadPriorities,
     size_min: Option<u64>,
     size_max: Option<u64>,
+    stats: Arc<StatsCounters>,
 }
 
 impl SyncTask {
@@ -177,18 +476,23 @@ impl SyncTask {
         if let Ok(local) = cfg.local.canonicalize() {
             cfg.local = local;
         }
-        let filter = Arc::new(PathFilter::new(&cfg.local, &cfg.include, &cfg.exclude));
+        let filter = Arc::new(
+            PathFilter::from_rules(&cfg.local, &cfg.filters).with_ignore_files(cfg.gitignore),
+        );
+        let priorities = UploadPriorities::new(&cfg.local, &cfg.priorities);
         let (size_min, size_max) = parse_size_filter(cfg.size.as_deref());
         Self {
             cfg,
             filter,
+            priorities,
             size_min,
             size_max,
+            stats: Arc::default(),
         }
     }
 
     pub async fn run(
-        self,
+        mut self,
         remote: impl RemoteFs,
         mut ctrl_rx: mpsc::Receiver<TaskCommand>,
         event_handler: Arc<dyn TaskEventHandler>,
@@ -196,11 +500,7 @@ impl SyncTask {
     ) {
         let (op_tx, mut op_rx) = mpsc::unbounded_channel::<FsEvent>();
         emit_state(&event_handler, TaskState::Starting("Opening cache".into()));
-        let cache_dir = self
-            .cfg
-            .cache_dir
-            .clone()
-            .unwrap_or_else(|| PathBuf::from(format!("cache/{}", self.cfg.id)));
+        let cache_dir = self.cfg.state_dir();
         tracing::info!(
             task_id = %self.cfg.id,
             task_name = %self.cfg.name,
@@ -225,12 +525,65 @@ impl SyncTask {
         };
         tracing::info!(task_id = %self.cfg.id, "task cache opened");
 
+        emit_state(
+            &event_handler,
+            TaskState::Starting("Connecting to remote".into()),
+        );
+        let mut offline = self.probe_remote(&remote, &event_handler).await;
+
+        if self.cfg.stale_temp_secs > 0 && offline.is_none() {
+            emit_state(
+                &event_handler,
+                TaskState::Starting("Removing stale remote temp files".into()),
+            );
+            self.sweep_stale_temp_files(&remote, &event_handler, &stop_token)
+                .await;
+        }
+
         emit_state(
             &event_handler,
             TaskState::Starting("Starting watcher".into()),
         );
-        let mut watcher_guard = match self.spawn_watcher(op_tx.clone()) {
-            Ok(guard) => guard,
+        let watch_filter: SharedFilter = Arc::new(RwLock::new(self.filter.clone()));
+        let (watcher_mode, fs_type) = resolve_mode(self.cfg.watcher_mode, &self.cfg.local);
+        let poll_interval = Duration::from_millis(self.cfg.poll_interval_ms.max(100));
+        match (watcher_mode, fs_type) {
+            (WatcherMode::Poll, Some(fs_type)) => emit_log(
+                &event_handler,
+                format!(
+                    "Local tree is on {fs_type}, polling it every {:.1}s instead of watching",
+                    poll_interval.as_secs_f64()
+                ),
+            ),
+            (WatcherMode::Poll, None) => emit_log(
+                &event_handler,
+                format!(
+                    "Polling local tree every {:.1}s",
+                    poll_interval.as_secs_f64()
+                ),
+            ),
+            (WatcherMode::Hybrid, _) => emit_log(
+                &event_handler,
+                format!(
+                    "Watching local tree and polling it every {:.1}s",
+                    poll_interval.as_secs_f64()
+                ),
+            ),
+            _ => {}
+        }
+        // A rescan requested during the initial sync is kept and runs as soon
+        // as the scanner starts.
+        let scan_trigger = Arc::new(ScanTrigger::default());
+        let (issue_tx, mut issue_rx) = mpsc::unbounded_channel();
+        let (mut watcher, startup_issue) = match self.spawn_watcher(
+            watcher_mode,
+            poll_interval,
+            watch_filter.clone(),
+            op_tx.clone(),
+            issue_tx,
+            scan_trigger.clone(),
+        ) {
+            Ok(started) => started,
             Err(e) => {
                 emit_state(
                     &event_handler,
@@ -244,118 +597,61 @@ impl SyncTask {
             &event_handler,
             TaskState::Starting("Scanning local tree".into()),
         );
-        {
-            let mut initial_ops: Vec<FsEvent> = Vec::new();
-            let mut live_cache_keys: HashSet<String> = HashSet::new();
-            let mut migrated_cache_entries = 0usize;
-            let mut state_snapshot = match store.load_all_u64().await {
-                Ok(snapshot) => snapshot,
-                Err(e) => {
-                    emit_state(
-                        &event_handler,
-                        TaskState::Error(format!("state store read error: {e}")),
-                    );
-                    return;
-                }
-            };
-            for entry in WalkDir::new(&self.cfg.local)
-                .into_iter()
-                .filter_entry(|entry| {
-                    entry.depth() == 0
-                        || !entry.file_type().is_dir()
-                        || self.filter.check_dir(entry.path())
-                })
-                .filter_map(|e| e.ok())
-            {
-                if entry.file_type().is_file() {
-                    let path = entry.into_path();
-                    if self.filter.check(&path) {
-                        let key = self.state_key(&path);
-                        if !state_snapshot.contains_key(&key) {
-                            if let Some(ts) =
-                                self.migrate_legacy_state_key(&path, &key, &store).await
-                            {
-                                migrated_cache_entries += 1;
-                                state_snapshot.insert(key.clone(), ts);
-                            }
-                        }
-                        let last = state_snapshot.get(&key);
-                        live_cache_keys.insert(key);
-                        if should_queue_entry(&path, self.size_min, self.size_max, last) {
-                            initial_ops.push(FsEvent::Modify(path));
-                        }
-                    }
-                }
-            }
-            if migrated_cache_entries > 0 {
-                emit_log(
+        // The startup walk streams files into the backlog, which the main loop
+        // drains in rank order while the walk goes on, so uploads start early
+        // and live batches can run in between. The periodic scanner only starts
+        // once both are done, otherwise it would re-queue the whole backlog.
+        let state_snapshot = match store.load_all().await {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                emit_state(
                     &event_handler,
-                    format!("Migrated {migrated_cache_entries} cache entrie(s) to relative keys"),
+                    TaskState::Error(format!("state store read error: {e}")),
                 );
-            }
-            let cleaned_cache_entries = match store.cleanup_missing(&live_cache_keys).await {
-                Ok(removed) if removed > 0 => {
-                    emit_log(
-                        &event_handler,
-                        format!(
-                            "Cleaned {removed} stale cache entries, {} live entries remain",
-                            live_cache_keys.len()
-                        ),
-                    );
-                    removed
-                }
-                Ok(_) => 0,
-                Err(e) => {
-                    emit_log(&event_handler, format!("Cache cleanup failed: {e}"));
-                    0
-                }
-            };
-            if cleaned_cache_entries > 0 {
-                if let Err(e) = store.flush().await {
-                    emit_log(&event_handler, format!("Cache cleanup flush failed: {e}"));
-                }
-            }
-            emit_log(
-                &event_handler,
-                format!("Initial scan found {} candidate file(s)", initial_ops.len()),
-            );
-            emit_state(
-                &event_handler,
-                TaskState::Starting("Applying initial sync".into()),
-            );
-            if let Err(e) = self
-                .flush_batch(&remote, initial_ops, &store, &event_handler, &stop_token)
-                .await
-            {
-                if let Some(stop_watcher) = watcher_guard.take() {
-                    stop_watcher();
-                }
-                if stop_token.is_cancelled() {
-                    emit_state(&event_handler, TaskState::Idle);
-                } else {
-                    emit_state(
-                        &event_handler,
-                        TaskState::Error(format!("initial sync error: {e}")),
-                    );
-                }
                 return;
             }
-        }
-
-        let scan_cancel = CancellationToken::new();
-        let scan_handle = {
-            let scan_cancel = scan_cancel.clone();
-            self.spawn_scanner(scan_cancel, op_tx.clone(), store.clone())
         };
+        let mut initial_scan = Some(InitialScan::new(
+            spawn_parallel_walk(
+                self.cfg.local.clone(),
+                self.filter.clone(),
+                stop_token.child_token(),
+            ),
+            state_snapshot,
+        ));
+        let mut backlog = Backlog::default();
+
+        match store.outbox_retry_now().await {
+            Ok(0) => {}
+            Ok(left) => emit_log(
+                &event_handler,
+                format!("Replaying {left} remote op(s) left from the last run"),
+            ),
+            Err(e) => emit_log(&event_handler, format!("Outbox read failed: {e}")),
+        }
+        let mut outbox_timer = self.outbox_timer(&store).await;
+
+        let mut scanner: Option<(CancellationToken, tokio::task::JoinHandle<()>)> = None;
+        let mut root_check: Option<std::pin::Pin<Box<Sleep>>> = None;
+        let mut warned: HashMap<std::mem::Discriminant<WatchIssue>, tokio::time::Instant> =
+            HashMap::new();
+        if let Some(issue) = startup_issue {
+            self.warn_watch_issue(&issue, &mut warned, &event_handler);
+        }
 
-        emit_state(&event_handler, TaskState::Running);
+        if offline.is_some() {
+            emit_state(&event_handler, live_state(false, &offline));
+        }
         // batching variables
-        let debounce = Duration::from_millis(self.cfg.debounce_ms);
+        let mut debounce = Duration::from_millis(self.cfg.debounce_ms);
         let mut batch: Vec<FsEvent> = Vec::new();
         let mut sleeper: Option<std::pin::Pin<Box<Sleep>>> = None;
         let mut stopped_by_command = false;
+        let mut paused = false;
+        let mut compact_at = PAUSED_COMPACT_EVENTS;
         loop {
             tokio::select! {
+                biased;
                 Some(cmd) = ctrl_rx.recv() => {
                     match cmd {
                         TaskCommand::Stop => {
@@ -363,119 +659,513 @@ impl SyncTask {
                             stopped_by_command = true;
                             break;
                         }
+                        TaskCommand::Pause if !paused => {
+                            paused = true;
+                            sleeper = None;
+                            emit_state(&event_handler, TaskState::Paused);
+                        }
+                        TaskCommand::Resume if paused => {
+                            paused = false;
+                            emit_state(&event_handler, live_state(paused, &offline));
+                            batch = collapse_ops(std::mem::take(&mut batch));
+                            compact_at = PAUSED_COMPACT_EVENTS;
+                            if !batch.is_empty() && offline.is_none() {
+                                emit_log(
+                                    &event_handler,
+                                    format!("Resuming with {} pending change(s)", batch.len()),
+                                );
+                                sleeper = Some(Box::pin(sleep(Duration::ZERO)));
+                            }
+                        }
+                        TaskCommand::Pause | TaskCommand::Resume => {}
+                        TaskCommand::Rescan => {
+                            emit_log(&event_handler, "Rescan requested");
+                            scan_trigger.full();
+                        }
+                        TaskCommand::UpdateConfig(cfg) => {
+                            let old_filter = self.filter.clone();
+                            let old_size = (self.size_min, self.size_max);
+                            if let Err(e) = self.update_config(*cfg) {
+                                emit_log(&event_handler, format!("Configuration update rejected: {e}"));
+                                continue;
+                            }
+                            debounce = Duration::from_millis(self.cfg.debounce_ms);
+                            *watch_filter.write().unwrap() = self.filter.clone();
+                            if let Some((scan_cancel, scan_handle)) = scanner.take() {
+                                scan_cancel.cancel();
+                                let _ = scan_handle.await;
+                                scanner = Some(self.spawn_scanner(
+                                    false,
+                                    scan_trigger.clone(),
+                                    op_tx.clone(),
+                                    store.clone(),
+                                    &event_handler,
+                                ));
+                            }
+                            emit_log(&event_handler, "Configuration updated");
+                            self.spawn_included_scan(old_filter, old_size, op_tx.clone(), &event_handler);
+                        }
+                        TaskCommand::Resync { paths } => {
+                            let queued = self.queue_resync(paths, &store, &event_handler, &mut batch).await;
+                            if queued > 0 && !paused && offline.is_none() {
+                                sleeper = Some(Box::pin(sleep(Duration::ZERO)));
+                            }
+                        }
                     }
                 }
                 _ = stop_token.cancelled() => {
                     stopped_by_command = true;
                     break;
                 }
+                Some(issue) = issue_rx.recv() => {
+                    // Nothing is watched until the root is back; the full
+                    // rescan after re-watching covers what happened meanwhile.
+                    if root_check.is_some() {
+                        continue;
+                    }
+                    self.warn_watch_issue(&issue, &mut warned, &event_handler);
+                    match &issue {
+                        WatchIssue::RootRemoved => {
+                            root_check = Some(Box::pin(sleep(ROOT_CHECK_INTERVAL)));
+                            continue;
+                        }
+                        WatchIssue::WatchLimit(_) => {
+                            watcher.add_polling();
+                        }
+                        WatchIssue::Overflow(_) | WatchIssue::Error(..) => {}
+                    }
+                    match issue.rescan_paths() {
+                        Some(paths) => scan_trigger.dirty(paths.iter().cloned()),
+                        None => scan_trigger.full(),
+                    }
+                }
+                _ = async { if let Some(ref mut t) = root_check { t.as_mut().await } }, if root_check.is_some() => {
+                    root_check = None;
+                    if !self.cfg.local.is_dir() {
+                        root_check = Some(Box::pin(sleep(ROOT_CHECK_INTERVAL)));
+                        continue;
+                    }
+                    match watcher.rewatch() {
+                        Ok(()) => {
+                            emit_log(
+                                &event_handler,
+                                format!("{} is back, watching it again", display_path(&self.cfg.local)),
+                            );
+                            scan_trigger.full();
+                        }
+                        Err(e) => {
+                            tracing::debug!("re-watching the task root failed: {e}");
+                            root_check = Some(Box::pin(sleep(ROOT_CHECK_INTERVAL)));
+                        }
+                    }
+                }
                 Some(op) = op_rx.recv() => {
                     batch.push(op);
-                    // if sleeper.is_none() {
-                    sleeper = Some(Box::pin(sleep(debounce)));
-                    // }
+                    if !paused && offline.is_none() {
+                        sleeper = Some(Box::pin(sleep(debounce)));
+                    } else if batch.len() >= compact_at {
+                        batch = collapse_ops(std::mem::take(&mut batch));
+                        compact_at = batch.len() + PAUSED_COMPACT_EVENTS;
+                    }
                 }
                 _ = async { if let Some(ref mut s) = sleeper { s.as_mut().await } }, if sleeper.is_some() => {
-                    if let Err(e) = self.flush_batch(&remote, std::mem::take(&mut batch), &store, &event_handler, &stop_token).await {
-                        if stop_token.is_cancelled() {
+                    let queued = match self.flush_batch(&remote, std::mem::take(&mut batch), &store, &event_handler, &stop_token).await {
+                        Ok(queued) => queued,
+                        Err(e) => {
+                            if stop_token.is_cancelled() {
+                                stopped_by_command = true;
+                            } else {
+                                emit_state(&event_handler, TaskState::Error(format!("batch error: {e}")));
+                            }
+                            break;
+                        }
+                    };
+                    sleeper = None;
+                    if queued > 0 {
+                        offline = self.probe_remote(&remote, &event_handler).await;
+                        if offline.is_some() {
+                            emit_state(&event_handler, live_state(paused, &offline));
+                        }
+                    }
+                    outbox_timer = self.outbox_timer(&store).await;
+                }
+                _ = async { if let Some(ref mut t) = outbox_timer { t.as_mut().await } }, if outbox_timer.is_some() && !paused && offline.is_none() => {
+                    let queued = match self.replay_outbox(&remote, &store, &event_handler, &stop_token).await {
+                        Ok(queued) => queued,
+                        Err(e) => {
+                            if stop_token.is_cancelled() {
+                                stopped_by_command = true;
+                            } else {
+                                emit_state(&event_handler, TaskState::Error(format!("outbox error: {e}")));
+                            }
+                            break;
+                        }
+                    };
+                    if queued > 0 {
+                        offline = self.probe_remote(&remote, &event_handler).await;
+                        if offline.is_some() {
+                            emit_state(&event_handler, live_state(paused, &offline));
+                        }
+                    }
+                    outbox_timer = self.outbox_timer(&store).await;
+                }
+                _ = async { if let Some(ref mut probe) = offline { probe.timer.as_mut().await } }, if offline.is_some() => {
+                    match remote.ping().await {
+                        Ok(()) => {
+                            // Held back files are compared before anything is
+                            // sent, so adopted ones are not uploaded.
+                            if let Some(scan) = initial_scan.as_mut().filter(|scan| !scan.deferred.is_empty()) {
+                                let queued = self
+                                    .plan_walked_files(Vec::new(), scan, Some(&remote), &store, &event_handler, &stop_token)
+                                    .await;
+                                backlog.queue_uploads(queued);
+                                if !scan.deferred.is_empty() {
+                                    if let Some(probe) = &mut offline {
+                                        probe.back_off(REMOTE_LISTING_FAILED.into());
+                                    }
+                                    continue;
+                                }
+                            }
+                            offline = None;
+                            emit_log(&event_handler, "Remote reachable again");
+                            emit_state(&event_handler, live_state(paused, &offline));
+                            if let Err(e) = store.outbox_retry_now().await {
+                                emit_log(&event_handler, format!("Outbox read failed: {e}"));
+                            }
+                            outbox_timer = self.outbox_timer(&store).await;
+                            batch = collapse_ops(std::mem::take(&mut batch));
+                            compact_at = PAUSED_COMPACT_EVENTS;
+                            if !batch.is_empty() && !paused {
+                                emit_log(
+                                    &event_handler,
+                                    format!("Sending {} change(s) collected while offline", batch.len()),
+                                );
+                                sleeper = Some(Box::pin(sleep(Duration::ZERO)));
+                            }
+                        }
+                        Err(e) => {
+                            if let Some(probe) = &mut offline {
+                                probe.back_off(e.to_string());
+                            }
+                        }
+                    }
+                }
+                // Once walked, the scan waits for its held back files before
+                // it finishes.
+                found = async { initial_scan.as_mut().unwrap().found.recv().await }, if initial_scan.as_ref().is_some_and(|scan| !scan.walk_done || scan.deferred.is_empty()) => {
+                    match found {
+                        Some(files) => {
+                            let scan = initial_scan.as_mut().unwrap();
+                            let queued = self
+                                .plan_walked_files(files, scan, offline.is_none().then_some(&remote), &store, &event_handler, &stop_token)
+                                .await;
+                            backlog.queue_uploads(queued);
+                            if !scan.deferred.is_empty() && offline.is_none() {
+                                offline = Some(self.offline_probe(REMOTE_LISTING_FAILED.into()));
+                                emit_state(&event_handler, live_state(paused, &offline));
+                            }
+                            if !paused && offline.is_none() && scan.progress_due() {
+                                emit_state(&event_handler, TaskState::Starting(scan.progress_label()));
+                            }
+                        }
+                        // the walk was cut short, its results are incomplete
+                        None if stop_token.is_cancelled() => {
                             stopped_by_command = true;
-                        } else {
-                            emit_state(&event_handler, TaskState::Error(format!("batch error: {e}")));
+                            break;
+                        }
+                        None if !initial_scan.as_ref().unwrap().deferred.is_empty() => {
+                            initial_scan.as_mut().unwrap().walk_done = true;
+                        }
+                        None => {
+                            let scan = initial_scan.take().unwrap();
+                            let synced_all = scan.candidates > 0 && backlog.is_empty();
+                            let removals = self.finish_initial_scan(scan, &store, &event_handler).await;
+                            backlog.removals.extend(removals);
+                            if synced_all && backlog.is_empty() {
+                                emit_log(&event_handler, "Initial sync finished");
+                            }
+                            emit_state(&event_handler, live_state(paused, &offline));
                         }
-                        break;
                     }
-                    sleeper = None;
                 }
+                _ = std::future::ready(()), if !backlog.is_empty() && !paused && offline.is_none() => {
+                    let chunk = backlog.next_chunk(INITIAL_SYNC_CHUNK);
+                    let queued = match self.flush_batch(&remote, chunk, &store, &event_handler, &stop_token).await {
+                        Ok(queued) => queued,
+                        Err(e) => {
+                            if stop_token.is_cancelled() {
+                                stopped_by_command = true;
+                            } else {
+                                emit_state(&event_handler, TaskState::Error(format!("initial sync error: {e}")));
+                            }
+                            break;
+                        }
+                    };
+                    if backlog.is_empty() && initial_scan.is_none() {
+                        emit_log(&event_handler, "Initial sync finished");
+                    }
+                    if queued > 0 {
+                        offline = self.probe_remote(&remote, &event_handler).await;
+                        if offline.is_some() {
+                            emit_state(&event_handler, live_state(paused, &offline));
+                        }
+                    }
+                    outbox_timer = self.outbox_timer(&store).await;
+                }
+            }
+            if backlog.is_empty() && initial_scan.is_none() && scanner.is_none() {
+                scanner = Some(self.spawn_scanner(
+                    true,
+                    scan_trigger.clone(),
+                    op_tx.clone(),
+                    store.clone(),
+                    &event_handler,
+                ));
             }
+            self.stats.set_queue_depth(backlog.len() + batch.len());
         }
-        if !batch.is_empty() {
+        // A paused or offline task must not upload on the way out; the startup
+        // scan picks the pending changes up next time.
+        if !batch.is_empty() && !paused && offline.is_none() {
             let _ = self
                 .flush_batch(&remote, batch, &store, &event_handler, &stop_token)
                 .await;
         }
-        scan_cancel.cancel();
-        let _ = scan_handle.await;
-        if let Some(stop_watcher) = watcher_guard {
-            stop_watcher();
+        if let Some((scan_cancel, scan_handle)) = scanner {
+            scan_cancel.cancel();
+            let _ = scan_handle.await;
         }
+        watcher.stop();
         if stopped_by_command {
             emit_state(&event_handler, TaskState::Idle);
         }
     }
 
+    /// Start the periodic scanner. With `scan_now` the first pass runs
+    /// immediately, otherwise after one interval. The first pass is full, and
+    /// so are those `trigger` asks for and one every `full_scan_secs`; other
+    /// passes read only the directories that changed or that `trigger` marked
+    /// dirty.
     fn spawn_scanner(
         &self,
-        cancel: CancellationToken,
+        scan_now: bool,
+        trigger: Arc<ScanTrigger>,
         scan_tx: mpsc::UnboundedSender<FsEvent>,
         store: StateStore,
-    ) -> tokio::task::JoinHandle<()> {
-        let scan_interval = Duration::from_millis(self.cfg.scan_ms).max(Duration::from_secs(30));
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) -> (CancellationToken, tokio::task::JoinHandle<()>) {
+        let cancel = CancellationToken::new();
+        let scan_interval = Duration::from_millis(self.cfg.scan_ms);
+        let full_interval =
+            (self.cfg.full_scan_secs > 0).then(|| Duration::from_secs(self.cfg.full_scan_secs));
         let scan_path = self.cfg.local.clone();
         let filter = self.filter.clone();
         let size_min = self.size_min;
         let size_max = self.size_max;
-
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(scan_interval);
+        let detection = self.cfg.change_detection;
+        let event_handler = event_handler.clone();
+
+        let scan_cancel = cancel.clone();
+        let handle = tokio::spawn(async move {
+            let mut dirs = DirScanner::new(scan_path.clone());
+            let mut state_snapshot = HashMap::new();
+            let mut snapshot_token = 0;
+            let mut next_scan = tokio::time::Instant::now();
+            if !scan_now {
+                next_scan += scan_interval;
+            }
+            let mut next_full = Some(next_scan);
             loop {
+                let mut full = false;
                 tokio::select! {
                     _ = cancel.cancelled() => break,
-                    _ = interval.tick() => {}
+                    _ = tokio::time::sleep_until(next_scan) => {}
+                    wanted = trigger.wait() => {
+                        full = wanted.full;
+                        dirs.invalidate(&wanted.dirty);
+                    }
                 }
+                let started = tokio::time::Instant::now();
+                full |= next_full.is_some_and(|at| started >= at);
+
+                let walk_filter = filter.clone();
+                let walk_cancel = cancel.clone();
+                let walk = tokio::task::spawn_blocking(move || {
+                    if full && walk_filter.reload_changed_ignore_files() {
+                        tracing::debug!("ignore files changed since the last full scan");
+                    }
+                    let scan = dirs.scan(&walk_filter, full, &walk_cancel);
+                    (dirs, scan)
+                })
+                .await;
+                let Ok((walked, Some(scan))) = walk else {
+                    break;
+                };
+                dirs = walked;
 
-                let state_snapshot = match store.load_all_u64().await {
-                    Ok(snapshot) => snapshot,
+                // Only rows written since the previous pass are read.
+                snapshot_token = match store.refresh(&mut state_snapshot, snapshot_token).await {
+                    Ok(token) => token,
                     Err(e) => {
                         crate::warn!("scan cache snapshot error: {e}");
+                        next_scan = tokio::time::Instant::now() + scan_interval;
                         continue;
                     }
                 };
-                for entry in WalkDir::new(&scan_path)
-                    .into_iter()
-                    .filter_entry(|entry| {
-                        entry.depth() == 0
-                            || !entry.file_type().is_dir()
-                            || filter.check_dir(entry.path())
-                    })
-                    .filter_map(|e| e.ok())
-                {
+                let mut queued = 0;
+                for path in &scan.files {
                     if cancel.is_cancelled() {
                         break;
                     }
-                    if !entry.file_type().is_file() {
-                        continue;
-                    }
-                    let path = entry.into_path();
-                    if !filter.check(&path) {
-                        continue;
-                    }
-                    let key = relative_posix_path(&path, &scan_path)
-                        .unwrap_or_else(|| normalize_key_path(&path));
-                    if should_queue_entry(&path, size_min, size_max, state_snapshot.get(&key)) {
-                        tracing::debug!(path = %display_posix_path(&path), "scanner queued modified file");
-                        if let Err(e) = scan_tx.send(FsEvent::Modify(path)) {
+                    let key = relative_posix_path(path, &scan_path)
+                        .unwrap_or_else(|| normalize_key_path(path));
+                    let last = state_snapshot.get(&key);
+                    if queue_candidate(path, size_min, size_max, detection, last).is_some() {
+                        tracing::debug!(path = %display_posix_path(path), "scanner queued modified file");
+                        if let Err(e) = scan_tx.send(FsEvent::Modify(path.clone())) {
                             crate::warn!("{:?}", e);
                             break;
                         }
+                        queued += 1;
                     }
                 }
+
+                let elapsed = started.elapsed();
+                event_handler.emit(TaskEvent::Scan(ScanReport {
+                    full,
+                    dirs: scan.dirs,
+                    dirs_listed: scan.dirs_listed,
+                    files_checked: scan.files.len(),
+                    queued,
+                    elapsed,
+                }));
+                let now = tokio::time::Instant::now();
+                if full {
+                    next_full = full_interval.map(|interval| now + interval);
+                }
+                // Slow passes space themselves out so the scanner never
+                // keeps a core busy.
+                next_scan = now + scan_interval.max(elapsed * SCAN_IDLE_FACTOR);
             }
-        })
+        });
+        (scan_cancel, handle)
     }
 
-    async fn flush_batch(
+    /// Swap in the filters and tunables of `cfg`. The fields checked by
+    /// [`TaskConfig::check_live_update`] must be unchanged.
+    fn update_config(&mut self, mut cfg: TaskConfig) -> Result<()> {
+        if let Ok(local) = cfg.local.canonicalize() {
+            cfg.local = local;
+        }
+        self.cfg.check_live_update(&cfg)?;
+        let stats = self.stats.clone();
+        *self = SyncTask::new(cfg);
+        self.stats = stats;
+        Ok(())
+    }
+
+    /// Walk the tree in the background and queue files that the current
+    /// filters and size range admit but the previous ones did not.
+    fn spawn_included_scan(
         &self,
-        remote: &impl RemoteFs,
-        ops: Vec<FsEvent>,
-        store: &StateStore,
+        old_filter: Arc<PathFilter>,
+        old_size: (Option<u64>, Option<u64>),
+        scan_tx: mpsc::UnboundedSender<FsEvent>,
         event_handler: &Arc<dyn TaskEventHandler>,
-        stop_token: &CancellationToken,
-    ) -> Result<()> {
-        if ops.is_empty() {
-            return Ok(());
-        }
-        // collapse only consecutive Modify operations for the same path; keep order for others
-        let ops = collapse_ops(ops);
+    ) {
+        let root = self.cfg.local.clone();
+        let filter = self.filter.clone();
+        let size = (self.size_min, self.size_max);
+        let event_handler = event_handler.clone();
+        tokio::task::spawn_blocking(move || {
+            let paths = newly_included(&root, &old_filter, old_size, &filter, size);
+            if !paths.is_empty() {
+                emit_log(
+                    &event_handler,
+                    format!("Queued {} newly included file(s)", paths.len()),
+                );
+            }
+            for path in paths {
+                if scan_tx.send(FsEvent::Modify(path)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Drop the recorded state below each path and queue it as modified, so
+    /// the next flush uploads it whatever the change detection says. Returns
+    /// the number of paths queued.
+    async fn queue_resync(
+        &self,
+        paths: Vec<PathBuf>,
+        store: &StateStore,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        batch: &mut Vec<FsEvent>,
+    ) -> usize {
+        let mut queued = 0;
+        for path in paths {
+            // `..` is resolved here so it cannot reach out of the root.
+            let mut resolved = self.cfg.local.clone();
+            for component in path.components() {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        resolved.pop();
+                    }
+                    component => resolved.push(component),
+                }
+            }
+            let path = if path.is_absolute() {
+                path.canonicalize().unwrap_or(resolved)
+            } else {
+                resolved
+            };
+            let Some(key) = self.relative_local_path(&path) else {
+                emit_log(
+                    event_handler,
+                    format!(
+                        "Resync skipped {}: outside the task root",
+                        display_path(&path)
+                    ),
+                );
+                continue;
+            };
+            let cleared = if key.is_empty() {
+                store.clear().await
+            } else {
+                store.remove_tree(&key).await
+            };
+            if let Err(e) = cleared {
+                emit_log(
+                    event_handler,
+                    format!("Resync skipped {}: {e}", display_path(&path)),
+                );
+                continue;
+            }
+            emit_log(
+                event_handler,
+                format!("Resync queued {}", display_path(&path)),
+            );
+            batch.push(FsEvent::Modify(path));
+            queued += 1;
+        }
+        queued
+    }
+
+    async fn flush_batch(
+        &self,
+        remote: &impl RemoteFs,
+        ops: Vec<FsEvent>,
+        store: &StateStore,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) -> Result<usize> {
+        if ops.is_empty() {
+            return Ok(0);
+        }
+        // collapse only consecutive Modify operations for the same path; keep order for others
+        let ops = collapse_ops(ops);
         crate::debug!("collapsed fs events: {:?}", ops);
         emit_log(event_handler, summarize_fs_events(&ops));
         if self.has_directory_like_event(&ops).await {
@@ -511,12 +1201,17 @@ impl SyncTask {
                         .await?;
                     }
                 }
+                FsEvent::Remove(p) if self.cfg.deletion_policy == DeletionPolicy::Keep => {
+                    tracing::debug!(path = %display_path(p), "keep remote copy of removed path");
+                    store.remove_tree(&self.state_key(p)).await?;
+                }
                 FsEvent::Remove(p) => {
                     let remote = self.remote_path(p);
                     tracing::debug!(remote = %remote, "queued remove");
                     planned_ops.push(PlannedRemoteOp {
                         op: RemoteOp::Remove { remote },
                         state_updates: vec![StateUpdate::RemoveTree(self.state_key(p))],
+                        rank: None,
                     });
                 }
                 FsEvent::MkDir(p) => {
@@ -535,10 +1230,10 @@ impl SyncTask {
                     tracing::debug!(from = %from_remote, to = %to_remote, "queued rename");
                     // Inherit timestamp from source to avoid unnecessary upload on pure rename
                     let from_key = self.state_key(from);
-                    let last = store.get_u64(&from_key).await?;
+                    let last = store.get(&from_key).await?;
                     let mut state_updates = Vec::new();
-                    if let Some(ts) = last {
-                        state_updates.push(StateUpdate::Put(self.state_key(to), ts));
+                    if let Some(state) = last {
+                        state_updates.push(StateUpdate::Put(self.state_key(to), state));
                     }
                     // Clear the source path record
                     state_updates.push(StateUpdate::RemoveTree(from_key));
@@ -548,10 +1243,13 @@ impl SyncTask {
                             to: to_remote,
                         },
                         state_updates,
+                        rank: None,
                     });
                 }
             }
         }
+        sort_ranked_runs(&mut planned_ops, |planned| planned.rank);
+        let mut queued = 0;
         if !planned_ops.is_empty() {
             crate::debug!(
                 "applying remote ops: {:?}",
@@ -561,13 +1259,27 @@ impl SyncTask {
                     .collect::<Vec<_>>()
             );
             emit_log(event_handler, summarize_planned_remote_ops(&planned_ops));
-            self.apply_remote_ops(remote, &planned_ops, store, event_handler, stop_token)
+            let outbox = self
+                .park_in_outbox(store, &planned_ops, event_handler)
+                .await?;
+            queued = self
+                .apply_remote_ops(
+                    remote,
+                    &planned_ops,
+                    &outbox,
+                    store,
+                    event_handler,
+                    stop_token,
+                )
                 .await?;
         } else {
             emit_log(event_handler, "No remote ops after cache/filter checks");
         }
         store.flush().await?;
-        Ok(())
+        if queued == 0 {
+            self.stats.mark_success();
+        }
+        Ok(queued)
     }
 
     async fn queue_directory_tree(
@@ -588,6 +1300,7 @@ impl SyncTask {
         planned_ops.push(PlannedRemoteOp {
             op: RemoteOp::MkDir { remote },
             state_updates: Vec::new(),
+            rank: None,
         });
 
         let mut files_seen = 0usize;
@@ -609,6 +1322,7 @@ impl SyncTask {
                     planned_ops.push(PlannedRemoteOp {
                         op: RemoteOp::MkDir { remote },
                         state_updates: Vec::new(),
+                        rank: None,
                     });
                 }
                 continue;
@@ -675,24 +1389,39 @@ impl SyncTask {
             }
         }
 
-        let Ok(modified) = meta.modified() else {
-            return Ok(false);
-        };
-        let Ok(dur) = modified.duration_since(UNIX_EPOCH) else {
+        let Some(stat) = LocalStat::from_metadata(&meta) else {
             return Ok(false);
         };
-
-        let mtime = dur.as_secs();
         let key = self.state_key(path);
         if !queued_uploads.insert(key.clone()) {
             return Ok(false);
         }
-        let last = match store.get_u64(&key).await? {
-            Some(ts) => Some(ts),
+        let last = match store.get(&key).await? {
+            Some(state) => Some(state),
             None => self.migrate_legacy_state_key(path, &key, store).await,
         };
-        if last == Some(mtime) {
-            tracing::debug!(path = %display_path(path), "skip unchanged file");
+        let mode = self.cfg.change_detection;
+        let hash = match detect_change(mode, &stat, last.as_ref()) {
+            Change::Unchanged => {
+                tracing::debug!(path = %display_path(path), "skip unchanged file");
+                return Ok(false);
+            }
+            Change::Changed if mode != ChangeDetection::Hash => None,
+            Change::Changed | Change::NeedsHash => {
+                let hash_path = path.clone();
+                match tokio::task::spawn_blocking(move || hash_file(&hash_path)).await? {
+                    Ok(hash) => Some(hash),
+                    Err(e) => {
+                        tracing::debug!(path = %display_path(path), error = %e, "skip unreadable file");
+                        return Ok(false);
+                    }
+                }
+            }
+        };
+        if hash.is_some() && hash == last.as_ref().and_then(|last| last.hash.clone()) {
+            // Touched but identical: remember the new mtime, skip the upload.
+            tracing::debug!(path = %display_path(path), "skip file with unchanged content");
+            store.put(key, stat.to_state(hash)).await?;
             return Ok(false);
         }
 
@@ -703,7 +1432,8 @@ impl SyncTask {
                 local: path.clone(),
                 remote,
             },
-            state_updates: vec![StateUpdate::Put(key, mtime)],
+            state_updates: vec![StateUpdate::Put(key, stat.to_state(hash))],
+            rank: Some(self.priorities.rank(path, stat.size, stat.mtime_secs())),
         });
 
         Ok(true)
@@ -737,69 +1467,679 @@ impl SyncTask {
         false
     }
 
-    async fn apply_remote_ops(
+    /// Plan the files of one walk chunk: migrate legacy cache keys, keep the
+    /// files that changed since the last sync and adopt untracked ones whose
+    /// remote copy already matches; `remote` is `None` while it is
+    /// unreachable, untracked files then wait in `scan.deferred`. Held back
+    /// files are planned along with the chunk once `remote` is set. Returns
+    /// the uploads to queue with their rank.
+    async fn plan_walked_files(
         &self,
-        remote: &impl RemoteFs,
-        ops: &[PlannedRemoteOp],
+        files: Vec<WalkedFile>,
+        scan: &mut InitialScan,
+        remote: Option<&impl RemoteFs>,
         store: &StateStore,
         event_handler: &Arc<dyn TaskEventHandler>,
         stop_token: &CancellationToken,
-    ) -> Result<()> {
-        let mut pending_state_updates = Vec::new();
-        for planned in ops {
-            if stop_token.is_cancelled() {
-                self.apply_state_updates(store, &pending_state_updates, event_handler)
-                    .await;
-                return Err(anyhow!("task stopped"));
+    ) -> Vec<(UploadRank, PathBuf)> {
+        let compare = self.cfg.initial_sync != InitialSync::Upload;
+        let mut candidates: Vec<(UploadRank, PathBuf)> = Vec::new();
+        let mut untracked = std::mem::take(&mut scan.deferred);
+        for WalkedFile { path, meta } in files {
+            scan.files += 1;
+            let key = self.state_key(&path);
+            if scan.legacy_keys && !scan.state_snapshot.contains_key(&key) {
+                if let Some(state) = self.migrate_legacy_state_key(&path, &key, store).await {
+                    scan.migrated += 1;
+                    scan.state_snapshot.insert(key.clone(), state);
+                }
             }
-            let op = &planned.op;
-            let mut attempt: u32 = 0;
-            let mut backoff = self.cfg.retry_backoff_ms;
-            let max = self.cfg.retry_max;
+            let last = scan.state_snapshot.get(&key);
+            if let Some(stat) = candidate_stat(
+                &meta,
+                self.size_min,
+                self.size_max,
+                self.cfg.change_detection,
+                last,
+            ) {
+                let rank = self.priorities.rank(&path, stat.size, stat.mtime_secs());
+                if compare && last.is_none() {
+                    untracked.push((rank, path.clone(), stat));
+                } else {
+                    candidates.push((rank, path));
+                }
+            }
+            scan.live_cache_keys.insert(key);
+        }
 
-            loop {
-                match remote
-                    .apply_batch_cancelled(vec![op.clone()], stop_token.clone())
-                    .await
-                {
-                    Ok(_) => {
-                        let detail = describe_remote_op(op);
-                        tracing::debug!(task_id = %self.cfg.id, task_name = %self.cfg.name, operation = %detail, "remote op applied");
-                        emit_remote_op_applied(event_handler, op.clone(), detail);
-                        pending_state_updates.extend_from_slice(&planned.state_updates);
-                        if pending_state_updates.len() >= 128 {
-                            self.apply_state_updates(store, &pending_state_updates, event_handler)
-                                .await;
-                            pending_state_updates.clear();
+        if !untracked.is_empty() {
+            let listing = match (&scan.remote_listing, remote) {
+                (Some(listing), _) => Some(listing),
+                (None, Some(remote)) => {
+                    emit_state(
+                        event_handler,
+                        TaskState::Starting("Comparing with remote tree".into()),
+                    );
+                    match self.remote_listing(remote).await {
+                        Ok(listing) => Some(&*scan.remote_listing.insert(listing)),
+                        Err(e) if remote.ping().await.is_err() => {
+                            tracing::debug!("remote listing failed while offline: {e}");
+                            None
                         }
-                        break;
+                        Err(e) => {
+                            emit_log(
+                                event_handler,
+                                format!("Cannot list remote tree, uploading all files: {e}"),
+                            );
+                            Some(&*scan.remote_listing.insert(HashMap::new()))
+                        }
+                    }
+                }
+                (None, None) => None,
+            };
+            match (listing, remote) {
+                (Some(listing), Some(remote)) if !listing.is_empty() => {
+                    let (adopted, checksum_errors) = self
+                        .adopt_remote_matches(
+                            remote,
+                            store,
+                            listing,
+                            untracked
+                                .iter()
+                                .map(|(_, path, stat)| (path.clone(), *stat))
+                                .collect(),
+                            event_handler,
+                            stop_token,
+                        )
+                        .await;
+                    scan.adopted += adopted.len();
+                    scan.checksum_errors += checksum_errors;
+                    untracked.retain(|(_, path, _)| !adopted.contains(path));
+                    candidates.extend(untracked.into_iter().map(|(rank, path, _)| (rank, path)));
+                }
+                (Some(_), _) => {
+                    candidates.extend(untracked.into_iter().map(|(rank, path, _)| (rank, path)));
+                }
+                (None, _) => {
+                    if scan.deferred.is_empty() {
+                        emit_log(
+                            event_handler,
+                            "Remote unreachable, comparing untracked files once it answers",
+                        );
                     }
+                    scan.deferred = untracked;
+                }
+            }
+        }
+        scan.candidates += candidates.len();
+        candidates
+    }
+
+    /// Wrap up the startup walk: report it, detect the files deleted while
+    /// the task was stopped and drop the cache rows of vanished files.
+    /// Returns the removals to queue.
+    async fn finish_initial_scan(
+        &self,
+        mut scan: InitialScan,
+        store: &StateStore,
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) -> Vec<FsEvent> {
+        if scan.migrated > 0 {
+            emit_log(
+                event_handler,
+                format!(
+                    "Migrated {} cache entrie(s) to relative keys",
+                    scan.migrated
+                ),
+            );
+        }
+        if scan
+            .remote_listing
+            .as_ref()
+            .is_some_and(|listing| !listing.is_empty())
+        {
+            let mut summary = format!("Adopted {} file(s) already on the remote", scan.adopted);
+            if scan.checksum_errors > 0 {
+                summary.push_str(&format!(", {} could not be compared", scan.checksum_errors));
+            }
+            emit_log(event_handler, summary);
+        }
+        let deletions = self
+            .detect_offline_deletions(&scan.state_snapshot, &mut scan.live_cache_keys)
+            .unwrap_or_else(|| {
+                emit_log(
+                    event_handler,
+                    "Local tree is missing or empty, not treating cached files as deleted",
+                );
+                Vec::new()
+            });
+        if !deletions.is_empty() {
+            let action = match self.cfg.deletion_policy {
+                DeletionPolicy::Propagate => "removing them from the remote",
+                DeletionPolicy::Keep => "keeping the remote copies",
+            };
+            emit_log(
+                event_handler,
+                format!(
+                    "Detected {} local deletion(s) while stopped, {action}",
+                    deletions.len()
+                ),
+            );
+        }
+        match store.cleanup_missing(&scan.live_cache_keys).await {
+            Ok(removed) if removed > 0 => {
+                emit_log(
+                    event_handler,
+                    format!(
+                        "Cleaned {removed} stale cache entries, {} live entries remain",
+                        scan.live_cache_keys.len()
+                    ),
+                );
+                if let Err(e) = store.flush().await {
+                    emit_log(event_handler, format!("Cache cleanup flush failed: {e}"));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => emit_log(event_handler, format!("Cache cleanup failed: {e}")),
+        }
+        emit_log(
+            event_handler,
+            format!(
+                "Initial scan found {} candidate file(s) among {} file(s)",
+                scan.candidates, scan.files
+            ),
+        );
+        match self.cfg.deletion_policy {
+            DeletionPolicy::Propagate => deletions.into_iter().map(FsEvent::Remove).collect(),
+            DeletionPolicy::Keep => Vec::new(),
+        }
+    }
+
+    /// Remote files below the task root by path.
+    async fn remote_listing(&self, remote: &impl RemoteFs) -> Result<HashMap<String, RemoteEntry>> {
+        Ok(remote
+            .list_tree(&self.cfg.remote)
+            .await?
+            .into_iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect())
+    }
+
+    /// Seed the state store for untracked local files whose remote copy in
+    /// `listing` already matches, so the initial sync only uploads the
+    /// differences. Returns the adopted paths and the number of files whose
+    /// checksum could not be compared; on a store error nothing is adopted
+    /// and everything is uploaded as usual.
+    async fn adopt_remote_matches(
+        &self,
+        remote: &impl RemoteFs,
+        store: &StateStore,
+        listing: &HashMap<String, RemoteEntry>,
+        files: Vec<(PathBuf, LocalStat)>,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) -> (HashSet<PathBuf>, usize) {
+        let by_checksum = self.cfg.initial_sync == InitialSync::AdoptChecksum;
+        let same_size = files.into_iter().filter(|(path, stat)| {
+            listing.get(&self.remote_path(path)).is_some_and(|entry| {
+                entry.size == stat.size
+                    && (by_checksum || entry.mtime.is_some_and(|mtime| mtime >= stat.mtime_secs()))
+            })
+        });
+
+        let mut checksum_errors = 0usize;
+        let mut matched = Vec::new();
+        if by_checksum {
+            let results = stream::iter(same_size)
+                .map(|(path, stat)| async move {
+                    if stop_token.is_cancelled() {
+                        return Ok(None);
+                    }
+                    let remote_hash = remote.checksum(&self.remote_path(&path)).await?;
+                    let local_path = path.clone();
+                    let (local, same) = tokio::task::spawn_blocking(move || {
+                        let local = hash_file(&local_path)?;
+                        let same = remote_hash.matches_file(&local_path, &local)?;
+                        Ok::<_, anyhow::Error>((local, same))
+                    })
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|hashed| hashed)?;
+                    Ok::<_, anyhow::Error>(same.then_some((path, stat, Some(local))))
+                })
+                .buffer_unordered(self.cfg.max_parallel_ops.max(1))
+                .collect::<Vec<_>>()
+                .await;
+            for result in results {
+                match result {
+                    Ok(Some(file)) => matched.push(file),
+                    Ok(None) => {}
                     Err(e) => {
-                        attempt += 1;
-                        if attempt > max {
-                            self.apply_state_updates(store, &pending_state_updates, event_handler)
-                                .await;
-                            let detail = format!("Remote op failed: {}", describe_remote_op(op));
-                            emit_remote_op_failed(event_handler, op.clone(), detail, e.to_string());
-                            return Err(e);
+                        if checksum_errors == 0 {
+                            emit_log(event_handler, format!("Checksum comparison failed: {e}"));
                         }
+                        checksum_errors += 1;
+                    }
+                }
+            }
+        } else {
+            matched.extend(same_size.map(|(path, stat)| (path, stat, None)));
+        }
+
+        let states = matched
+            .iter()
+            .map(|(path, stat, hash)| (self.state_key(path), stat.to_state(hash.clone())))
+            .collect::<Vec<_>>();
+        if let Err(e) = store.put_many(&states).await {
+            emit_log(event_handler, format!("Cache update failed: {e}"));
+            return (HashSet::new(), checksum_errors);
+        }
+        let adopted = matched.into_iter().map(|(path, _, _)| path).collect();
+        (adopted, checksum_errors)
+    }
+
+    /// Remove `*.fsync.tmp` leftovers of interrupted uploads below the remote
+    /// root. Failures are only logged; they never keep the task from starting.
+    async fn sweep_stale_temp_files(
+        &self,
+        remote: &impl RemoteFs,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) {
+        let entries = match remote.list_tree(&self.cfg.remote).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                emit_log(event_handler, format!("Skipped remote temp cleanup: {e}"));
+                return;
+            }
+        };
+        let cutoff = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|dur| dur.as_secs())
+            .unwrap_or_default()
+            .saturating_sub(self.cfg.stale_temp_secs);
+        let mut removed = 0usize;
+        for entry in entries {
+            // Entries without an mtime may belong to an upload in progress.
+            if entry.is_dir
+                || !entry.path.ends_with(UPLOAD_TEMP_SUFFIX)
+                || entry.mtime.is_none_or(|mtime| mtime > cutoff)
+            {
+                continue;
+            }
+            let op = RemoteOp::Remove {
+                remote: entry.path.clone(),
+            };
+            match remote
+                .apply_batch_cancelled(vec![op], stop_token.clone())
+                .await
+            {
+                Ok(()) => removed += 1,
+                Err(_) if stop_token.is_cancelled() => return,
+                Err(e) => emit_log(
+                    event_handler,
+                    format!("Failed to remove stale temp file {}: {e}", entry.path),
+                ),
+            }
+        }
+        if removed > 0 {
+            emit_log(
+                event_handler,
+                format!("Removed {removed} stale remote temp file(s)"),
+            );
+        }
+    }
+
+    /// Run `ops` along their dependency graph with up to `max_parallel_ops`
+    /// ops in flight; `outbox` holds the outbox entry of each op. Applied ops
+    /// leave the outbox. An op that still fails after its retries stays
+    /// queued with a backoff, together with the ops that depend on it, while
+    /// the rest of the batch runs. Returns the number of ops left queued.
+    async fn apply_remote_ops(
+        &self,
+        remote: &impl RemoteFs,
+        ops: &[PlannedRemoteOp],
+        outbox: &[OutboxSlot],
+        store: &StateStore,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) -> Result<usize> {
+        let mut scheduler = OpScheduler::new(ops.iter().map(|planned| &planned.op));
+        let limit = self.cfg.max_parallel_ops.max(1);
+        let mut running = FuturesUnordered::new();
+        let mut pending_state_updates = Vec::new();
+        let mut applied_ids = Vec::new();
+        // (outbox id, failed rounds, next attempt, error)
+        let mut deferred: Vec<(i64, u32, u64, Option<String>)> = Vec::new();
+        let mut first_error = None;
+        let mut applied = 0usize;
+        let mut failed = 0usize;
+        let mut skipped = 0usize;
+        let mut progress = BatchProgress::new(
+            ops.iter()
+                .map(|planned| planned.rank.map(|rank| rank.size).unwrap_or_default())
+                .collect(),
+        );
+        let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let started = tokio::time::Instant::now();
+        let stats_before = self.stats.snapshot();
+
+        loop {
+            while running.len() < limit && !stop_token.is_cancelled() {
+                let Some(node) = scheduler.next_ready() else {
+                    break;
+                };
+                let transfer = progress.start(node, remote_op_target(&ops[node].op).to_string());
+                running.push(async move {
+                    let result = self
+                        .apply_op_with_retry(
+                            remote,
+                            &ops[node].op,
+                            transfer,
+                            event_handler,
+                            stop_token,
+                        )
+                        .await;
+                    (node, result)
+                });
+            }
+            let next = tokio::select! {
+                next = running.next() => next,
+                _ = ticker.tick(), if !running.is_empty() => {
+                    if progress.should_emit() {
+                        emit_progress(event_handler, progress.snapshot());
+                    }
+                    continue;
+                }
+            };
+            let Some((node, result)) = next else {
+                break;
+            };
+            let planned = &ops[node];
+            let sent = progress.finish(node, result.is_ok());
+            match result {
+                Ok(()) => {
+                    self.stats.record_applied(&planned.op, sent);
+                    let detail = describe_remote_op(&planned.op);
+                    tracing::debug!(task_id = %self.cfg.id, task_name = %self.cfg.name, operation = %detail, "remote op applied");
+                    emit_remote_op_applied(event_handler, planned.op.clone(), detail);
+                    pending_state_updates.extend_from_slice(&planned.state_updates);
+                    applied_ids.push(outbox[node].id);
+                    if pending_state_updates.len() >= 128 {
+                        self.confirm_applied(
+                            store,
+                            &mut pending_state_updates,
+                            &mut applied_ids,
+                            event_handler,
+                        )
+                        .await;
+                    }
+                    scheduler.complete(node);
+                    applied += 1;
+                }
+                Err(_) if stop_token.is_cancelled() => {}
+                Err(e) => {
+                    self.stats.record_failure();
+                    let detail = format!("Remote op failed: {}", describe_remote_op(&planned.op));
+                    emit_remote_op_failed(event_handler, planned.op.clone(), detail, e.to_string());
+                    let attempts = outbox[node].attempts + 1;
+                    let retry_at = unix_millis() + self.outbox_backoff(attempts).as_millis() as u64;
+                    deferred.push((outbox[node].id, attempts, retry_at, Some(e.to_string())));
+                    for dependent in scheduler.fail(node) {
+                        progress.finish(dependent, false);
                         emit_log(
                             event_handler,
                             format!(
-                                "Remote op failed, retry {attempt}/{max}: {}: {e}",
-                                describe_remote_op(op)
+                                "Skipped {}: depends on failed op",
+                                describe_remote_op(&ops[dependent].op)
                             ),
                         );
-                        tokio::time::sleep(Duration::from_millis(backoff)).await;
-                        backoff = backoff.saturating_mul(2);
+                        let slot = outbox[dependent];
+                        deferred.push((slot.id, slot.attempts, retry_at, None));
+                        skipped += 1;
                     }
+                    failed += 1;
+                    first_error.get_or_insert(e);
                 }
             }
         }
 
-        self.apply_state_updates(store, &pending_state_updates, event_handler)
+        let elapsed = started.elapsed();
+        self.stats.add_transfer_time(elapsed);
+        self.confirm_applied(
+            store,
+            &mut pending_state_updates,
+            &mut applied_ids,
+            event_handler,
+        )
+        .await;
+        for (id, attempts, retry_at, error) in &deferred {
+            store
+                .outbox_defer(*id, *attempts, *retry_at, error.as_deref())
+                .await?;
+        }
+        emit_progress(event_handler, progress.snapshot());
+        let stats_after = self.stats.snapshot();
+        event_handler.emit(TaskEvent::BatchFinished(BatchReport {
+            applied,
+            failed,
+            skipped,
+            bytes_sent: stats_after.bytes_sent - stats_before.bytes_sent,
+            retries: stats_after.retries - stats_before.retries,
+            elapsed,
+        }));
+        if stop_token.is_cancelled() {
+            return Err(anyhow!("task stopped"));
+        }
+        if let Some(e) = first_error {
+            emit_log(
+                event_handler,
+                format!("{failed} remote op(s) failed, {skipped} skipped, queued for retry: {e}"),
+            );
+        }
+        Ok(deferred.len())
+    }
+
+    /// Record the state of applied ops and drop them from the outbox.
+    async fn confirm_applied(
+        &self,
+        store: &StateStore,
+        state_updates: &mut Vec<StateUpdate>,
+        outbox_ids: &mut Vec<i64>,
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) {
+        self.apply_state_updates(store, state_updates, event_handler)
             .await;
-        Ok(())
+        state_updates.clear();
+        // A leftover entry only repeats an op that already succeeded.
+        if let Err(e) = store.outbox_remove(outbox_ids).await {
+            emit_log(event_handler, format!("Outbox update failed: {e}"));
+        }
+        outbox_ids.clear();
+    }
+
+    /// Record `ops` in the outbox before they run. Queued ops writing to the
+    /// same remote paths are dropped, the new ops replace them.
+    async fn park_in_outbox(
+        &self,
+        store: &StateStore,
+        ops: &[PlannedRemoteOp],
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) -> Result<Vec<OutboxSlot>> {
+        let targets = ops
+            .iter()
+            .flat_map(|planned| match &planned.op {
+                RemoteOp::Rename { from, to } => vec![from.clone(), to.clone()],
+                op => vec![remote_op_target(op).to_string()],
+            })
+            .collect::<Vec<_>>();
+        let superseded = store.outbox_supersede(&targets).await?;
+        if superseded > 0 {
+            emit_log(
+                event_handler,
+                format!("Replaced {superseded} queued remote op(s) with newer changes"),
+            );
+        }
+        let entries = ops
+            .iter()
+            .map(|planned| {
+                Ok((
+                    remote_op_target(&planned.op).to_string(),
+                    serde_json::to_string(planned)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let ids = store.outbox_push(&entries).await?;
+        Ok(ids
+            .into_iter()
+            .map(|id| OutboxSlot { id, attempts: 0 })
+            .collect())
+    }
+
+    /// Run the outbox entries that are due. Returns the number of ops left
+    /// queued.
+    async fn replay_outbox(
+        &self,
+        remote: &impl RemoteFs,
+        store: &StateStore,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) -> Result<usize> {
+        let mut ops = Vec::new();
+        let mut outbox = Vec::new();
+        let mut dropped = Vec::new();
+        for row in store.outbox_due(unix_millis()).await? {
+            let planned = match serde_json::from_str::<PlannedRemoteOp>(&row.payload) {
+                Ok(planned) => planned,
+                Err(e) => {
+                    emit_log(
+                        event_handler,
+                        format!("Dropped unreadable outbox entry {}: {e}", row.id),
+                    );
+                    dropped.push(row.id);
+                    continue;
+                }
+            };
+            // The removal of the local file is synced on its own.
+            if let RemoteOp::Upload { local, .. } = &planned.op {
+                if !local.exists() {
+                    emit_log(
+                        event_handler,
+                        format!(
+                            "Dropped queued upload of {}: local file is gone",
+                            display_path(local)
+                        ),
+                    );
+                    dropped.push(row.id);
+                    continue;
+                }
+            }
+            ops.push(planned);
+            outbox.push(OutboxSlot {
+                id: row.id,
+                attempts: row.attempts,
+            });
+        }
+        store.outbox_remove(&dropped).await?;
+        if ops.is_empty() {
+            return Ok(0);
+        }
+        emit_log(
+            event_handler,
+            format!("Retrying {} queued remote op(s)", ops.len()),
+        );
+        let queued = self
+            .apply_remote_ops(remote, &ops, &outbox, store, event_handler, stop_token)
+            .await?;
+        store.flush().await?;
+        Ok(queued)
+    }
+
+    /// Ping the remote. When it does not answer, return the schedule for
+    /// further pings; the caller announces [`TaskState::Offline`].
+    async fn probe_remote(
+        &self,
+        remote: &impl RemoteFs,
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) -> Option<OfflineProbe> {
+        let e = remote.ping().await.err()?;
+        emit_log(
+            event_handler,
+            format!("Remote unreachable, collecting changes until it answers: {e}"),
+        );
+        Some(self.offline_probe(e.to_string()))
+    }
+
+    /// First ping schedule after the remote failed with `reason`.
+    fn offline_probe(&self, reason: String) -> OfflineProbe {
+        let delay = Duration::from_millis(self.cfg.retry_backoff_ms)
+            .clamp(Duration::from_secs(1), OFFLINE_MAX_PROBE_INTERVAL);
+        OfflineProbe::new(reason, delay)
+    }
+
+    /// Publish the outbox size and return a timer for its next due entry.
+    async fn outbox_timer(&self, store: &StateStore) -> Option<std::pin::Pin<Box<Sleep>>> {
+        match store.outbox_len().await {
+            Ok(len) => self.stats.set_outstanding_ops(len),
+            Err(e) => crate::warn!("outbox count error: {e}"),
+        }
+        let next = match store.outbox_next_attempt().await {
+            Ok(next) => next?,
+            Err(e) => {
+                crate::warn!("outbox read error: {e}");
+                return None;
+            }
+        };
+        let delay = Duration::from_millis(next.saturating_sub(unix_millis()));
+        Some(Box::pin(sleep(delay)))
+    }
+
+    /// Delay before retry round `attempts + 1` of an outbox entry.
+    fn outbox_backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u64 << attempts.min(16);
+        Duration::from_millis(self.cfg.retry_backoff_ms.max(1).saturating_mul(factor))
+            .min(OUTBOX_MAX_BACKOFF)
+    }
+
+    async fn apply_op_with_retry(
+        &self,
+        remote: &impl RemoteFs,
+        op: &RemoteOp,
+        progress: TransferProgress,
+        event_handler: &Arc<dyn TaskEventHandler>,
+        stop_token: &CancellationToken,
+    ) -> Result<()> {
+        let mut attempt: u32 = 0;
+        let mut backoff = self.cfg.retry_backoff_ms;
+        let max = self.cfg.retry_max;
+        loop {
+            progress.reset();
+            match remote
+                .apply_op(op.clone(), stop_token.clone(), progress.clone())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= max || stop_token.is_cancelled() => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    self.stats.record_retry();
+                    emit_log(
+                        event_handler,
+                        format!(
+                            "Remote op failed, retry {attempt}/{max}: {}: {e}",
+                            describe_remote_op(op)
+                        ),
+                    );
+                    tokio::select! {
+                        _ = stop_token.cancelled() => return Err(e),
+                        _ = tokio::time::sleep(Duration::from_millis(backoff)) => {}
+                    }
+                    backoff = backoff.saturating_mul(2);
+                }
+            }
+        }
     }
 
     async fn apply_state_updates(
@@ -811,11 +2151,11 @@ impl SyncTask {
         let puts = updates
             .iter()
             .filter_map(|update| match update {
-                StateUpdate::Put(key, ts) => Some((key.clone(), *ts)),
+                StateUpdate::Put(key, state) => Some((key.clone(), state.clone())),
                 StateUpdate::RemoveTree(_) => None,
             })
             .collect::<Vec<_>>();
-        if let Err(e) = store.put_many_u64(&puts).await {
+        if let Err(e) = store.put_many(&puts).await {
             emit_log(event_handler, format!("Cache update failed: {e}"));
         }
 
@@ -832,54 +2172,95 @@ impl SyncTask {
         }
     }
 
+    /// Start the watchers `mode` calls for on the task root. Changes go to
+    /// `op_tx`, lost events and watcher errors to `issue_tx`, and a changed
+    /// ignore file asks `scan_trigger` to rescan what it covers. `mode` must
+    /// already be resolved, not `Auto`.
     fn spawn_watcher(
         &self,
+        mode: WatcherMode,
+        poll_interval: Duration,
+        filter: SharedFilter,
         op_tx: mpsc::UnboundedSender<FsEvent>,
-    ) -> Result<Option<impl FnOnce()>> {
-        let path = self.cfg.local.clone();
-        let filter = self.filter.clone();
-        let mut watcher: RecommendedWatcher = RecommendedWatcher::new(
-            move |res: notify::Result<notify::Event>| match res {
-                Ok(event) => {
-                    tracing::debug!(kind = ?event.kind, paths = ?event.paths, "watch event received");
-                    for op in event_to_ops(event) {
-                        tracing::debug!(op = ?op, "watch event converted");
-                        let pass = match &op {
-                            FsEvent::MkDir(path) => filter.check_dir(path),
-                            FsEvent::Rename(from, to) => {
-                                filter.check(from)
-                                    || filter.check(to)
-                                    || filter.check_dir(from)
-                                    || filter.check_dir(to)
-                            }
-                            _ => filter.check(op.path()),
-                        };
-                        if pass {
-                            let _ = op_tx.send(op);
-                        } else {
-                            tracing::debug!(op = ?op, "watch event ignored by filter");
-                        }
+        issue_tx: mpsc::UnboundedSender<WatchIssue>,
+        scan_trigger: Arc<ScanTrigger>,
+    ) -> Result<(TaskWatcher<impl EventHandler + Clone>, Option<WatchIssue>)> {
+        let root = self.cfg.local.clone();
+        let handler = move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                tracing::debug!(kind = ?event.kind, paths = ?event.paths, "watch event received");
+                if event.need_rescan() {
+                    let _ = issue_tx.send(WatchIssue::Overflow(event.paths));
+                    return;
+                }
+                // Some backends flag lost or coalesced events only this way;
+                // rescan what they point at, or the whole root.
+                if event.paths.is_empty() {
+                    scan_trigger.full();
+                    return;
+                }
+                if matches!(event.kind, EventKind::Any | EventKind::Other) {
+                    scan_trigger.dirty(event.paths);
+                    return;
+                }
+                if event.kind.is_remove()
+                    && event.paths.iter().any(|path| path == &root)
+                    && !root.is_dir()
+                {
+                    let _ = issue_tx.send(WatchIssue::RootRemoved);
+                }
+                let filter = filter.read().unwrap().clone();
+                for path in &event.paths {
+                    if let Some(dir) = filter.reload_ignore_file(path) {
+                        tracing::debug!(path = %display_path(path), "ignore file changed");
+                        scan_trigger.dirty([dir]);
                     }
                 }
-                Err(e) => eprintln!("watch error: {e}"),
-            },
-            notify::Config::default(),
-        )
-        .map_err(|e| anyhow!(e))?;
-        watcher
-            .watch(&path, RecursiveMode::Recursive)
-            .map_err(|e| anyhow!(e))?;
-        // Leak the watcher to keep it alive.
-        // std::mem::forget(watcher);
-        let f = {
-            let path = self.cfg.local.clone();
-            move || {
-                if let Err(e) = watcher.unwatch(&path) {
-                    tracing::error!("{e}");
+                for op in event_to_ops(event) {
+                    tracing::debug!(op = ?op, "watch event converted");
+                    let pass = match &op {
+                        FsEvent::MkDir(path) => filter.check_dir(path),
+                        FsEvent::Rename(from, to) => {
+                            filter.check(from)
+                                || filter.check(to)
+                                || filter.check_dir(from)
+                                || filter.check_dir(to)
+                        }
+                        _ => filter.check(op.path()),
+                    };
+                    if pass {
+                        let _ = op_tx.send(op);
+                    } else {
+                        tracing::debug!(op = ?op, "watch event ignored by filter");
+                    }
                 }
             }
+            Err(e) => {
+                tracing::debug!("watch error: {e}");
+                let _ = issue_tx.send(WatchIssue::from_error(e));
+            }
         };
-        Ok(Some(f))
+        TaskWatcher::start(self.cfg.local.clone(), mode, poll_interval, handler)
+    }
+
+    /// Tell the user about `issue`, unless the same kind of issue was
+    /// reported less than [`WATCH_WARNING_INTERVAL`] ago.
+    fn warn_watch_issue(
+        &self,
+        issue: &WatchIssue,
+        warned: &mut HashMap<std::mem::Discriminant<WatchIssue>, tokio::time::Instant>,
+        event_handler: &Arc<dyn TaskEventHandler>,
+    ) {
+        let now = tokio::time::Instant::now();
+        let last = warned.insert(std::mem::discriminant(issue), now);
+        if last.is_some_and(|last| now - last < WATCH_WARNING_INTERVAL) {
+            return;
+        }
+        let (message, remediation) = issue.warning(&self.cfg.local);
+        event_handler.emit(TaskEvent::Warning(TaskWarning {
+            message,
+            remediation,
+        }));
     }
 }
 
@@ -895,6 +2276,67 @@ impl SyncTask {
         relative_posix_path(local, &self.cfg.local)
     }
 
+    /// Find files recorded in the state store that vanished locally while the
+    /// task was stopped and return the top-most missing path of each.
+    ///
+    /// With [`DeletionPolicy::Propagate`] the keys below those paths are added
+    /// to `live_keys`, so their rows survive the startup cleanup and are only
+    /// dropped once the remote remove succeeds. Keys that still exist locally
+    /// (now filtered out) or that cannot be mapped back to a local path are
+    /// left for the cleanup as before.
+    ///
+    /// Returns `None` without touching any row when the local root is missing
+    /// or empty, which usually means an unmounted volume rather than a
+    /// deliberate wipe.
+    fn detect_offline_deletions(
+        &self,
+        snapshot: &HashMap<String, FileState>,
+        live_keys: &mut HashSet<String>,
+    ) -> Option<Vec<PathBuf>> {
+        if !snapshot.is_empty() && (live_keys.is_empty() || !self.cfg.local.is_dir()) {
+            live_keys.extend(snapshot.keys().cloned());
+            return None;
+        }
+        let mut roots: HashSet<String> = HashSet::new();
+        let mut removed_keys = Vec::new();
+        for key in snapshot.keys() {
+            if live_keys.contains(key) || key.is_empty() || Path::new(key).is_absolute() {
+                continue;
+            }
+            let local = self.cfg.local.join(key);
+            if std::fs::symlink_metadata(&local).is_ok() {
+                continue;
+            }
+            let mut prefix = String::new();
+            for part in key.split('/') {
+                if !prefix.is_empty() {
+                    prefix.push('/');
+                }
+                prefix.push_str(part);
+                if std::fs::symlink_metadata(self.cfg.local.join(&prefix)).is_err() {
+                    break;
+                }
+            }
+            roots.insert(prefix);
+            removed_keys.push(key.clone());
+        }
+        if self.cfg.deletion_policy == DeletionPolicy::Propagate {
+            live_keys.extend(removed_keys);
+        }
+
+        // Roots nested in another root (e.g. `a/b` next to `a`) are covered by it.
+        let deletions = roots
+            .iter()
+            .filter(|root| {
+                !root
+                    .match_indices('/')
+                    .any(|(idx, _)| roots.contains(&root[..idx]))
+            })
+            .map(|rel| self.cfg.local.join(rel))
+            .collect();
+        Some(deletions)
+    }
+
     fn state_key(&self, local: &Path) -> String {
         self.relative_local_path(local)
             .unwrap_or_else(|| normalize_key_path(local))
@@ -905,22 +2347,21 @@ impl SyncTask {
         local: &Path,
         key: &str,
         store: &StateStore,
-    ) -> Option<u64> {
-        let key = key.to_string();
-        if let Ok(Some(ts)) = store.get_u64(&key).await {
-            return Some(ts);
+    ) -> Option<FileState> {
+        if let Ok(Some(state)) = store.get(key).await {
+            return Some(state);
         }
 
         for legacy_key in self.legacy_state_keys(local) {
             if legacy_key == key {
                 continue;
             }
-            let Ok(Some(ts)) = store.get_u64(&legacy_key).await else {
+            let Ok(Some(state)) = store.get(&legacy_key).await else {
                 continue;
             };
-            if store.put_u64(key.clone(), ts).await.is_ok() {
-                let _ = store.remove_u64(&legacy_key).await;
-                return Some(ts);
+            if store.put(key.to_string(), state.clone()).await.is_ok() {
+                let _ = store.remove(&legacy_key).await;
+                return Some(state);
             }
         }
 
@@ -942,53 +2383,35 @@ impl SyncTask {
 }
 
 pub fn spawn_task<R: RemoteFs>(cfg: TaskConfig, remote: R) -> SyncTaskHandle {
-    let (ctrl_tx, ctrl_rx) = mpsc::channel(4);
+    let (ctrl_tx, ctrl_rx) = mpsc::channel(16);
     let (state_tx, state_rx) = watch::channel(TaskState::Starting("Task spawned".into()));
+    let (progress_tx, progress_rx) = watch::channel(None);
+    let (event_tx, _) = broadcast::channel(4_096);
     let (log_tx, _) = broadcast::channel(65_536);
     let initial_log_rx = Mutex::new(Some(log_tx.subscribe()));
     let stop_token = CancellationToken::new();
     let event_handler = Arc::new(BroadcastTaskEventHandler::new(
         state_tx.clone(),
+        progress_tx,
+        event_tx.clone(),
         log_tx.clone(),
     ));
     let task = SyncTask::new(cfg.clone());
+    let stats = task.stats.clone();
     tokio::spawn(task.run(remote, ctrl_rx, event_handler, stop_token.clone()));
     SyncTaskHandle {
-        cfg,
+        cfg: Mutex::new(cfg),
         ctrl_tx,
         state_rx,
+        progress_rx,
+        stats,
+        event_tx,
         log_tx,
         initial_log_rx,
         stop_token,
     }
 }
 
-fn parse_size_filter(input: Option<&str>) -> (Option<u64>, Option<u64>) {
-    if let Some(s) = input {
-        let s = s.trim();
-        if s.is_empty() {
-            return (None, None);
-        }
-        if let Some((a, b)) = s.split_once("..") {
-            let min = if a.is_empty() {
-                None
-            } else {
-                a.parse::<u64>().ok()
-            };
-            let max = if b.is_empty() {
-                None
-            } else {
-                b.parse::<u64>().ok()
-            };
-            return (min, max);
-        }
-        if let Ok(n) = s.parse::<u64>() {
-            return (Some(n), None);
-        }
-    }
-    (None, None)
-}
-
 fn emit_state(event_handler: &Arc<dyn TaskEventHandler>, state: TaskState) {
     event_handler.emit(TaskEvent::State(state));
 }
@@ -1000,6 +2423,10 @@ fn emit_log(event_handler: &Arc<dyn TaskEventHandler>, message: impl Into<String
     }));
 }
 
+fn emit_progress(event_handler: &Arc<dyn TaskEventHandler>, progress: SyncProgress) {
+    event_handler.emit(TaskEvent::Progress(progress));
+}
+
 fn emit_remote_op_applied(
     event_handler: &Arc<dyn TaskEventHandler>,
     op: RemoteOp,
@@ -1083,45 +2510,663 @@ fn describe_remote_op(op: &RemoteOp) -> String {
     }
 }
 
+fn remote_op_target(op: &RemoteOp) -> &str {
+    match op {
+        RemoteOp::Upload { remote, .. }
+        | RemoteOp::Remove { remote }
+        | RemoteOp::MkDir { remote } => remote,
+        RemoteOp::Rename { to, .. } => to,
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_millis() as u64)
+        .unwrap_or_default()
+}
+
 fn display_path(path: &std::path::Path) -> String {
     display_posix_path(path)
 }
 
+/// State of a started task: paused wins over offline.
+fn live_state(paused: bool, offline: &Option<OfflineProbe>) -> TaskState {
+    match offline {
+        _ if paused => TaskState::Paused,
+        Some(probe) => TaskState::Offline(probe.reason.clone()),
+        None => TaskState::Running,
+    }
+}
+
 fn state_label(state: &TaskState) -> String {
     match state {
         TaskState::Idle => "Idle".into(),
         TaskState::Starting(stage) => format!("Starting - {stage}"),
         TaskState::Running => "Running".into(),
+        TaskState::Paused => "Paused".into(),
+        TaskState::Offline(reason) => format!("Offline - {reason}"),
         TaskState::Error(e) => format!("Error - {e}"),
     }
 }
 
-fn should_queue_entry(
+/// Files below `root` admitted by `filter` and `size` that `old_filter` or
+/// `old_size` kept out, either directly or by pruning one of their parents.
+fn newly_included(
+    root: &Path,
+    old_filter: &PathFilter,
+    old_size: (Option<u64>, Option<u64>),
+    filter: &PathFilter,
+    size: (Option<u64>, Option<u64>),
+) -> Vec<PathBuf> {
+    let in_range = |len: u64, (min, max): (Option<u64>, Option<u64>)| {
+        min.is_none_or(|min| len >= min) && max.is_none_or(|max| len <= max)
+    };
+    let mut paths = Vec::new();
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_type().is_dir() || filter.check_dir(entry.path())
+        })
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() || !filter.check(entry.path()) {
+            continue;
+        }
+        let Ok(len) = entry.metadata().map(|meta| meta.len()) else {
+            continue;
+        };
+        if !in_range(len, size) {
+            continue;
+        }
+        let was_included = old_filter.check(entry.path())
+            && in_range(len, old_size)
+            && entry
+                .path()
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| *dir != root)
+                .all(|dir| old_filter.check_dir(dir));
+        if !was_included {
+            paths.push(entry.into_path());
+        }
+    }
+    paths
+}
+
+/// Stat `path` and return it when it passes the size filter and may differ
+/// from the recorded state. Hash comparisons are left to the upload planner.
+fn queue_candidate(
     path: &PathBuf,
     size_min: Option<u64>,
     size_max: Option<u64>,
-    last_mtime: Option<&u64>,
-) -> bool {
-    let Ok(meta) = std::fs::metadata(path) else {
-        return false;
-    };
-    if let Some(min) = size_min {
-        if meta.len() < min {
-            return false;
+    detection: ChangeDetection,
+    last: Option<&FileState>,
+) -> Option<LocalStat> {
+    let meta = std::fs::metadata(path).ok()?;
+    candidate_stat(&meta, size_min, size_max, detection, last)
+}
+
+/// [`queue_candidate`] for a file whose metadata is already known.
+fn candidate_stat(
+    meta: &std::fs::Metadata,
+    size_min: Option<u64>,
+    size_max: Option<u64>,
+    detection: ChangeDetection,
+    last: Option<&FileState>,
+) -> Option<LocalStat> {
+    if size_min.is_some_and(|min| meta.len() < min) || size_max.is_some_and(|max| meta.len() > max)
+    {
+        return None;
+    }
+    let stat = LocalStat::from_metadata(meta)?;
+    (detect_change(detection, &stat, last) != Change::Unchanged).then_some(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::hash_file_sha256;
+    use crate::remote::{fake::FakeRemote, RemoteChecksum};
+
+    struct Events(Mutex<Vec<TaskEvent>>);
+
+    impl TaskEventHandler for Events {
+        fn emit(&self, event: TaskEvent) {
+            self.0.lock().unwrap().push(event);
         }
     }
-    if let Some(max) = size_max {
-        if meta.len() > max {
-            return false;
+
+    fn events() -> Arc<dyn TaskEventHandler> {
+        Arc::new(Events(Mutex::new(Vec::new())))
+    }
+
+    fn now_secs() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn remote_file(path: &str, size: u64, mtime: Option<u64>) -> RemoteEntry {
+        RemoteEntry {
+            path: path.into(),
+            is_dir: false,
+            size,
+            mtime,
         }
     }
 
-    let Ok(modified) = meta.modified() else {
-        return false;
-    };
-    let Ok(dur) = modified.duration_since(UNIX_EPOCH) else {
-        return false;
-    };
-    let mtime = dur.as_secs();
-    last_mtime.copied() != Some(mtime)
+    fn config(local: &Path, patch: impl FnOnce(&mut TaskConfig)) -> TaskConfig {
+        let mut cfg: TaskConfig = serde_json::from_value(serde_json::json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "test",
+            "local": local,
+            "remote": "/srv",
+            "remote_cfg": { "type": "sftp", "host": "h", "user": "u", "password": null, "key": null },
+        }))
+        .unwrap();
+        patch(&mut cfg);
+        cfg
+    }
+
+    fn task(local: &Path, patch: impl FnOnce(&mut TaskConfig)) -> SyncTask {
+        SyncTask::new(config(local, patch))
+    }
+
+    async fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(20);
+        while !done() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "timed out waiting for {what}"
+            );
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    async fn store(dir: &Path) -> StateStore {
+        StateStore::open(0, &dir.join(".cache")).await.unwrap()
+    }
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fsync-{label}-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_only_stale_temp_files() {
+        let dir = temp_dir("sweep");
+        let task = task(&dir, |cfg| cfg.stale_temp_secs = 3_600);
+        let old = Some(now_secs() - 7_200);
+        let remote = FakeRemote::with_files([
+            remote_file("/srv/a.txt.fsync.tmp", 1, old),
+            remote_file("/srv/sub/b.bin.fsync.tmp", 1, old),
+            remote_file("/srv/fresh.txt.fsync.tmp", 1, Some(now_secs() - 60)),
+            remote_file("/srv/unknown.txt.fsync.tmp", 1, None),
+            remote_file("/srv/a.txt", 1, old),
+            remote_file("/srv/notes.fsync.tmp.txt", 1, old),
+            RemoteEntry {
+                path: "/srv/dir.fsync.tmp".into(),
+                is_dir: true,
+                size: 0,
+                mtime: old,
+            },
+            remote_file("/elsewhere/c.fsync.tmp", 1, old),
+        ]);
+
+        task.sweep_stale_temp_files(&remote, &events(), &CancellationToken::new())
+            .await;
+
+        assert_eq!(
+            remote.paths(),
+            [
+                "/elsewhere/c.fsync.tmp",
+                "/srv/a.txt",
+                "/srv/dir.fsync.tmp",
+                "/srv/fresh.txt.fsync.tmp",
+                "/srv/notes.fsync.tmp.txt",
+                "/srv/unknown.txt.fsync.tmp",
+            ]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    fn keys(keys: &[&str]) -> HashSet<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn offline_deletions_report_top_most_missing_paths() {
+        let dir = temp_dir("offline-deletions");
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        std::fs::write(dir.join("keep.txt"), "k").unwrap();
+        std::fs::write(dir.join("dir/b"), "b").unwrap();
+        let snapshot = [
+            "keep.txt",
+            "gone.txt",
+            "gonedir/x",
+            "gonedir/y/z",
+            "dir/b",
+            "dir/c",
+        ]
+        .into_iter()
+        .map(|key| (key.to_string(), FileState::default()))
+        .collect::<HashMap<_, _>>();
+
+        let propagate = task(&dir, |_| {});
+        let mut live = keys(&["keep.txt", "dir/b"]);
+        let mut found = propagate
+            .detect_offline_deletions(&snapshot, &mut live)
+            .unwrap();
+        found.sort();
+        let root = propagate.cfg.local.clone();
+        assert_eq!(
+            found,
+            [
+                root.join("dir/c"),
+                root.join("gone.txt"),
+                root.join("gonedir")
+            ]
+        );
+        // The removed rows stay until the remote remove succeeds.
+        assert_eq!(live, snapshot.keys().cloned().collect());
+
+        let keep = task(&dir, |cfg| cfg.deletion_policy = DeletionPolicy::Keep);
+        let mut live = keys(&["keep.txt", "dir/b"]);
+        assert_eq!(
+            keep.detect_offline_deletions(&snapshot, &mut live)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(live, keys(&["keep.txt", "dir/b"]));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn offline_deletions_skip_an_empty_or_missing_root() {
+        let dir = temp_dir("offline-deletions-guard");
+        let snapshot = ["a.txt", "sub/b.txt"]
+            .into_iter()
+            .map(|key| (key.to_string(), FileState::default()))
+            .collect::<HashMap<_, _>>();
+
+        // Nothing walked: an unmounted volume looks like this.
+        let empty = task(&dir, |_| {});
+        let mut live = HashSet::new();
+        assert_eq!(empty.detect_offline_deletions(&snapshot, &mut live), None);
+        assert_eq!(live, keys(&["a.txt", "sub/b.txt"]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let missing = task(&dir, |_| {});
+        let mut live = keys(&["a.txt"]);
+        assert_eq!(missing.detect_offline_deletions(&snapshot, &mut live), None);
+        assert_eq!(live, keys(&["a.txt", "sub/b.txt"]));
+    }
+
+    #[tokio::test]
+    async fn adoption_compares_checksums_of_same_size_files() {
+        let dir = temp_dir("adopt");
+        for (name, content) in [
+            ("same.txt", "hello"),
+            ("sha.txt", "sha-2"),
+            ("changed.txt", "world"),
+            ("resized.txt", "grown!"),
+            ("unhashed.txt", "nohash"),
+            ("new.txt", "fresh"),
+        ] {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let adopt = task(&dir, |cfg| cfg.initial_sync = InitialSync::AdoptChecksum);
+        let root = adopt.cfg.local.clone();
+        let remote = FakeRemote::with_files([
+            remote_file("/srv/same.txt", 5, Some(0)),
+            remote_file("/srv/sha.txt", 5, Some(0)),
+            remote_file("/srv/changed.txt", 5, Some(0)),
+            remote_file("/srv/resized.txt", 5, Some(0)),
+            remote_file("/srv/unhashed.txt", 6, Some(0)),
+        ]);
+        let hash = |name: &str| hash_file(&root.join(name)).unwrap();
+        remote.checksums.lock().unwrap().extend([
+            (
+                "/srv/same.txt".into(),
+                RemoteChecksum::Blake3(hash("same.txt")),
+            ),
+            (
+                "/srv/sha.txt".into(),
+                RemoteChecksum::Sha256(hash_file_sha256(&root.join("sha.txt")).unwrap()),
+            ),
+            (
+                "/srv/changed.txt".into(),
+                RemoteChecksum::Blake3(hash("same.txt")),
+            ),
+            (
+                "/srv/resized.txt".into(),
+                RemoteChecksum::Blake3(hash("resized.txt")),
+            ),
+        ]);
+        let listing = adopt.remote_listing(&remote).await.unwrap();
+        let files = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_file())
+            .map(|entry| {
+                let stat = LocalStat::from_metadata(&entry.metadata().unwrap()).unwrap();
+                (entry.path(), stat)
+            })
+            .collect();
+        let store = store(&dir).await;
+
+        let (adopted, checksum_errors) = adopt
+            .adopt_remote_matches(
+                &remote,
+                &store,
+                &listing,
+                files,
+                &events(),
+                &CancellationToken::new(),
+            )
+            .await;
+
+        let mut adopted = adopted.into_iter().collect::<Vec<_>>();
+        adopted.sort();
+        assert_eq!(adopted, [root.join("same.txt"), root.join("sha.txt")]);
+        assert_eq!(checksum_errors, 1);
+        let state = store.get("same.txt").await.unwrap().unwrap();
+        assert_eq!(state.hash, Some(hash("same.txt")));
+        assert_eq!(store.get("changed.txt").await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn late_high_priority_files_overtake_the_backlog() {
+        let dir = temp_dir("backlog");
+        // sizes decide the order of equal-priority files
+        for (name, content) in [
+            ("a.bin", "a"),
+            ("b.bin", "bb"),
+            ("c.bin", "ccc"),
+            ("urgent.txt", "urgent"),
+        ] {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let ranked = task(&dir, |cfg| {
+            cfg.priorities = vec![crate::config::PriorityRule {
+                pattern: crate::config::Pattern("*.txt".into()),
+                priority: 10,
+            }]
+        });
+        let root = ranked.cfg.local.clone();
+        let walked = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| WalkedFile {
+                    path: root.join(name),
+                    meta: std::fs::metadata(root.join(name)).unwrap(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let store = store(&dir).await;
+        let (_found_tx, found) = mpsc::channel(1);
+        let mut scan = InitialScan::new(found, HashMap::new());
+        let mut backlog = Backlog::default();
+        let mut plan = async |files| {
+            ranked
+                .plan_walked_files(
+                    files,
+                    &mut scan,
+                    None::<&FakeRemote>,
+                    &store,
+                    &events(),
+                    &CancellationToken::new(),
+                )
+                .await
+        };
+
+        backlog.queue_uploads(plan(walked(&["a.bin", "b.bin", "c.bin"])).await);
+        let first = backlog.next_chunk(1);
+        backlog.queue_uploads(plan(walked(&["urgent.txt"])).await);
+        backlog
+            .removals
+            .push_back(FsEvent::Remove(root.join("gone.txt")));
+        let rest = backlog.next_chunk(INITIAL_SYNC_CHUNK);
+
+        assert_eq!(first, [FsEvent::Modify(root.join("a.bin"))]);
+        assert_eq!(
+            rest,
+            [
+                FsEvent::Modify(root.join("urgent.txt")),
+                FsEvent::Modify(root.join("b.bin")),
+                FsEvent::Modify(root.join("c.bin")),
+                FsEvent::Remove(root.join("gone.txt")),
+            ]
+        );
+        assert!(backlog.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn untracked_files_wait_for_an_offline_remote() {
+        let dir = temp_dir("offline-start");
+        let root = dir.join("local");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("same.txt"), "hello").unwrap();
+        std::fs::write(root.join("new.txt"), "fresh").unwrap();
+        let remote =
+            FakeRemote::with_files([remote_file("/srv/same.txt", 5, Some(now_secs() + 3_600))]);
+        remote.set_online(false);
+        let cfg = config(&root, |cfg| {
+            cfg.initial_sync = InitialSync::Adopt;
+            cfg.cache_dir = Some(dir.join("cache"));
+            cfg.debounce_ms = 10;
+        });
+        let handle = spawn_task(cfg, remote.clone());
+        let mut logs = handle.subscribe_logs();
+
+        wait_until("the task to go offline", || {
+            matches!(*handle.state(), TaskState::Offline(_))
+        })
+        .await;
+        // Let the startup walk finish, then change something while offline.
+        sleep(Duration::from_millis(300)).await;
+        std::fs::write(root.join("late.txt"), "later").unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert!(remote.uploads.lock().unwrap().is_empty());
+        remote.set_online(true);
+
+        wait_until("the held back uploads", || remote.paths().len() == 3).await;
+        let mut uploads = remote.uploads.lock().unwrap().clone();
+        uploads.sort();
+        uploads.dedup();
+        assert_eq!(uploads, ["/srv/late.txt", "/srv/new.txt"]);
+        let mut messages = Vec::new();
+        while let Ok(log) = logs.try_recv() {
+            messages.push(log.message);
+        }
+        assert!(
+            messages
+                .iter()
+                .any(|m| m.contains("collected while offline")),
+            "{messages:#?}"
+        );
+        assert!(
+            messages
+                .iter()
+                .any(|m| m == "Adopted 1 file(s) already on the remote"),
+            "{messages:#?}"
+        );
+        handle.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changes_made_while_paused_are_sent_together_on_resume() {
+        let dir = temp_dir("pause");
+        let root = dir.join("local");
+        std::fs::create_dir_all(&root).unwrap();
+        let remote = FakeRemote::default();
+        let cfg = config(&root, |cfg| {
+            cfg.cache_dir = Some(dir.join("cache"));
+            cfg.debounce_ms = 10;
+        });
+        let handle = spawn_task(cfg, remote.clone());
+        let mut logs = handle.subscribe_logs();
+        wait_until("the task to run", || {
+            matches!(*handle.state(), TaskState::Running)
+        })
+        .await;
+
+        handle.pause();
+        wait_until("the task to pause", || {
+            matches!(*handle.state(), TaskState::Paused)
+        })
+        .await;
+        std::fs::write(root.join("a.txt"), "first").unwrap();
+        sleep(Duration::from_millis(300)).await;
+        std::fs::write(root.join("a.txt"), "second version").unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert!(remote.paths().is_empty());
+        assert!(remote.uploads.lock().unwrap().is_empty());
+
+        handle.resume();
+        wait_until("the upload", || !remote.uploads.lock().unwrap().is_empty()).await;
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(*remote.uploads.lock().unwrap(), ["/srv/a.txt"]);
+        assert_eq!(remote.files.lock().unwrap()["/srv/a.txt"].size, 14);
+        let mut messages = Vec::new();
+        while let Ok(log) = logs.try_recv() {
+            messages.push(log.message);
+        }
+        assert!(
+            messages
+                .iter()
+                .any(|m| m == "Resuming with 1 pending change(s)"),
+            "{messages:#?}"
+        );
+        handle.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn resync_clears_only_the_state_below_its_path() {
+        let dir = temp_dir("resync-state");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let resync = task(&dir, |_| {});
+        let root = resync.cfg.local.clone();
+        let store = store(&dir).await;
+        for key in ["sub/a.txt", "sub/deep/b.txt", "subway.txt", "top.txt"] {
+            store.put(key.into(), FileState::default()).await.unwrap();
+        }
+        let outside = temp_dir("resync-outside");
+        let mut batch = Vec::new();
+
+        let queued = resync
+            .queue_resync(
+                vec!["./sub".into(), outside.clone(), "../elsewhere".into()],
+                &store,
+                &events(),
+                &mut batch,
+            )
+            .await;
+
+        assert_eq!(queued, 1);
+        assert_eq!(batch, [FsEvent::Modify(root.join("sub"))]);
+        let mut left = store
+            .load_all()
+            .await
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["subway.txt", "top.txt"]);
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_dir_all(outside);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rescan_skips_synced_files_and_resync_uploads_them_again() {
+        let dir = temp_dir("resync");
+        let root = dir.join("local");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        for name in ["sub/a.txt", "sub/b.txt", "top.txt"] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+        let remote = FakeRemote::default();
+        let cfg = config(&root, |cfg| {
+            cfg.cache_dir = Some(dir.join("cache"));
+            cfg.debounce_ms = 10;
+        });
+        let handle = spawn_task(cfg, remote.clone());
+        let mut events = handle.subscribe_events();
+        let mut full_scans = Vec::new();
+        let mut next_full_scan = |full_scans: &mut Vec<ScanReport>| {
+            while let Ok(event) = events.try_recv() {
+                if let TaskEvent::Scan(report) = event {
+                    if report.full {
+                        full_scans.push(report);
+                    }
+                }
+            }
+            !full_scans.is_empty()
+        };
+        wait_until("the initial sync", || {
+            remote.uploads.lock().unwrap().len() == 3
+        })
+        .await;
+        wait_until("the first scan", || next_full_scan(&mut full_scans)).await;
+        remote.uploads.lock().unwrap().clear();
+        full_scans.clear();
+
+        handle.rescan();
+        wait_until("the rescan", || next_full_scan(&mut full_scans)).await;
+        assert_eq!(full_scans[0].queued, 0);
+        sleep(Duration::from_millis(300)).await;
+        assert!(remote.uploads.lock().unwrap().is_empty());
+
+        handle.resync(vec!["sub".into()]);
+        wait_until("the resync", || remote.uploads.lock().unwrap().len() >= 2).await;
+        sleep(Duration::from_millis(300)).await;
+        let mut uploads = remote.uploads.lock().unwrap().clone();
+        uploads.sort();
+        assert_eq!(uploads, ["/srv/sub/a.txt", "/srv/sub/b.txt"]);
+        handle.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn widened_filters_queue_newly_included_files_and_keep_stats() {
+        let dir = temp_dir("reload");
+        let root = dir.join("local");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "text").unwrap();
+        std::fs::write(root.join("b.log"), "log").unwrap();
+        let remote = FakeRemote::default();
+        let cfg = config(&root, |cfg| {
+            cfg.cache_dir = Some(dir.join("cache"));
+            cfg.debounce_ms = 10;
+            cfg.filters = vec![crate::config::Pattern("*.log".into())];
+        });
+        let handle = spawn_task(cfg, remote.clone());
+        wait_until("the initial sync", || handle.stats().files_uploaded == 1).await;
+        assert_eq!(*remote.uploads.lock().unwrap(), ["/srv/a.txt"]);
+
+        let mut widened = handle.config();
+        widened.filters.clear();
+        handle.update_config(widened).unwrap();
+
+        wait_until("the newly included file", || {
+            handle.stats().files_uploaded == 2
+        })
+        .await;
+        assert_eq!(
+            *remote.uploads.lock().unwrap(),
+            ["/srv/a.txt", "/srv/b.log"]
+        );
+        assert_eq!(handle.stats().bytes_sent, 7);
+        assert!(handle.config().filters.is_empty());
+        handle.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
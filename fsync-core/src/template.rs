@@ -0,0 +1,179 @@
+//! Variable expansion for remote paths and remote profile fields.
+//!
+//! Templates use `{name}` placeholders. Supported names are `user`,
+//! `hostname`, `task_name`, `task_id`, `date` (UTC, `YYYY-MM-DD`) and
+//! `env:NAME` for environment variables. `{{` and `}}` produce literal braces.
+
+use anyhow::{anyhow, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values available to a template, resolved lazily so that e.g. a missing
+/// hostname only fails templates that actually reference `{hostname}`.
+#[derive(Debug, Clone)]
+pub struct TemplateContext {
+    pub task_name: String,
+    pub task_id: String,
+}
+
+impl TemplateContext {
+    fn lookup(&self, name: &str) -> Result<String> {
+        if let Some(var) = name.strip_prefix("env:") {
+            if var.is_empty() {
+                return Err(anyhow!("empty environment variable name in `{{{name}}}`"));
+            }
+            return std::env::var(var)
+                .map_err(|_| anyhow!("environment variable `{var}` is not set"));
+        }
+        match name {
+            "user" => local_user(),
+            "hostname" => local_hostname(),
+            "task_name" => Ok(self.task_name.clone()),
+            "task_id" => Ok(self.task_id.clone()),
+            "date" => Ok(utc_date(SystemTime::now())),
+            _ => Err(anyhow!("unknown template variable `{{{name}}}`")),
+        }
+    }
+}
+
+/// Expand every placeholder in `input`, failing on unknown or unresolved ones.
+/// Errors give the position of the problem, never the input itself, which
+/// may be sensitive.
+pub fn expand_template(input: &str, ctx: &TemplateContext) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.char_indices().peekable();
+    while let Some((at, ch)) = chars.next() {
+        match ch {
+            '{' if chars.next_if(|(_, next)| *next == '{').is_some() => output.push('{'),
+            '}' if chars.next_if(|(_, next)| *next == '}').is_some() => output.push('}'),
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for (_, ch) in chars.by_ref() {
+                    if ch == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(ch);
+                }
+                if !closed {
+                    return Err(anyhow!(
+                        "unterminated template variable at character {}",
+                        input[..at].chars().count() + 1
+                    ));
+                }
+                output.push_str(&ctx.lookup(name.trim())?);
+            }
+            '}' => {
+                return Err(anyhow!(
+                    "unmatched `}}` at character {}",
+                    input[..at].chars().count() + 1
+                ))
+            }
+            _ => output.push(ch),
+        }
+    }
+    Ok(output)
+}
+
+fn local_user() -> Result<String> {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .ok_or_else(|| anyhow!("cannot determine local user for `{{user}}`"))
+}
+
+fn local_hostname() -> Result<String> {
+    if let Some(name) = ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+    {
+        return Ok(name);
+    }
+    for path in ["/etc/hostname", "/proc/sys/kernel/hostname"] {
+        if let Ok(name) = std::fs::read_to_string(path) {
+            let name = name.trim();
+            if !name.is_empty() {
+                return Ok(name.to_string());
+            }
+        }
+    }
+    Err(anyhow!(
+        "cannot determine local hostname for `{{hostname}}`"
+    ))
+}
+
+fn utc_date(now: SystemTime) -> String {
+    let days = now
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ctx() -> TemplateContext {
+        TemplateContext {
+            task_name: "project".into(),
+            task_id: "42".into(),
+        }
+    }
+
+    #[test]
+    fn expands_task_variables() {
+        assert_eq!(
+            expand_template("/srv/{task_name}/{task_id}", &ctx()).unwrap(),
+            "/srv/project/42"
+        );
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        std::env::set_var("FSYNC_TEMPLATE_TEST_ROOT", "/srv/dev");
+        assert_eq!(
+            expand_template("{env:FSYNC_TEMPLATE_TEST_ROOT}/x", &ctx()).unwrap(),
+            "/srv/dev/x"
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(expand_template("a{{b}}c", &ctx()).unwrap(), "a{b}c");
+    }
+
+    #[test]
+    fn rejects_unknown_and_unterminated_variables() {
+        assert!(expand_template("/srv/{nope}", &ctx()).is_err());
+        assert!(expand_template("/srv/{user", &ctx()).is_err());
+        assert!(expand_template("/srv/user}", &ctx()).is_err());
+        assert!(expand_template("{env:FSYNC_TEMPLATE_TEST_UNSET}", &ctx()).is_err());
+    }
+
+    #[test]
+    fn errors_do_not_echo_the_input() {
+        let error = expand_template("s3cr{t", &ctx()).unwrap_err().to_string();
+        assert_eq!(error, "unterminated template variable at character 5");
+        let error = expand_template("s3cr}t", &ctx()).unwrap_err().to_string();
+        assert!(!error.contains("s3cr"));
+    }
+
+    #[test]
+    fn formats_utc_date() {
+        let ts = UNIX_EPOCH + Duration::from_secs(1_709_164_800); // 2024-02-29
+        assert_eq!(utc_date(ts), "2024-02-29");
+        assert_eq!(utc_date(UNIX_EPOCH), "1970-01-01");
+    }
+}
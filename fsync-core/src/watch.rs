@@ -0,0 +1,431 @@
+//! Choice of change watcher for a task root.
+//!
+//! Native notifications only see changes made through the local kernel, so
+//! on network and FUSE mounts they stay silent about edits made by other
+//! clients (and, for some FUSE filesystems, about local ones too). Those
+//! roots are polled instead when the task leaves the mode on `auto`.
+//!
+//! Watchers can also lose events: the inotify queue overflows, the watch
+//! limit runs out, or the root is deleted and recreated under them. Those
+//! are reported as [`WatchIssue`]s so the task can rescan what was missed
+//! and watch again.
+
+use crate::config::WatcherMode;
+use anyhow::{anyhow, Result};
+use notify::{ErrorKind, EventHandler, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Filesystem types whose native notifications cannot be trusted.
+const UNRELIABLE_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb",
+    "smb2",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "vboxsf",
+    "vmhgfs",
+    "virtiofs",
+    "lustre",
+    "gpfs",
+    "beegfs",
+];
+
+/// Watcher mode to use for `root`, with the reason `auto` picked polling.
+pub(crate) fn resolve_mode(mode: WatcherMode, root: &Path) -> (WatcherMode, Option<String>) {
+    if mode != WatcherMode::Auto {
+        return (mode, None);
+    }
+    match unreliable_fs(root) {
+        Some(fs_type) => (WatcherMode::Poll, Some(fs_type)),
+        None => (WatcherMode::Native, None),
+    }
+}
+
+/// Type of the filesystem holding `root` when native events do not work on
+/// it, or `None` when they do or it cannot be told.
+#[cfg(target_os = "linux")]
+fn unreliable_fs(root: &Path) -> Option<String> {
+    let root = std::fs::canonicalize(root).ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    let fs_type = mount_fs_type(&mounts, &root)?;
+    is_unreliable(&fs_type).then_some(fs_type)
+}
+
+#[cfg(windows)]
+fn unreliable_fs(root: &Path) -> Option<String> {
+    use std::path::{Component, Prefix};
+    let root = std::fs::canonicalize(root).ok()?;
+    match root.components().next()? {
+        Component::Prefix(prefix)
+            if matches!(prefix.kind(), Prefix::UNC(..) | Prefix::VerbatimUNC(..)) =>
+        {
+            Some("network share".into())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn unreliable_fs(_root: &Path) -> Option<String> {
+    None
+}
+
+fn is_unreliable(fs_type: &str) -> bool {
+    UNRELIABLE_FS_TYPES.contains(&fs_type) || fs_type == "fuse" || fs_type.starts_with("fuse.")
+}
+
+/// Filesystem type of the deepest mount in `mounts` (`/proc/self/mounts`
+/// format) that contains `path`.
+#[cfg(any(target_os = "linux", test))]
+fn mount_fs_type(mounts: &str, path: &Path) -> Option<String> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = unescape_mount_field(fields.next()?);
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Undo the octal escapes (`\040` for a space, ...) of a mounts field.
+#[cfg(any(target_os = "linux", test))]
+fn unescape_mount_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok());
+        match code {
+            Some(code) => {
+                out.push(code);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Something that made the watcher miss changes.
+#[derive(Debug)]
+pub(crate) enum WatchIssue {
+    /// The event queue overflowed; `paths` is where events were lost, empty
+    /// when that is unknown
+    Overflow(Vec<PathBuf>),
+    /// No more native watches can be added; `paths` are left unwatched
+    WatchLimit(Vec<PathBuf>),
+    /// The task root was deleted
+    RootRemoved,
+    Error(String, Vec<PathBuf>),
+}
+
+impl WatchIssue {
+    pub fn from_error(error: notify::Error) -> Self {
+        if is_watch_limit(&error) {
+            return WatchIssue::WatchLimit(error.paths);
+        }
+        let message = notify::Error::new(error.kind).to_string();
+        WatchIssue::Error(message, error.paths)
+    }
+
+    /// Paths to scan again, or `None` for the whole tree.
+    pub fn rescan_paths(&self) -> Option<&[PathBuf]> {
+        match self {
+            WatchIssue::Overflow(paths)
+            | WatchIssue::WatchLimit(paths)
+            | WatchIssue::Error(_, paths)
+                if !paths.is_empty() =>
+            {
+                Some(paths)
+            }
+            _ => None,
+        }
+    }
+
+    /// What happened and what the user can do about it.
+    pub fn warning(&self, root: &Path) -> (String, String) {
+        match self {
+            WatchIssue::Overflow(_) => (
+                "The watcher's event queue overflowed and changes were lost; rescanning".into(),
+                "Raise fs.inotify.max_queued_events (sysctl) if this keeps happening, \
+                 or exclude directories with heavy churn such as build output"
+                    .into(),
+            ),
+            WatchIssue::WatchLimit(paths) => (
+                format!(
+                    "The native watch limit is reached, {} director{} not watched; \
+                     polling the tree as well",
+                    paths.len().max(1),
+                    if paths.len() > 1 { "ies are" } else { "y is" }
+                ),
+                "Raise fs.inotify.max_user_watches (e.g. `sysctl fs.inotify.max_user_watches=524288`), \
+                 exclude large directories, or set the watcher mode to `poll`"
+                    .into(),
+            ),
+            WatchIssue::RootRemoved => (
+                format!("{} was removed; watching resumes once it exists again", root.display()),
+                "Recreate the directory, or stop the task if it moved for good".into(),
+            ),
+            WatchIssue::Error(message, _) => (
+                format!("Watcher error: {message}; rescanning"),
+                "Check that the directory is still readable; set the watcher mode to `poll` \
+                 if the filesystem does not support change notifications"
+                    .into(),
+            ),
+        }
+    }
+}
+
+/// `ENOSPC` from `inotify_add_watch`, which is how some kernels report
+/// that `max_user_watches` is exhausted.
+#[cfg(target_os = "linux")]
+const ENOSPC: i32 = 28;
+
+fn is_watch_limit(error: &notify::Error) -> bool {
+    match &error.kind {
+        ErrorKind::MaxFilesWatch => true,
+        #[cfg(target_os = "linux")]
+        ErrorKind::Io(e) => e.raw_os_error() == Some(ENOSPC),
+        _ => false,
+    }
+}
+
+/// The watchers of one task root.
+pub(crate) struct TaskWatcher<H> {
+    root: PathBuf,
+    handler: H,
+    poll_interval: Duration,
+    watchers: Vec<Box<dyn Watcher + Send>>,
+    /// Set once the tree is polled
+    poller: Option<Arc<Mutex<Poller>>>,
+}
+
+/// Poll watcher of a [`TaskWatcher`], which shows up once its first walk is
+/// done.
+#[derive(Default)]
+struct Poller {
+    watcher: Option<PollWatcher>,
+    /// A poll watcher that finishes starting after this is dropped
+    stopped: bool,
+}
+
+impl Poller {
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.watcher = None;
+    }
+}
+
+impl<H: EventHandler + Clone> TaskWatcher<H> {
+    /// Watch `root` as `mode` says; `mode` must already be resolved, not
+    /// `Auto`. When the native watch limit runs out, the tree is polled as
+    /// well and the issue is returned.
+    pub fn start(
+        root: PathBuf,
+        mode: WatcherMode,
+        poll_interval: Duration,
+        handler: H,
+    ) -> Result<(Self, Option<WatchIssue>)> {
+        let mut watcher = Self {
+            root,
+            handler,
+            poll_interval,
+            watchers: Vec::new(),
+            poller: None,
+        };
+        let mut issue = None;
+        if matches!(mode, WatcherMode::Native | WatcherMode::Hybrid) {
+            let mut native = RecommendedWatcher::new(watcher.handler.clone(), Default::default())
+                .map_err(|e| anyhow!(e))?;
+            if let Err(e) = native.watch(&watcher.root, RecursiveMode::Recursive) {
+                if !is_watch_limit(&e) {
+                    return Err(anyhow!(e));
+                }
+                issue = Some(WatchIssue::WatchLimit(e.paths));
+            }
+            // Keep the watches that were added before the limit hit.
+            watcher.watchers.push(Box::new(native));
+        }
+        if matches!(mode, WatcherMode::Poll | WatcherMode::Hybrid) || issue.is_some() {
+            watcher.add_polling();
+        }
+        Ok((watcher, issue))
+    }
+
+    /// Poll the tree in addition to the native watches. Returns `false`
+    /// when it was polled already.
+    pub fn add_polling(&mut self) -> bool {
+        if self.poller.is_some() {
+            return false;
+        }
+        self.poller = Some(self.spawn_poller());
+        true
+    }
+
+    /// Start a poll watcher on a blocking thread: it stats every file under
+    /// the root before it returns. Errors go to the event handler.
+    fn spawn_poller(&self) -> Arc<Mutex<Poller>> {
+        let poller = Arc::new(Mutex::new(Poller::default()));
+        let slot = poller.clone();
+        let root = self.root.clone();
+        let mut handler = self.handler.clone();
+        let config = notify::Config::default().with_poll_interval(self.poll_interval);
+        tokio::task::spawn_blocking(move || {
+            let started = PollWatcher::new(handler.clone(), config).and_then(|mut watcher| {
+                watcher.watch(&root, RecursiveMode::Recursive)?;
+                Ok(watcher)
+            });
+            match started {
+                Ok(watcher) => {
+                    let mut slot = slot.lock().unwrap();
+                    if !slot.stopped {
+                        slot.watcher = Some(watcher);
+                    }
+                }
+                Err(e) => handler.handle_event(Err(e)),
+            }
+        });
+        poller
+    }
+
+    /// Watch the root again, after it was removed and recreated.
+    pub fn rewatch(&mut self) -> Result<()> {
+        for watcher in &mut self.watchers {
+            let _ = watcher.unwatch(&self.root);
+            watcher
+                .watch(&self.root, RecursiveMode::Recursive)
+                .map_err(|e| anyhow!(e))?;
+        }
+        if let Some(poller) = self.poller.take() {
+            poller.lock().unwrap().stop();
+            self.poller = Some(self.spawn_poller());
+        }
+        Ok(())
+    }
+
+    pub fn stop(mut self) {
+        for watcher in &mut self.watchers {
+            if let Err(e) = watcher.unwatch(&self.root) {
+                tracing::debug!("unwatch: {e}");
+            }
+        }
+        if let Some(poller) = self.poller {
+            poller.lock().unwrap().stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deepest_mount_decides_the_filesystem() {
+        let mounts = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+server:/export /mnt/nfs nfs4 rw,vers=4.2 0 0
+/dev/sdb1 /mnt/nfs/local\\040disk xfs rw 0 0
+user@host:/srv /home/me/remote fuse.sshfs rw,nosuid 0 0
+";
+        let fs_type = |path: &str| mount_fs_type(mounts, Path::new(path)).unwrap();
+        assert_eq!(fs_type("/home/me/docs"), "ext4");
+        assert_eq!(fs_type("/mnt/nfs/projects"), "nfs4");
+        assert_eq!(fs_type("/mnt/nfs/local disk/a"), "xfs");
+        assert_eq!(fs_type("/mnt/nfsother"), "ext4");
+        assert_eq!(fs_type("/home/me/remote/x"), "fuse.sshfs");
+
+        assert!(is_unreliable("nfs4"));
+        assert!(is_unreliable("fuse.sshfs"));
+        assert!(!is_unreliable("ext4"));
+        assert!(!is_unreliable("fusectl"));
+    }
+
+    #[test]
+    fn watch_limit_errors_are_recognised() {
+        let limit = notify::Error::new(ErrorKind::MaxFilesWatch).add_path("/data/deep".into());
+        let issue = WatchIssue::from_error(limit);
+        assert!(matches!(issue, WatchIssue::WatchLimit(_)));
+        assert_eq!(
+            issue.rescan_paths(),
+            Some(&[PathBuf::from("/data/deep")][..])
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            let io = notify::Error::io(std::io::Error::from_raw_os_error(ENOSPC));
+            assert!(matches!(
+                WatchIssue::from_error(io),
+                WatchIssue::WatchLimit(_)
+            ));
+        }
+        let gone = notify::Error::path_not_found();
+        let issue = WatchIssue::from_error(gone);
+        assert!(matches!(issue, WatchIssue::Error(..)));
+        assert_eq!(issue.rescan_paths(), None);
+    }
+
+    #[tokio::test]
+    async fn polling_starts_in_the_background() {
+        let root = std::env::temp_dir().join(format!("fsync-poll-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let handler = move |res: notify::Result<notify::Event>| {
+            let _ = tx.send(res);
+        };
+        let (mut watcher, issue) = TaskWatcher::start(
+            root.clone(),
+            WatcherMode::Poll,
+            Duration::from_millis(50),
+            handler,
+        )
+        .unwrap();
+        assert!(issue.is_none());
+        assert!(!watcher.add_polling());
+        let poller = watcher.poller.clone().unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(20);
+        while poller.lock().unwrap().watcher.is_none() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "poller never started"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        std::fs::write(root.join("new.txt"), "x").unwrap();
+        // the root's own mtime change may be reported first
+        let created = tokio::time::timeout(Duration::from_secs(20), async {
+            while let Some(event) = rx.recv().await {
+                if event.unwrap().paths == [root.join("new.txt")] {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(created, Ok(true));
+
+        watcher.stop();
+        assert!(poller.lock().unwrap().watcher.is_none());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
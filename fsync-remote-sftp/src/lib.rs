@@ -1,26 +1,88 @@
+mod reconnect;
 mod ssh_client;
 mod utils;
 
 use crate::utils::{create_dir_all, remove_dir_all};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use fsync_core::{RemoteFs, RemoteOp};
-use russh::client::AuthResult;
+use fsync_core::{
+    RemoteAttrRules, RemoteChecksum, RemoteEntry, RemoteFs, RemoteOp, TransferProgress,
+    UPLOAD_TEMP_SUFFIX,
+};
+use russh::client::{AuthResult, Handle};
+use russh::ChannelMsg;
 use russh_sftp::client::error::Error as SftpError;
 use russh_sftp::client::SftpSession;
-use russh_sftp::protocol::StatusCode;
+use russh_sftp::protocol::{FileAttributes, StatusCode};
 use ssh_client::Client;
 use std::collections::HashSet;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OnceCell};
 use tokio_util::sync::CancellationToken;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Exit status of a shell command that was not found.
+const COMMAND_NOT_FOUND: u32 = 127;
+
+/// Server-side hash commands, in order of preference.
+#[derive(Debug, Clone, Copy)]
+enum HashTool {
+    B3sum,
+    Sha256sum,
+}
+
+impl HashTool {
+    const ALL: [HashTool; 2] = [HashTool::B3sum, HashTool::Sha256sum];
+
+    fn program(self) -> &'static str {
+        match self {
+            HashTool::B3sum => "b3sum",
+            HashTool::Sha256sum => "sha256sum",
+        }
+    }
+
+    fn command(self, remote: &str) -> String {
+        let quoted = shell_quote(remote);
+        match self {
+            HashTool::B3sum => format!("b3sum --no-names -- {quoted}"),
+            HashTool::Sha256sum => format!("sha256sum -- {quoted}"),
+        }
+    }
+
+    /// Digest in the first field of the command's output.
+    fn parse(self, output: &[u8]) -> Option<RemoteChecksum> {
+        let digest = std::str::from_utf8(output)
+            .ok()?
+            .split_whitespace()
+            .next()?;
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digest = digest.to_ascii_lowercase();
+        Some(match self {
+            HashTool::B3sum => RemoteChecksum::Blake3(digest),
+            HashTool::Sha256sum => RemoteChecksum::Sha256(digest),
+        })
+    }
+}
+
+pub use reconnect::ReconnectingSftpRemote;
 
 pub struct SftpRemote {
+    session: Handle<Client>,
     sftp: SftpSession,
+    /// Hash command found on the server, probed on the first checksum
+    hash_tool: OnceCell<Option<HashTool>>,
     ensured_dirs: Mutex<HashSet<String>>,
+    attr_rules: Option<RemoteAttrRules>,
+    /// Distinguishes this connection's temp files from those of other
+    /// clients (or earlier runs) uploading into the same directory.
+    session_token: String,
+    temp_seq: AtomicU64,
 }
 
 impl SftpRemote {
@@ -68,11 +130,22 @@ impl SftpRemote {
         let sftp = SftpSession::new(channel.into_stream()).await?;
         info!("current path: {:?}", sftp.canonicalize(".").await?);
         Ok(Self {
+            session,
             sftp,
+            hash_tool: OnceCell::new(),
             ensured_dirs: Mutex::new(HashSet::new()),
+            attr_rules: None,
+            session_token: session_token(),
+            temp_seq: AtomicU64::new(0),
         })
     }
 
+    /// Apply `rules` to every uploaded file and created directory.
+    pub fn with_attr_rules(mut self, rules: RemoteAttrRules) -> Self {
+        self.attr_rules = (!rules.is_empty()).then_some(rules);
+        self
+    }
+
     async fn ensure_dir_all(&self, remote: &str) -> Result<()> {
         let remote = normalize_remote_dir(remote);
         if remote.is_empty() {
@@ -84,9 +157,171 @@ impl SftpRemote {
         }
 
         create_dir_all(&self.sftp, &remote).await?;
+        let chain = remote_dir_chain(&remote);
+        for dir in &chain {
+            self.apply_attrs(dir, true).await?;
+        }
         let mut ensured_dirs = self.ensured_dirs.lock().await;
-        for dir in remote_dir_chain(&remote) {
-            ensured_dirs.insert(dir);
+        ensured_dirs.extend(chain);
+        Ok(())
+    }
+
+    /// Run `command` on the server and return its exit status and stdout.
+    async fn exec(&self, command: &str) -> Result<(u32, Vec<u8>)> {
+        let mut channel = self.session.channel_open_session().await?;
+        channel.exec(true, command).await?;
+        let mut stdout = Vec::new();
+        let mut status = None;
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+                ChannelMsg::ExitStatus { exit_status } => status = Some(exit_status),
+                ChannelMsg::Failure => return Err(anyhow!("the server refused to run commands")),
+                _ => {}
+            }
+        }
+        let status =
+            status.ok_or_else(|| anyhow!("remote command ended without an exit status"))?;
+        Ok((status, stdout))
+    }
+
+    /// First hash command the server can run, if any. Logs a warning once
+    /// when checksums have to be computed by downloading the files.
+    async fn hash_tool(&self) -> Option<HashTool> {
+        *self
+            .hash_tool
+            .get_or_init(|| async {
+                for tool in HashTool::ALL {
+                    match self.exec(&format!("{} --version", tool.program())).await {
+                        Ok((0, _)) => return Some(tool),
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::debug!("cannot run {} on the server: {e}", tool.program());
+                            break;
+                        }
+                    }
+                }
+                warn!(
+                    "neither b3sum nor sha256sum runs on the server, remote checksums download each file"
+                );
+                None
+            })
+            .await
+    }
+
+    /// BLAKE3 digest of `remote`, read over SFTP.
+    async fn download_checksum(&self, remote: &str) -> Result<RemoteChecksum> {
+        let mut file = self.sftp.open(remote).await?;
+        let mut hasher = blake3::Hasher::new();
+        let mut buf = vec![0_u8; 256 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(RemoteChecksum::Blake3(
+            hasher.finalize().to_hex().to_string(),
+        ))
+    }
+
+    /// Unique temp path next to `remote`; always ends in [`UPLOAD_TEMP_SUFFIX`].
+    fn upload_temp_path(&self, remote: &str) -> String {
+        let seq = self.temp_seq.fetch_add(1, Ordering::Relaxed);
+        format!("{remote}.{}-{seq}{UPLOAD_TEMP_SUFFIX}", self.session_token)
+    }
+
+    async fn apply_attrs(&self, remote: &str, is_dir: bool) -> Result<()> {
+        let Some(attrs) = self
+            .attr_rules
+            .as_ref()
+            .and_then(|rules| rules.lookup(remote, is_dir))
+        else {
+            return Ok(());
+        };
+
+        let mut metadata = FileAttributes::empty();
+        metadata.permissions = attrs.mode;
+        if attrs.uid.is_some() || attrs.gid.is_some() {
+            // SFTP sets uid and gid as a pair, so keep the current value of the
+            // one the rule leaves unspecified.
+            let current = self.sftp.metadata(remote).await?;
+            metadata.uid = attrs.uid.or(current.uid);
+            metadata.gid = attrs.gid.or(current.gid);
+        }
+        self.sftp
+            .set_metadata(remote, metadata)
+            .await
+            .map_err(|e| anyhow!("set attributes on {remote} failed: {e}"))
+    }
+
+    async fn apply_one(
+        &self,
+        op: RemoteOp,
+        cancel: &CancellationToken,
+        progress: &TransferProgress,
+    ) -> Result<()> {
+        if cancel.is_cancelled() {
+            return Err(anyhow!("remote operation cancelled"));
+        }
+        match op {
+            RemoteOp::Upload { local, remote } => {
+                if let Some(parent) = remote_parent(&remote) {
+                    self.ensure_dir_all(&parent).await?;
+                }
+                let mut reader = tokio::fs::File::open(local).await?;
+                let tmp_remote = self.upload_temp_path(&remote);
+                let mut remote_file = self.sftp.create(&tmp_remote).await?;
+                let upload_result = copy_cancelled(&mut reader, &mut remote_file, cancel, progress)
+                    .await
+                    .and_then(|_| {
+                        if cancel.is_cancelled() {
+                            Err(anyhow!("remote operation cancelled"))
+                        } else {
+                            Ok(())
+                        }
+                    });
+                if let Err(e) = upload_result {
+                    let _ = remote_file.shutdown().await;
+                    let _ = self.sftp.remove_file(tmp_remote.as_str()).await;
+                    return Err(e);
+                }
+                remote_file.flush().await?;
+                let _ = remote_file.shutdown().await;
+                if let Err(e) = self.sftp.rename(&tmp_remote, &remote).await {
+                    if !is_no_such_file(&e) {
+                        let _ = self.sftp.remove_file(remote.as_str()).await;
+                        self.sftp.rename(&tmp_remote, &remote).await?;
+                    }
+                }
+                self.apply_attrs(&remote, false).await?;
+            }
+            RemoteOp::Remove { remote } => {
+                let metadata = match self.sftp.metadata(remote.as_str()).await {
+                    Ok(metadata) => metadata,
+                    Err(e) if is_no_such_file(&e) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                };
+                if metadata.is_dir() {
+                    remove_dir_all(&self.sftp, &remote).await?;
+                } else {
+                    self.sftp.remove_file(remote.as_str()).await?;
+                }
+            }
+            RemoteOp::MkDir { remote } => {
+                self.ensure_dir_all(&remote).await?;
+            }
+            RemoteOp::Rename { from, to } => {
+                if let Some(parent) = remote_parent(&to) {
+                    self.ensure_dir_all(&parent).await?;
+                }
+                if let Err(e) = self.sftp.rename(from, to).await {
+                    if !is_no_such_file(&e) {
+                        return Err(e.into());
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -104,89 +339,93 @@ impl RemoteFs for SftpRemote {
         ops: Vec<RemoteOp>,
         cancel: CancellationToken,
     ) -> Result<()> {
-        if ops.is_empty() {
-            return Ok(());
-        }
-
         for op in ops {
-            if cancel.is_cancelled() {
-                return Err(anyhow!("remote operation cancelled"));
-            }
-            match op {
-                RemoteOp::Upload { local, remote } => {
-                    if let Some(parent) = remote_parent(&remote) {
-                        self.ensure_dir_all(&parent).await?;
-                    }
-                    let mut reader = tokio::fs::File::open(local).await?;
-                    let tmp_remote = upload_temp_path(&remote);
-                    let mut remote_file = self.sftp.create(&tmp_remote).await?;
-                    let upload_result = copy_cancelled(&mut reader, &mut remote_file, &cancel)
-                        .await
-                        .and_then(|_| {
-                            if cancel.is_cancelled() {
-                                Err(anyhow!("remote operation cancelled"))
-                            } else {
-                                Ok(())
-                            }
-                        });
-                    if let Err(e) = upload_result {
-                        let _ = remote_file.shutdown().await;
-                        let _ = self.sftp.remove_file(tmp_remote.as_str()).await;
-                        return Err(e);
-                    }
-                    remote_file.flush().await?;
-                    let _ = remote_file.shutdown().await;
-                    if let Err(e) = self.sftp.rename(&tmp_remote, &remote).await {
-                        if !is_no_such_file(&e) {
-                            let _ = self.sftp.remove_file(remote.as_str()).await;
-                            self.sftp.rename(&tmp_remote, &remote).await?;
-                        }
-                    }
-                }
-                RemoteOp::Remove { remote } => {
-                    let metadata = match self.sftp.metadata(remote.as_str()).await {
-                        Ok(metadata) => metadata,
-                        Err(e) if is_no_such_file(&e) => continue,
-                        Err(e) => return Err(e.into()),
-                    };
-                    if metadata.is_dir() {
-                        remove_dir_all(&self.sftp, &remote).await?;
-                    } else {
-                        self.sftp.remove_file(remote.as_str()).await?;
-                    }
-                }
-                RemoteOp::MkDir { remote } => {
-                    self.ensure_dir_all(&remote).await?;
-                }
-                RemoteOp::Rename { from, to } => {
-                    if let Some(parent) = remote_parent(&to) {
-                        self.ensure_dir_all(&parent).await?;
-                    }
-                    if let Err(e) = self.sftp.rename(from, to).await {
-                        if !is_no_such_file(&e) {
-                            return Err(e.into());
-                        }
-                    }
-                }
-            }
+            self.apply_one(op, &cancel, &TransferProgress::default())
+                .await?;
         }
         Ok(())
     }
 
+    async fn apply_op(
+        &self,
+        op: RemoteOp,
+        cancel: CancellationToken,
+        progress: TransferProgress,
+    ) -> Result<()> {
+        self.apply_one(op, &cancel, &progress).await
+    }
+
     async fn ping(&self) -> Result<()> {
         let _ = self.sftp.metadata(".").await?;
         Ok(())
     }
+
+    /// Hashed on the server with `b3sum` or `sha256sum` when available, so
+    /// the file does not travel; otherwise downloaded and hashed locally.
+    async fn checksum(&self, remote: &str) -> Result<RemoteChecksum> {
+        let Some(tool) = self.hash_tool().await else {
+            return self.download_checksum(remote).await;
+        };
+        match self.exec(&tool.command(remote)).await? {
+            (0, output) => tool
+                .parse(&output)
+                .ok_or_else(|| anyhow!("unexpected {} output for {remote}", tool.program())),
+            (COMMAND_NOT_FOUND, _) => self.download_checksum(remote).await,
+            (status, _) => Err(anyhow!(
+                "{} failed on {remote} (exit status {status})",
+                tool.program()
+            )),
+        }
+    }
+
+    async fn list_dir(&self, remote: &str) -> Result<Vec<RemoteEntry>> {
+        let entries = match self.sftp.read_dir(remote).await {
+            Ok(entries) => entries,
+            Err(e) if is_no_such_file(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(entries
+            .filter(|entry| !matches!(entry.file_name().as_str(), "." | ".."))
+            .map(|entry| {
+                let metadata = entry.metadata();
+                RemoteEntry {
+                    path: remote_child(remote, &entry.file_name()),
+                    is_dir: metadata.is_dir(),
+                    size: metadata.size.unwrap_or_default(),
+                    mtime: metadata.mtime.map(u64::from),
+                }
+            })
+            .collect())
+    }
 }
 
-fn upload_temp_path(remote: &str) -> String {
-    format!("{remote}.fsync.tmp")
+fn session_token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_nanos() as u64)
+        .unwrap_or_default();
+    format!("{:x}{:08x}", std::process::id(), nanos as u32)
+}
+
+/// `value` as a single POSIX shell word.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn remote_child(dir: &str, name: &str) -> String {
+    let dir = dir.replace('\\', "/");
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{name}", dir.trim_end_matches('/'))
+    }
 }
 
 async fn copy_cancelled<R, W>(
     reader: &mut R,
     writer: &mut W,
     cancel: &CancellationToken,
+    progress: &TransferProgress,
 ) -> Result<u64>
 where
     R: tokio::io::AsyncRead + Unpin,
@@ -210,6 +449,7 @@ where
             result = writer.write_all(&buf[..n]) => result?,
         }
         written += n as u64;
+        progress.add(n as u64);
     }
 }
 
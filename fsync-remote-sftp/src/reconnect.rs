@@ -0,0 +1,136 @@
+use crate::SftpRemote;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use fsync_core::{
+    RemoteAttrRules, RemoteChecksum, RemoteEntry, RemoteFs, RemoteOp, TransferProgress,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// [`SftpRemote`] that opens its session lazily and opens a new one when a
+/// ping finds the current one dead, so a task can start while the server is
+/// down and carry on once it is back.
+pub struct ReconnectingSftpRemote {
+    host: String,
+    user: String,
+    password: Option<String>,
+    allowed_fingerprints: Option<Vec<String>>,
+    attr_rules: Option<RemoteAttrRules>,
+    session: RwLock<Option<Arc<SftpRemote>>>,
+}
+
+impl ReconnectingSftpRemote {
+    /// Does not connect; see [`ReconnectingSftpRemote::connect`].
+    pub fn new(
+        host_with_port: &str,
+        user: &str,
+        password: Option<&str>,
+        allowed_fingerprints: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            host: host_with_port.to_string(),
+            user: user.to_string(),
+            password: password.map(str::to_string),
+            allowed_fingerprints,
+            attr_rules: None,
+            session: RwLock::new(None),
+        }
+    }
+
+    /// Apply `rules` to every uploaded file and created directory.
+    pub fn with_attr_rules(mut self, rules: RemoteAttrRules) -> Self {
+        self.attr_rules = (!rules.is_empty()).then_some(rules);
+        self
+    }
+
+    /// Open a session now unless one is already open.
+    pub async fn connect(&self) -> Result<()> {
+        self.session().await.map(|_| ())
+    }
+
+    async fn session(&self) -> Result<Arc<SftpRemote>> {
+        if let Some(session) = self.session.read().await.as_ref() {
+            return Ok(session.clone());
+        }
+        let mut slot = self.session.write().await;
+        if let Some(session) = slot.as_ref() {
+            return Ok(session.clone());
+        }
+        info!(host = %self.host, "opening SFTP session");
+        let connect = SftpRemote::connect(
+            &self.host,
+            &self.user,
+            self.password.as_deref(),
+            self.allowed_fingerprints.clone(),
+        );
+        let mut remote = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| anyhow!("connecting to {} timed out", self.host))??;
+        if let Some(rules) = &self.attr_rules {
+            remote = remote.with_attr_rules(rules.clone());
+        }
+        let session = Arc::new(remote);
+        *slot = Some(session.clone());
+        Ok(session)
+    }
+
+    /// Forget `dead` so the next call opens a new session.
+    async fn drop_session(&self, dead: &Arc<SftpRemote>) {
+        let mut slot = self.session.write().await;
+        if slot
+            .as_ref()
+            .is_some_and(|session| Arc::ptr_eq(session, dead))
+        {
+            *slot = None;
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteFs for ReconnectingSftpRemote {
+    async fn apply_batch(&self, ops: Vec<RemoteOp>) -> Result<()> {
+        self.session().await?.apply_batch(ops).await
+    }
+
+    async fn apply_batch_cancelled(
+        &self,
+        ops: Vec<RemoteOp>,
+        cancel: CancellationToken,
+    ) -> Result<()> {
+        self.session()
+            .await?
+            .apply_batch_cancelled(ops, cancel)
+            .await
+    }
+
+    async fn apply_op(
+        &self,
+        op: RemoteOp,
+        cancel: CancellationToken,
+        progress: TransferProgress,
+    ) -> Result<()> {
+        self.session().await?.apply_op(op, cancel, progress).await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let session = self.session().await?;
+        if session.ping().await.is_ok() {
+            return Ok(());
+        }
+        self.drop_session(&session).await;
+        self.session().await?.ping().await
+    }
+
+    async fn checksum(&self, remote: &str) -> Result<RemoteChecksum> {
+        self.session().await?.checksum(remote).await
+    }
+
+    async fn list_dir(&self, remote: &str) -> Result<Vec<RemoteEntry>> {
+        self.session().await?.list_dir(remote).await
+    }
+}
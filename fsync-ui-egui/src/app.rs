@@ -3,22 +3,32 @@ mod shell;
 mod tasks_ui;
 
 use anyhow::Result;
-use fsync_core::{spawn_task, RemoteCfg, RemoteOpLog, SyncTaskHandle, TaskConfig, TaskState};
-use fsync_remote_sftp::SftpRemote;
+use fsync_core::{
+    audit_task, repair_task, spawn_task, AuditOptions, PathFilter, Pattern, RemoteAttrRules,
+    RemoteCfg, RemoteOpLog, SyncTaskHandle, TaskConfig, TaskState, TaskStats,
+};
+use fsync_remote_sftp::{ReconnectingSftpRemote, SftpRemote};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use tokio::sync::broadcast;
 
 use crate::models::{
-    default_task_cache_dir, find_remote_profile, path_text, sample_task, selected_draft,
-    selected_profile_draft, state_label, AppConfig, AppState, Draft, LoadedTask, PanelTab,
-    RemoteProfileDraft, TaskView, ThemeMode,
+    blank_to_none, default_task_cache_dir, find_remote_profile, path_text, sample_task,
+    selected_draft, selected_profile_draft, state_label, AppConfig, AppState, AuditStatus, Draft,
+    FilterPreviewState, LoadedTask, PanelTab, RemoteProfileDraft, TaskView, ThemeMode,
 };
 use crate::operation_logs::OperationLogNotification;
-use crate::storage::{load_config, persist_app_config, save_state, AppStorage};
+use crate::storage::{load_config, persist_app_config, save_state, save_task_stats, AppStorage};
 use crate::theme::{configure_style, install_chinese_fonts};
 
+/// How often the totals of running tasks are written to the database.
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Entries the pattern editor preview walks before it gives up.
+const FILTER_PREVIEW_MAX_ENTRIES: usize = 50_000;
+/// Example paths the pattern editor preview lists of each kind.
+const FILTER_PREVIEW_SAMPLES: usize = 8;
+
 pub(crate) struct FSyncApp {
     runtime: Arc<Runtime>,
     storage: Arc<AppStorage>,
@@ -32,25 +42,12 @@ pub(crate) struct FSyncApp {
     selected_profile: Option<usize>,
     profile_draft: RemoteProfileDraft,
     profile_password_visible: bool,
-    pattern_editor: Option<PatternEditorKind>,
+    pattern_editor: bool,
     pattern_draft: Vec<String>,
     new_pattern: String,
+    filter_preview: Arc<Mutex<FilterPreviewState>>,
     operation_log_rx: broadcast::Receiver<OperationLogNotification>,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub(crate) enum PatternEditorKind {
-    Include,
-    Exclude,
-}
-
-impl PatternEditorKind {
-    fn title(self) -> &'static str {
-        match self {
-            Self::Include => "Include Patterns",
-            Self::Exclude => "Exclude Patterns",
-        }
-    }
+    stats_saved_at: Instant,
 }
 
 impl FSyncApp {
@@ -88,10 +85,12 @@ impl FSyncApp {
             selected_profile,
             profile_draft,
             profile_password_visible: false,
-            pattern_editor: None,
+            pattern_editor: false,
             pattern_draft: Vec::new(),
             new_pattern: String::new(),
+            filter_preview: Arc::default(),
             operation_log_rx,
+            stats_saved_at: Instant::now(),
         }
     }
 
@@ -175,6 +174,8 @@ impl FSyncApp {
                 last_operation_log_id: 0,
                 state: TaskState::Idle,
                 starting: false,
+                audit: None,
+                stats: TaskStats::default(),
             });
             state.selected = Some(state.tasks.len() - 1);
             drop(state);
@@ -212,11 +213,33 @@ impl FSyncApp {
             find_remote_profile(&state.remote_profiles, self.draft.remote_profile_id).cloned()
         };
         let cfg = self.draft.to_config(profile.as_ref())?;
-        if let Some(task) = self.state.lock().unwrap().tasks.get_mut(idx) {
-            task.cfg = cfg;
-            task.remote_profile_id = self.draft.remote_profile_id;
+        let mut state = self.state.lock().unwrap();
+        let Some(task) = state.tasks.get_mut(idx) else {
+            return Ok(());
+        };
+        let live_update = task
+            .handle
+            .as_ref()
+            .map(|handle| handle.update_config(cfg.resolve_templates()?));
+        task.cfg = cfg;
+        task.remote_profile_id = self.draft.remote_profile_id;
+        match live_update {
+            None => {
+                drop(state);
+                self.toast("Task updated in memory");
+            }
+            Some(Ok(())) => {
+                task.logs
+                    .push("Configuration applied to the running task".into());
+                drop(state);
+                self.toast("Task updated and applied to the running task");
+            }
+            Some(Err(e)) => {
+                task.logs.push(format!("Configuration not applied: {e}"));
+                drop(state);
+                self.toast("Task updated; restart it to apply the change");
+            }
         }
-        self.toast("Task updated in memory");
         Ok(())
     }
 
@@ -271,6 +294,118 @@ impl FSyncApp {
         });
     }
 
+    fn toggle_pause(&mut self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        let Some(task) = state.tasks.get_mut(idx) else {
+            return;
+        };
+        let Some(handle) = &task.handle else {
+            return;
+        };
+        match task.state {
+            TaskState::Running | TaskState::Offline(_) => {
+                handle.pause();
+                task.logs.push("Pause requested".into());
+            }
+            TaskState::Paused => {
+                handle.resume();
+                task.logs.push("Resume requested".into());
+            }
+            _ => {}
+        }
+    }
+
+    fn rescan_task(&mut self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(task) = state.tasks.get_mut(idx) {
+            if let Some(handle) = &task.handle {
+                handle.rescan();
+                task.logs.push("Rescan requested".into());
+            }
+        }
+    }
+
+    /// Upload every file of the task again, whatever its recorded state.
+    fn resync_task(&mut self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(task) = state.tasks.get_mut(idx) {
+            if let Some(handle) = &task.handle {
+                handle.resync(vec![task.cfg.local.clone()]);
+                task.logs.push("Full resync requested".into());
+            }
+        }
+    }
+
+    /// Compare the task's local and remote trees in the background. With
+    /// `repair`, missing and outdated files are uploaded first and recorded
+    /// in the task's state store, like `fsync-cli audit --repair`; extra
+    /// remote files are left alone.
+    fn audit_task(&mut self, idx: usize, repair: bool) {
+        let mut state = self.state.lock().unwrap();
+        let Some(task) = state.tasks.get_mut(idx) else {
+            return;
+        };
+        if matches!(task.audit, Some(AuditStatus::Running)) {
+            return;
+        }
+        if task.remote_profile_id.is_none() {
+            drop(state);
+            self.toast("Select a remote profile first");
+            return;
+        }
+        if repair && (task.handle.is_some() || task.starting) {
+            drop(state);
+            self.toast("Stop the task before repairing");
+            return;
+        }
+        let cfg = task.cfg.clone();
+        let previous = match task.audit.replace(AuditStatus::Running) {
+            Some(AuditStatus::Done(report)) if repair => Some(report),
+            _ => None,
+        };
+        drop(state);
+
+        let state = self.state.clone();
+        self.runtime.spawn(async move {
+            let result = async {
+                let cfg = cfg.resolve_templates().map_err(|e| e.to_string())?;
+                let remote = connect_remote(&cfg).await?;
+                let repaired = match previous {
+                    Some(report) => Some(
+                        repair_task(&cfg, &remote, &report, false)
+                            .await
+                            .map_err(|e| format!("repair failed: {e}"))?,
+                    ),
+                    None => None,
+                };
+                let report = audit_task(&cfg, &remote, AuditOptions::default())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok::<_, String>((repaired, report))
+            }
+            .await;
+            let mut state = state.lock().unwrap();
+            if let Some(task) = state.tasks.get_mut(idx) {
+                match result {
+                    Ok((repaired, report)) => {
+                        if let Some(repaired) = repaired {
+                            task.logs.push(format!("Repair applied {repaired} op(s)"));
+                        }
+                        task.logs.push(format!(
+                            "Audit finished: {} difference(s)",
+                            report.entries.len()
+                        ));
+                        task.audit = Some(AuditStatus::Done(report));
+                    }
+                    Err(e) => {
+                        task.logs.push(format!("Audit failed: {e}"));
+                        task.audit = Some(AuditStatus::Failed(e));
+                    }
+                }
+            }
+        });
+    }
+
     fn start_all(&mut self) {
         let len = self.state.lock().unwrap().tasks.len();
         for idx in 0..len {
@@ -301,6 +436,8 @@ impl FSyncApp {
 
     fn poll_task_events(&mut self) {
         let mut operation_logs: Vec<(String, RemoteOpLog)> = Vec::new();
+        let mut stats = Vec::new();
+        let save_running_stats = self.stats_saved_at.elapsed() >= STATS_SAVE_INTERVAL;
         {
             let mut state = self.state.lock().unwrap();
             for task in &mut state.tasks {
@@ -330,9 +467,16 @@ impl FSyncApp {
                         task.starting = matches!(task.state, TaskState::Starting(_));
                     }
                     if matches!(task.state, TaskState::Idle | TaskState::Error(_)) {
+                        task.stats = TaskStats {
+                            queue_depth: 0,
+                            ..task.total_stats()
+                        };
+                        stats.push((task.cfg.id.to_string(), task.stats.clone()));
                         task.handle = None;
                         task.log_rx = None;
                         task.starting = false;
+                    } else if save_running_stats {
+                        stats.push((task.cfg.id.to_string(), task.total_stats()));
                     }
                 }
                 if task.logs.len() > 1_000 {
@@ -353,6 +497,18 @@ impl FSyncApp {
             }
         }
 
+        if save_running_stats {
+            self.stats_saved_at = Instant::now();
+        }
+        if !stats.is_empty() {
+            let storage = self.storage.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = save_task_stats(&storage, &stats).await {
+                    tracing::warn!(error = %e, "failed to save task statistics");
+                }
+            });
+        }
+
         self.poll_operation_log_notifications();
     }
 
@@ -489,34 +645,71 @@ impl FSyncApp {
             .unwrap_or_else(RemoteProfileDraft::new_empty);
     }
 
-    fn open_pattern_editor(&mut self, kind: PatternEditorKind) {
-        self.pattern_draft = match kind {
-            PatternEditorKind::Include => patterns_from_text(&self.draft.include),
-            PatternEditorKind::Exclude => patterns_from_text(&self.draft.exclude),
-        };
+    fn open_pattern_editor(&mut self) {
+        self.pattern_draft = patterns_from_text(&self.draft.filters);
         self.new_pattern.clear();
-        self.pattern_editor = Some(kind);
+        self.pattern_editor = true;
     }
 
     fn apply_pattern_editor(&mut self) {
-        let Some(kind) = self.pattern_editor else {
+        if !self.pattern_editor {
             return;
-        };
-        let value = self
+        }
+        self.draft.filters = self
             .pattern_draft
             .iter()
             .map(|pattern| pattern.trim())
             .filter(|pattern| !pattern.is_empty())
             .collect::<Vec<_>>()
             .join("; ");
-        match kind {
-            PatternEditorKind::Include => self.draft.include = value,
-            PatternEditorKind::Exclude => self.draft.exclude = value,
-        }
-        self.pattern_editor = None;
+        self.pattern_editor = false;
         self.new_pattern.clear();
     }
 
+    /// Walk the local folder with the rules being edited, unless a preview
+    /// is running or the latest one is still current.
+    fn refresh_filter_preview(&self, ctx: &eframe::egui::Context) {
+        let rules = self
+            .pattern_draft
+            .iter()
+            .map(|rule| rule.trim().to_string())
+            .filter(|rule| !rule.is_empty())
+            .collect::<Vec<_>>();
+        let key = (
+            self.draft.local.trim().to_string(),
+            self.draft.size.trim().to_string(),
+            rules,
+            self.draft.gitignore,
+        );
+        let mut preview = self.filter_preview.lock().unwrap();
+        if preview.running || preview.key.as_ref() == Some(&key) {
+            return;
+        }
+        preview.key = Some(key.clone());
+        preview.running = true;
+        drop(preview);
+
+        let state = self.filter_preview.clone();
+        let ctx = ctx.clone();
+        self.runtime.spawn_blocking(move || {
+            let (local, size, rules, gitignore) = key;
+            let result = if local.is_empty() || !std::path::Path::new(&local).is_dir() {
+                Err("Choose an existing local folder to preview the rules".to_string())
+            } else {
+                let rules = rules.into_iter().map(Pattern).collect::<Vec<_>>();
+                // Built like the running task's filter.
+                let filter = PathFilter::from_rules(&local, &rules)
+                    .with_ignore_files(gitignore)
+                    .with_size_filter(blank_to_none(&size).as_deref());
+                Ok(filter.preview(FILTER_PREVIEW_SAMPLES, FILTER_PREVIEW_MAX_ENTRIES))
+            };
+            let mut preview = state.lock().unwrap();
+            preview.running = false;
+            preview.result = Some(result);
+            ctx.request_repaint();
+        });
+    }
+
     fn persist_state(&self) -> Result<usize> {
         let (tasks, remote_profiles) = {
             let state = self.state.lock().unwrap();
@@ -528,6 +721,7 @@ impl FSyncApp {
                         cfg: task.cfg.clone(),
                         remote_profile_id: task.remote_profile_id,
                         recent_logs: Vec::new(),
+                        stats: task.total_stats(),
                     })
                     .collect::<Vec<_>>(),
                 state.remote_profiles.clone(),
@@ -548,7 +742,10 @@ fn patterns_from_text(value: &str) -> Vec<String> {
         .collect()
 }
 
+/// Spawn the task even when the server does not answer: it then starts
+/// offline and reconnects on its own.
 async fn start_remote_task(cfg: TaskConfig) -> Result<SyncTaskHandle, String> {
+    let cfg = cfg.resolve_templates().map_err(|e| e.to_string())?;
     let RemoteCfg::Sftp {
         host,
         user,
@@ -556,6 +753,38 @@ async fn start_remote_task(cfg: TaskConfig) -> Result<SyncTaskHandle, String> {
         fingerprints,
         ..
     } = cfg.remote_cfg.clone();
+    let attr_rules =
+        RemoteAttrRules::new(&cfg.remote, &cfg.attributes).map_err(|e| e.to_string())?;
+    let remote = ReconnectingSftpRemote::new(&host, &user, password.as_deref(), fingerprints)
+        .with_attr_rules(attr_rules);
+    match remote.connect().await {
+        Ok(()) => tracing::info!(
+            task_id = %cfg.id,
+            task_name = %cfg.name,
+            "SFTP connected, spawning sync task"
+        ),
+        Err(e) => tracing::warn!(
+            task_id = %cfg.id,
+            task_name = %cfg.name,
+            host = %host,
+            error = %e,
+            "SFTP connect failed, spawning sync task offline"
+        ),
+    }
+    Ok(spawn_task(cfg, remote))
+}
+
+/// Connect to the task's SFTP server, retrying with the task's backoff.
+async fn connect_remote(cfg: &TaskConfig) -> Result<SftpRemote, String> {
+    let RemoteCfg::Sftp {
+        host,
+        user,
+        password,
+        fingerprints,
+        ..
+    } = cfg.remote_cfg.clone();
+    let attr_rules =
+        RemoteAttrRules::new(&cfg.remote, &cfg.attributes).map_err(|e| e.to_string())?;
     let mut attempt = 0u32;
     let max = cfg.retry_max;
     let mut backoff = cfg.retry_backoff_ms;
@@ -568,15 +797,7 @@ async fn start_remote_task(cfg: TaskConfig) -> Result<SyncTaskHandle, String> {
             "connecting to SFTP"
         );
         match SftpRemote::connect(&host, &user, password.as_deref(), fingerprints.clone()).await {
-            Ok(remote) => {
-                tracing::info!(
-                    task_id = %cfg.id,
-                    task_name = %cfg.name,
-                    host = %host,
-                    "SFTP connected, spawning sync task"
-                );
-                return Ok(spawn_task(cfg, remote));
-            }
+            Ok(remote) => return Ok(remote.with_attr_rules(attr_rules)),
             Err(e) => {
                 attempt += 1;
                 tracing::warn!(
@@ -1,9 +1,17 @@
 use eframe::egui;
+use fsync_core::{
+    AttrTarget, ChangeDetection, DeletionPolicy, DriftKind, InitialSync, SyncProgress, TaskState,
+    TaskStats, WatcherMode,
+};
 
-use crate::app::{FSyncApp, PatternEditorKind};
-use crate::models::{find_remote_profile, path_text, patterns_text, state_label, PanelTab};
+use crate::app::FSyncApp;
+use crate::models::{
+    find_remote_profile, path_text, patterns_text, state_label, AttrDraft, AuditStatus,
+    FilterPreviewState, PanelTab, PriorityDraft,
+};
 use crate::widgets::{
-    edit_field, edit_remote_profile_selector, info_tile_sized, status_color, status_dot,
+    edit_choice, edit_field, edit_remote_profile_selector, info_tile_sized, status_color,
+    status_dot,
 };
 
 impl FSyncApp {
@@ -206,15 +214,66 @@ impl FSyncApp {
     }
 
     pub(super) fn render_dashboard(&mut self, ui: &mut egui::Ui, idx: usize) {
-        let (cfg, logs, profile) = {
+        let (cfg, logs, profile, audit, task_state, starting, progress, stats) = {
             let state = self.state.lock().unwrap();
             let task = &state.tasks[idx];
             (
                 task.cfg.clone(),
                 task.logs.clone(),
                 find_remote_profile(&state.remote_profiles, task.remote_profile_id).cloned(),
+                task.audit.clone(),
+                task.state.clone(),
+                task.starting,
+                task.handle.as_ref().and_then(|handle| handle.progress()),
+                task.total_stats(),
             )
         };
+        let mut pause_clicked = false;
+        let mut rescan_clicked = false;
+        let mut resync_clicked = false;
+        ui.horizontal(|ui| {
+            let status = state_label(&task_state);
+            status_dot(ui, status_color(ui, &task_state, starting), &status);
+            ui.label(egui::RichText::new(&status).strong());
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let paused = matches!(task_state, TaskState::Paused);
+                let can_pause = (!starting
+                    && matches!(task_state, TaskState::Running | TaskState::Offline(_)))
+                    || paused;
+                pause_clicked = ui
+                    .add_enabled(
+                        can_pause,
+                        egui::Button::new(if paused { "Resume" } else { "Pause" }),
+                    )
+                    .on_hover_text("Keep watching but hold back remote changes")
+                    .clicked();
+                let active = matches!(
+                    task_state,
+                    TaskState::Running | TaskState::Paused | TaskState::Offline(_)
+                );
+                resync_clicked = ui
+                    .add_enabled(active, egui::Button::new("Resync"))
+                    .on_hover_text("Upload every file again, ignoring the cached state")
+                    .clicked();
+                rescan_clicked = ui
+                    .add_enabled(active, egui::Button::new("Rescan"))
+                    .on_hover_text("Walk the local tree now")
+                    .clicked();
+            });
+        });
+        if pause_clicked {
+            self.toggle_pause(idx);
+        }
+        if rescan_clicked {
+            self.rescan_task(idx);
+        }
+        if resync_clicked {
+            self.resync_task(idx);
+        }
+        if let Some(progress) = progress.filter(|progress| !progress.is_finished()) {
+            render_progress(ui, &progress);
+        }
+        ui.add_space(4.0);
         dashboard_info_row(
             ui,
             "Local",
@@ -250,13 +309,20 @@ impl FSyncApp {
         );
         dashboard_info_row(
             ui,
-            "Include",
-            &patterns_text(&cfg.include),
-            "Exclude",
-            &patterns_text(&cfg.exclude),
+            "Filters",
+            &patterns_text(&cfg.filters),
+            "Gitignore",
+            if cfg.gitignore { "Honored" } else { "Not used" },
             78.0,
         );
 
+        ui.add_space(8.0);
+        ui.heading("Statistics");
+        render_stats(ui, &stats);
+
+        ui.add_space(8.0);
+        self.render_audit(ui, idx, audit.as_ref());
+
         ui.add_space(8.0);
         ui.heading("Logs");
         ui.add_space(4.0);
@@ -286,6 +352,82 @@ impl FSyncApp {
             });
     }
 
+    fn render_audit(&mut self, ui: &mut egui::Ui, idx: usize, audit: Option<&AuditStatus>) {
+        let running = matches!(audit, Some(AuditStatus::Running));
+        let repairable = match audit {
+            Some(AuditStatus::Done(report)) => report
+                .entries
+                .iter()
+                .any(|entry| entry.kind != DriftKind::ExtraRemote),
+            _ => false,
+        };
+        ui.horizontal(|ui| {
+            ui.heading("Drift Audit");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui
+                    .add_enabled(repairable, egui::Button::new("Repair"))
+                    .on_hover_text("Upload missing and outdated files")
+                    .clicked()
+                {
+                    self.audit_task(idx, true);
+                }
+                if ui
+                    .add_enabled(!running, egui::Button::new("Audit"))
+                    .on_hover_text("Compare local and remote trees without changing anything")
+                    .clicked()
+                {
+                    self.audit_task(idx, false);
+                }
+                if running {
+                    ui.spinner();
+                }
+            });
+        });
+        match audit {
+            None => {
+                ui.label(egui::RichText::new("Not audited yet").weak());
+            }
+            Some(AuditStatus::Running) => {
+                ui.label(egui::RichText::new("Comparing local and remote trees...").weak());
+            }
+            Some(AuditStatus::Failed(e)) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Audit failed: {e}"));
+            }
+            Some(AuditStatus::Done(report)) => {
+                ui.label(format!(
+                    "{} local file(s), {} remote file(s), {} difference(s)",
+                    report.local_files,
+                    report.remote_files,
+                    report.entries.len()
+                ));
+                if report.entries.is_empty() {
+                    return;
+                }
+                egui::ScrollArea::vertical()
+                    .id_salt("task_audit_scroll")
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("task_audit_grid")
+                            .num_columns(4)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for header in ["Difference", "Path", "Local size", "Remote size"] {
+                                    ui.label(egui::RichText::new(header).strong());
+                                }
+                                ui.end_row();
+                                for entry in &report.entries {
+                                    ui.label(drift_label(entry.kind));
+                                    ui.label(egui::RichText::new(&entry.path).monospace());
+                                    ui.label(size_text(entry.local_size));
+                                    ui.label(size_text(entry.remote_size));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            }
+        }
+    }
+
     pub(super) fn render_settings(&mut self, ui: &mut egui::Ui) {
         egui::ScrollArea::vertical()
             .id_salt("task_settings_scroll")
@@ -302,30 +444,14 @@ impl FSyncApp {
                     edit_field(&mut columns[0], "Local", &mut self.draft.local);
                     edit_field(&mut columns[1], "Remote", &mut self.draft.remote);
                 });
-                let include = self.draft.include.clone();
-                let exclude = self.draft.exclude.clone();
-                let mut edit_include = false;
-                let mut edit_exclude = false;
-                ui.columns(2, |columns| {
-                    edit_include = pattern_preview(
-                        &mut columns[0],
-                        "Include",
-                        &include,
-                        "No include patterns",
-                    );
-                    edit_exclude = pattern_preview(
-                        &mut columns[1],
-                        "Exclude",
-                        &exclude,
-                        "No exclude patterns",
-                    );
-                });
-                if edit_include {
-                    self.open_pattern_editor(PatternEditorKind::Include);
-                }
-                if edit_exclude {
-                    self.open_pattern_editor(PatternEditorKind::Exclude);
+                let filters = self.draft.filters.clone();
+                if pattern_preview(ui, "Filters", &filters, "No filter rules, everything syncs") {
+                    self.open_pattern_editor();
                 }
+                ui.checkbox(
+                    &mut self.draft.gitignore,
+                    "Skip what .gitignore files and .git/info/exclude ignore",
+                );
                 edit_remote_profile_selector(
                     ui,
                     &profiles,
@@ -343,6 +469,68 @@ impl FSyncApp {
                     );
                 });
                 edit_field(ui, "Size", &mut self.draft.size);
+                ui.columns(4, |columns| {
+                    edit_choice(
+                        &mut columns[0],
+                        "Change Detection",
+                        &mut self.draft.change_detection,
+                        &[
+                            (ChangeDetection::Mtime, "Mtime"),
+                            (ChangeDetection::MtimeSize, "Mtime and size"),
+                            (ChangeDetection::Hash, "Content hash"),
+                        ],
+                    );
+                    edit_choice(
+                        &mut columns[1],
+                        "Deletions",
+                        &mut self.draft.deletion_policy,
+                        &[
+                            (DeletionPolicy::Propagate, "Propagate"),
+                            (DeletionPolicy::Keep, "Keep remote"),
+                        ],
+                    );
+                    edit_choice(
+                        &mut columns[2],
+                        "Initial Sync",
+                        &mut self.draft.initial_sync,
+                        &[
+                            (InitialSync::Upload, "Upload all"),
+                            (InitialSync::Adopt, "Adopt by mtime"),
+                            (InitialSync::AdoptChecksum, "Adopt by checksum"),
+                        ],
+                    );
+                    edit_choice(
+                        &mut columns[3],
+                        "Watcher",
+                        &mut self.draft.watcher_mode,
+                        &[
+                            (WatcherMode::Auto, "Auto"),
+                            (WatcherMode::Native, "Native"),
+                            (WatcherMode::Poll, "Poll"),
+                            (WatcherMode::Hybrid, "Hybrid"),
+                        ],
+                    );
+                });
+                ui.columns(4, |columns| {
+                    edit_field(&mut columns[0], "Poll ms", &mut self.draft.poll_interval_ms);
+                    edit_field(
+                        &mut columns[1],
+                        "Parallel ops",
+                        &mut self.draft.max_parallel_ops,
+                    );
+                    edit_field(
+                        &mut columns[2],
+                        "Stale temp s",
+                        &mut self.draft.stale_temp_secs,
+                    );
+                    edit_field(
+                        &mut columns[3],
+                        "Full scan s",
+                        &mut self.draft.full_scan_secs,
+                    );
+                });
+                edit_priority_rules(ui, &mut self.draft.priorities);
+                edit_attr_rules(ui, &mut self.draft.attributes);
                 ui.add_space(12.0);
                 ui.horizontal(|ui| {
                     if ui
@@ -380,16 +568,17 @@ impl FSyncApp {
     }
 
     pub(super) fn render_pattern_modal(&mut self, ctx: &egui::Context) {
-        let Some(kind) = self.pattern_editor else {
+        if !self.pattern_editor {
             return;
-        };
+        }
+        self.refresh_filter_preview(ctx);
 
         const MODAL_WIDTH: f32 = 680.0;
-        const MODAL_HEIGHT: f32 = 430.0;
+        const MODAL_HEIGHT: f32 = 620.0;
         const CONTENT_WIDTH: f32 = MODAL_WIDTH - 24.0;
 
         let mut open = true;
-        egui::Window::new(kind.title())
+        egui::Window::new("Filter Rules")
             .open(&mut open)
             .fixed_size(egui::vec2(MODAL_WIDTH, MODAL_HEIGHT))
             .resizable(false)
@@ -397,11 +586,21 @@ impl FSyncApp {
             .constrain(true)
             .show(ctx, |ui| {
                 ui.set_width(CONTENT_WIDTH);
+                ui.label(
+                    egui::RichText::new(
+                        "The last matching rule wins. `!` keeps a path, a trailing `/` \
+                         matches directories only and `re:` starts a regular expression.",
+                    )
+                    .small()
+                    .weak(),
+                );
+                ui.add_space(4.0);
                 ui.horizontal(|ui| {
                     let edit_width = CONTENT_WIDTH - 80.0;
                     ui.add_sized(
                         [edit_width, 28.0],
-                        egui::TextEdit::singleline(&mut self.new_pattern).hint_text("Pattern"),
+                        egui::TextEdit::singleline(&mut self.new_pattern)
+                            .hint_text("Rule, e.g. target/ or !*.rs"),
                     );
                     if ui
                         .add_sized([72.0, 28.0], egui::Button::new("Add"))
@@ -421,7 +620,7 @@ impl FSyncApp {
                     .inner_margin(egui::Margin::same(8))
                     .show(ui, |ui| {
                         ui.set_width(CONTENT_WIDTH);
-                        let height = 300.0;
+                        let height = 200.0;
                         egui::ScrollArea::vertical()
                             .id_salt("pattern_editor_list")
                             .max_height(height)
@@ -434,7 +633,7 @@ impl FSyncApp {
                                 let len = self.pattern_draft.len();
 
                                 if len == 0 {
-                                    ui.label(egui::RichText::new("No patterns").weak());
+                                    ui.label(egui::RichText::new("No rules").weak());
                                 }
 
                                 for idx in 0..len {
@@ -496,6 +695,10 @@ impl FSyncApp {
                             });
                     });
 
+                ui.add_space(8.0);
+                let preview = self.filter_preview.clone();
+                filter_preview_panel(ui, &preview.lock().unwrap(), CONTENT_WIDTH);
+
                 ui.add_space(10.0);
                 ui.horizontal(|ui| {
                     if ui
@@ -508,19 +711,212 @@ impl FSyncApp {
                         .add_sized([72.0, 28.0], egui::Button::new("Cancel"))
                         .clicked()
                     {
-                        self.pattern_editor = None;
+                        self.pattern_editor = false;
                         self.new_pattern.clear();
                     }
                 });
             });
 
         if !open {
-            self.pattern_editor = None;
+            self.pattern_editor = false;
             self.new_pattern.clear();
         }
     }
 }
 
+fn rule_list_header(ui: &mut egui::Ui, label: &str) -> bool {
+    let mut add_clicked = false;
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(label).small().weak());
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            if ui
+                .add_sized([72.0, 24.0], egui::Button::new("Add"))
+                .clicked()
+            {
+                add_clicked = true;
+            }
+        });
+    });
+    add_clicked
+}
+
+fn edit_priority_rules(ui: &mut egui::Ui, rules: &mut Vec<PriorityDraft>) {
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().faint_bg_color)
+        .inner_margin(egui::Margin::symmetric(10, 7))
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            if rule_list_header(ui, "Upload Priorities") {
+                rules.push(PriorityDraft {
+                    priority: "1".into(),
+                    ..Default::default()
+                });
+            }
+            if rules.is_empty() {
+                ui.label(egui::RichText::new("No rules, every file has priority 0").weak());
+                return;
+            }
+            let mut remove_idx = None;
+            egui::Grid::new("task_priority_rules")
+                .num_columns(3)
+                .show(ui, |ui| {
+                    for (idx, rule) in rules.iter_mut().enumerate() {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut rule.pattern)
+                                .hint_text("Pattern")
+                                .desired_width(280.0),
+                        );
+                        ui.add(
+                            egui::TextEdit::singleline(&mut rule.priority)
+                                .hint_text("Priority")
+                                .desired_width(72.0),
+                        );
+                        if ui.button("Delete").clicked() {
+                            remove_idx = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(idx) = remove_idx {
+                rules.remove(idx);
+            }
+        });
+}
+
+fn edit_attr_rules(ui: &mut egui::Ui, rules: &mut Vec<AttrDraft>) {
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().faint_bg_color)
+        .inner_margin(egui::Margin::symmetric(10, 7))
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            if rule_list_header(ui, "Remote Attributes") {
+                rules.push(AttrDraft::default());
+            }
+            if rules.is_empty() {
+                ui.label(
+                    egui::RichText::new("No rules, the server decides modes and owners").weak(),
+                );
+                return;
+            }
+            let mut remove_idx = None;
+            egui::Grid::new("task_attr_rules")
+                .num_columns(6)
+                .show(ui, |ui| {
+                    for (idx, rule) in rules.iter_mut().enumerate() {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut rule.pattern)
+                                .hint_text("Pattern")
+                                .desired_width(200.0),
+                        );
+                        egui::ComboBox::from_id_salt(("task_attr_target", idx))
+                            .selected_text(attr_target_label(rule.target))
+                            .width(72.0)
+                            .show_ui(ui, |ui| {
+                                for target in [AttrTarget::Any, AttrTarget::File, AttrTarget::Dir] {
+                                    ui.selectable_value(
+                                        &mut rule.target,
+                                        target,
+                                        attr_target_label(target),
+                                    );
+                                }
+                            });
+                        for (value, hint) in [
+                            (&mut rule.mode, "Mode"),
+                            (&mut rule.uid, "UID"),
+                            (&mut rule.gid, "GID"),
+                        ] {
+                            ui.add(
+                                egui::TextEdit::singleline(value)
+                                    .hint_text(hint)
+                                    .desired_width(64.0),
+                            );
+                        }
+                        if ui.button("Delete").clicked() {
+                            remove_idx = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(idx) = remove_idx {
+                rules.remove(idx);
+            }
+        });
+}
+
+fn attr_target_label(target: AttrTarget) -> &'static str {
+    match target {
+        AttrTarget::Any => "Any",
+        AttrTarget::File => "Files",
+        AttrTarget::Dir => "Dirs",
+    }
+}
+
+/// What the rules being edited sync of the local folder.
+fn filter_preview_panel(ui: &mut egui::Ui, preview: &FilterPreviewState, width: f32) {
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().faint_bg_color)
+        .inner_margin(egui::Margin::same(8))
+        .show(ui, |ui| {
+            ui.set_width(width);
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Preview").small().weak());
+                if preview.running {
+                    ui.spinner();
+                }
+            });
+            let report = match &preview.result {
+                None => {
+                    ui.label(egui::RichText::new("Walking the local folder...").weak());
+                    return;
+                }
+                Some(Err(e)) => {
+                    ui.label(egui::RichText::new(e).weak());
+                    return;
+                }
+                Some(Ok(report)) => report,
+            };
+            ui.label(format!(
+                "{} file(s) synced, {} excluded, {} folder(s) skipped{}",
+                report.synced,
+                report.excluded,
+                report.skipped_dirs,
+                if report.truncated {
+                    " (stopped early, the folder is large)"
+                } else {
+                    ""
+                }
+            ));
+            egui::ScrollArea::vertical()
+                .id_salt("pattern_preview_scroll")
+                .max_height(150.0)
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    egui::Grid::new("pattern_preview_grid")
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for path in &report.synced_samples {
+                                ui.label("Synced");
+                                ui.label(egui::RichText::new(path).monospace());
+                                ui.end_row();
+                            }
+                            for explained in &report.excluded_samples {
+                                ui.label("Excluded");
+                                ui.label(egui::RichText::new(&explained.path).monospace());
+                                ui.end_row();
+                                ui.label("");
+                                ui.label(
+                                    egui::RichText::new(explained.reason.to_string())
+                                        .small()
+                                        .weak(),
+                                );
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
+
 fn pattern_preview(ui: &mut egui::Ui, label: &str, value: &str, empty_text: &str) -> bool {
     let mut modify_clicked = false;
     egui::Frame::group(ui.style())
@@ -573,3 +969,110 @@ fn dashboard_info_row(
     });
     ui.add_space(spacing);
 }
+
+fn drift_label(kind: DriftKind) -> &'static str {
+    match kind {
+        DriftKind::MissingRemote => "Missing remotely",
+        DriftKind::ExtraRemote => "Extra on remote",
+        DriftKind::SizeMismatch => "Size differs",
+        DriftKind::MtimeMismatch => "Remote older",
+        DriftKind::ChecksumMismatch => "Content differs",
+    }
+}
+
+fn size_text(size: Option<u64>) -> String {
+    size.map(|size| size.to_string())
+        .unwrap_or_else(|| "-".into())
+}
+
+fn render_progress(ui: &mut egui::Ui, progress: &SyncProgress) {
+    let eta = progress
+        .eta()
+        .map(|eta| format!(", {} left", duration_text(eta)))
+        .unwrap_or_default();
+    ui.add(
+        egui::ProgressBar::new(progress.fraction())
+            .show_percentage()
+            .text(format!(
+                "{} / {} ops, {} of {}{eta}",
+                progress.ops_done,
+                progress.ops_total,
+                bytes_text(progress.bytes_done),
+                bytes_text(progress.bytes_total),
+            )),
+    );
+    for op in &progress.in_flight {
+        if op.bytes_total == 0 {
+            continue;
+        }
+        ui.label(
+            egui::RichText::new(format!(
+                "{}  {} / {}",
+                op.remote,
+                bytes_text(op.bytes_done),
+                bytes_text(op.bytes_total)
+            ))
+            .monospace()
+            .weak(),
+        );
+    }
+}
+
+fn render_stats(ui: &mut egui::Ui, stats: &TaskStats) {
+    let throughput = stats
+        .throughput()
+        .map(|rate| format!("{}/s", bytes_text(rate as u64)))
+        .unwrap_or_else(|| "-".into());
+    let last_sync = stats
+        .since_last_success()
+        .map(|since| format!("{} ago", duration_text(since)))
+        .unwrap_or_else(|| "Never".into());
+    let rows = [
+        ("Uploaded", stats.files_uploaded.to_string()),
+        ("Sent", bytes_text(stats.bytes_sent)),
+        ("Removed", stats.files_removed.to_string()),
+        ("Throughput", throughput),
+        ("Renamed", stats.files_renamed.to_string()),
+        ("Last sync", last_sync),
+        ("Failures", stats.failures.to_string()),
+        ("Retries", stats.retries.to_string()),
+        ("Queued", stats.queue_depth.to_string()),
+        ("Outstanding", stats.outstanding_ops.to_string()),
+    ];
+    egui::Grid::new("task_stats_grid")
+        .num_columns(4)
+        .spacing([16.0, 4.0])
+        .show(ui, |ui| {
+            for pair in rows.chunks(2) {
+                for (label, value) in pair {
+                    ui.label(egui::RichText::new(*label).weak());
+                    ui.label(egui::RichText::new(value).strong());
+                }
+                ui.end_row();
+            }
+        });
+}
+
+fn bytes_text(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn duration_text(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3_600 {
+        format!("{}h {:02}m", secs / 3_600, secs % 3_600 / 60)
+    } else {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    }
+}
@@ -1,6 +1,10 @@
 use anyhow::{anyhow, Result};
 use eframe::egui::ThemePreference;
-use fsync_core::{Pattern, RemoteCfg, SyncTaskHandle, TaskConfig, TaskLog, TaskState};
+use fsync_core::{
+    AttrRule, AttrTarget, AuditReport, ChangeDetection, DeletionPolicy, FilterPreview, InitialSync,
+    Pattern, PriorityRule, RemoteAttrRules, RemoteCfg, SyncTaskHandle, TaskConfig, TaskLog,
+    TaskState, TaskStats, WatcherMode,
+};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -79,6 +83,45 @@ pub(crate) struct TaskView {
     pub(crate) last_operation_log_id: i64,
     pub(crate) state: TaskState,
     pub(crate) starting: bool,
+    pub(crate) audit: Option<AuditStatus>,
+    /// Totals of finished runs, as stored in the database
+    pub(crate) stats: TaskStats,
+}
+
+impl TaskView {
+    /// Stored totals plus the counters of the running task, if any. The
+    /// outbox size is a gauge and comes from the latest run only.
+    pub(crate) fn total_stats(&self) -> TaskStats {
+        match &self.handle {
+            Some(handle) => {
+                let live = handle.stats();
+                TaskStats {
+                    outstanding_ops: live.outstanding_ops,
+                    ..self.stats.merged(&live)
+                }
+            }
+            None => self.stats.clone(),
+        }
+    }
+}
+
+/// Latest drift audit of a task, shown on the dashboard.
+#[derive(Debug, Clone)]
+pub(crate) enum AuditStatus {
+    Running,
+    Done(AuditReport),
+    Failed(String),
+}
+
+/// Live preview of the rules in the pattern editor, computed off the UI
+/// thread.
+#[derive(Debug, Default)]
+pub(crate) struct FilterPreviewState {
+    /// Local folder, size filter, rules and `.gitignore` option of the
+    /// latest preview requested
+    pub(crate) key: Option<(String, String, Vec<String>, bool)>,
+    pub(crate) running: bool,
+    pub(crate) result: Option<Result<FilterPreview, String>>,
 }
 
 #[derive(Debug, Clone)]
@@ -97,6 +140,7 @@ pub(crate) struct LoadedTask {
     pub(crate) cfg: TaskConfig,
     pub(crate) remote_profile_id: Option<Uuid>,
     pub(crate) recent_logs: Vec<OperationLogRecord>,
+    pub(crate) stats: TaskStats,
 }
 
 #[derive(Default)]
@@ -113,16 +157,43 @@ pub(crate) struct Draft {
     pub(crate) local: String,
     pub(crate) remote: String,
     pub(crate) cache_dir: String,
-    pub(crate) include: String,
-    pub(crate) exclude: String,
+    pub(crate) filters: String,
+    pub(crate) gitignore: bool,
     pub(crate) size: String,
     pub(crate) scan_ms: String,
+    pub(crate) full_scan_secs: String,
     pub(crate) debounce_ms: String,
     pub(crate) retry_max: String,
     pub(crate) retry_backoff_ms: String,
+    pub(crate) change_detection: ChangeDetection,
+    pub(crate) deletion_policy: DeletionPolicy,
+    pub(crate) initial_sync: InitialSync,
+    pub(crate) watcher_mode: WatcherMode,
+    pub(crate) poll_interval_ms: String,
+    pub(crate) max_parallel_ops: String,
+    pub(crate) stale_temp_secs: String,
+    pub(crate) priorities: Vec<PriorityDraft>,
+    pub(crate) attributes: Vec<AttrDraft>,
     pub(crate) remote_profile_id: Option<Uuid>,
 }
 
+/// One upload priority rule being edited.
+#[derive(Clone, Default)]
+pub(crate) struct PriorityDraft {
+    pub(crate) pattern: String,
+    pub(crate) priority: String,
+}
+
+/// One remote attribute rule being edited; blank fields are left unset.
+#[derive(Clone, Default)]
+pub(crate) struct AttrDraft {
+    pub(crate) pattern: String,
+    pub(crate) target: AttrTarget,
+    pub(crate) mode: String,
+    pub(crate) uid: String,
+    pub(crate) gid: String,
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct RemoteProfileDraft {
     pub(crate) id: Uuid,
@@ -200,23 +271,45 @@ impl Draft {
                     .clone()
                     .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR).join(cfg.id.to_string())),
             ),
-            include: cfg
-                .include
-                .iter()
-                .map(|p| p.0.as_str())
-                .collect::<Vec<_>>()
-                .join(";"),
-            exclude: cfg
-                .exclude
+            filters: cfg
+                .filters
                 .iter()
                 .map(|p| p.0.as_str())
                 .collect::<Vec<_>>()
                 .join(";"),
+            gitignore: cfg.gitignore,
             size: cfg.size.clone().unwrap_or_default(),
             scan_ms: cfg.scan_ms.to_string(),
+            full_scan_secs: cfg.full_scan_secs.to_string(),
             debounce_ms: cfg.debounce_ms.to_string(),
             retry_max: cfg.retry_max.to_string(),
             retry_backoff_ms: cfg.retry_backoff_ms.to_string(),
+            change_detection: cfg.change_detection,
+            deletion_policy: cfg.deletion_policy,
+            initial_sync: cfg.initial_sync,
+            watcher_mode: cfg.watcher_mode,
+            poll_interval_ms: cfg.poll_interval_ms.to_string(),
+            max_parallel_ops: cfg.max_parallel_ops.to_string(),
+            stale_temp_secs: cfg.stale_temp_secs.to_string(),
+            priorities: cfg
+                .priorities
+                .iter()
+                .map(|rule| PriorityDraft {
+                    pattern: rule.pattern.0.clone(),
+                    priority: rule.priority.to_string(),
+                })
+                .collect(),
+            attributes: cfg
+                .attributes
+                .iter()
+                .map(|rule| AttrDraft {
+                    pattern: rule.pattern.0.clone(),
+                    target: rule.target,
+                    mode: rule.mode.clone().unwrap_or_default(),
+                    uid: rule.uid.map(|uid| uid.to_string()).unwrap_or_default(),
+                    gid: rule.gid.map(|gid| gid.to_string()).unwrap_or_default(),
+                })
+                .collect(),
             remote_profile_id: task.remote_profile_id,
         }
     }
@@ -231,22 +324,79 @@ impl Draft {
         if self.remote.trim().is_empty() {
             return Err(anyhow!("remote path is required"));
         }
-        Ok(TaskConfig {
+        let cfg = TaskConfig {
             id: self.id,
             name: self.name.trim().to_string(),
             local: PathBuf::from(self.local.trim()),
             remote: self.remote.trim().to_string(),
             cache_dir: blank_to_none(&self.cache_dir).map(PathBuf::from),
-            include: split_patterns(&self.include),
-            exclude: split_patterns(&self.exclude),
+            filters: split_patterns(&self.filters),
+            gitignore: self.gitignore,
             scan_ms: parse_u64(&self.scan_ms, "scan interval")?,
+            full_scan_secs: parse_u64(&self.full_scan_secs, "full scan interval")?,
             size: blank_to_none(&self.size),
+            change_detection: self.change_detection,
+            deletion_policy: self.deletion_policy,
+            initial_sync: self.initial_sync,
             retry_max: parse_u32(&self.retry_max, "retry max")?,
             retry_backoff_ms: parse_u64(&self.retry_backoff_ms, "retry backoff")?,
             debounce_ms: parse_u64(&self.debounce_ms, "debounce")?,
+            watcher_mode: self.watcher_mode,
+            poll_interval_ms: parse_u64(&self.poll_interval_ms, "poll interval")?,
+            max_parallel_ops: parse_usize(&self.max_parallel_ops, "parallel ops")?,
+            stale_temp_secs: parse_u64(&self.stale_temp_secs, "stale temp age")?,
+            priorities: self
+                .priorities
+                .iter()
+                .filter(|rule| !rule.pattern.trim().is_empty())
+                .map(PriorityDraft::to_rule)
+                .collect::<Result<_>>()?,
+            attributes: self
+                .attributes
+                .iter()
+                .filter(|rule| !rule.pattern.trim().is_empty())
+                .map(AttrDraft::to_rule)
+                .collect::<Result<_>>()?,
             remote_cfg: remote_profile
                 .map(remote_cfg_from_profile)
                 .unwrap_or_else(placeholder_remote_cfg),
+        };
+        // Templates are stored unexpanded; validate them before accepting the draft.
+        cfg.resolve_templates()?;
+        RemoteAttrRules::new(&cfg.remote, &cfg.attributes)?;
+        Ok(cfg)
+    }
+}
+
+impl PriorityDraft {
+    fn to_rule(&self) -> Result<PriorityRule> {
+        let pattern = self.pattern.trim();
+        let priority = self
+            .priority
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| anyhow!("priority of `{pattern}` must be an integer"))?;
+        Ok(PriorityRule {
+            pattern: Pattern(pattern.to_string()),
+            priority,
+        })
+    }
+}
+
+impl AttrDraft {
+    fn to_rule(&self) -> Result<AttrRule> {
+        let pattern = self.pattern.trim();
+        let id = |value: &str, label: &str| {
+            blank_to_none(value)
+                .map(|value| parse_u32(&value, &format!("{label} of `{pattern}`")))
+                .transpose()
+        };
+        Ok(AttrRule {
+            pattern: Pattern(pattern.to_string()),
+            target: self.target,
+            mode: blank_to_none(&self.mode),
+            uid: id(&self.uid, "uid")?,
+            gid: id(&self.gid, "gid")?,
         })
     }
 }
@@ -385,11 +535,20 @@ pub(crate) fn parse_u32(value: &str, label: &str) -> Result<u32> {
         .map_err(|_| anyhow!("{label} must be a non-negative integer"))
 }
 
+pub(crate) fn parse_usize(value: &str, label: &str) -> Result<usize> {
+    value
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| anyhow!("{label} must be a non-negative integer"))
+}
+
 pub(crate) fn state_label(state: &TaskState) -> String {
     match state {
         TaskState::Idle => "Idle".into(),
         TaskState::Starting(stage) => stage.clone(),
         TaskState::Running => "Running".into(),
+        TaskState::Paused => "Paused".into(),
+        TaskState::Offline(reason) => format!("Offline: {reason}"),
         TaskState::Error(e) => format!("Error: {e}"),
     }
 }
@@ -406,13 +565,23 @@ pub(crate) fn sample_task(
             local: PathBuf::from("."),
             remote: "/tmp/fsync".into(),
             cache_dir: Some(default_task_cache_dir(cache_root, &id.to_string())),
-            include: Vec::new(),
-            exclude: Vec::new(),
-            scan_ms: 300,
+            filters: Vec::new(),
+            gitignore: false,
+            scan_ms: TaskConfig::default_scan_ms(),
+            full_scan_secs: TaskConfig::default_full_scan_secs(),
             size: None,
-            retry_max: 3,
-            retry_backoff_ms: 500,
-            debounce_ms: 150,
+            change_detection: Default::default(),
+            deletion_policy: Default::default(),
+            initial_sync: Default::default(),
+            retry_max: TaskConfig::default_retry_max(),
+            retry_backoff_ms: TaskConfig::default_retry_backoff_ms(),
+            debounce_ms: TaskConfig::default_debounce_ms(),
+            watcher_mode: Default::default(),
+            poll_interval_ms: TaskConfig::default_poll_interval_ms(),
+            max_parallel_ops: TaskConfig::default_max_parallel_ops(),
+            stale_temp_secs: TaskConfig::default_stale_temp_secs(),
+            priorities: Vec::new(),
+            attributes: Vec::new(),
             remote_cfg: remote_profile
                 .map(remote_cfg_from_profile)
                 .unwrap_or_else(placeholder_remote_cfg),
@@ -424,6 +593,8 @@ pub(crate) fn sample_task(
         last_operation_log_id: 0,
         state: TaskState::Idle,
         starting: false,
+        audit: None,
+        stats: TaskStats::default(),
     }
 }
 
@@ -1,6 +1,9 @@
 use anyhow::{anyhow, Result};
-use fsync_core::{Pattern, TaskConfig, TaskState};
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use fsync_core::{
+    legacy_filter_rules, AttrRule, Pattern, PriorityRule, TaskConfig, TaskState, TaskStats,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{sqlite::SqlitePoolOptions, Row, SqlitePool};
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
@@ -119,6 +122,8 @@ pub(crate) fn load_config(
                 .unwrap_or_default(),
             state: TaskState::Idle,
             starting: false,
+            audit: None,
+            stats: task.stats,
         })
         .collect();
     state.selected = if state.tasks.is_empty() {
@@ -179,6 +184,15 @@ async fn migrate_database(pool: &SqlitePool) -> Result<()> {
             retry_max INTEGER NOT NULL,
             retry_backoff_ms INTEGER NOT NULL,
             debounce_ms INTEGER NOT NULL,
+            change_detection TEXT,
+            deletion_policy TEXT,
+            initial_sync TEXT,
+            watcher_mode TEXT,
+            poll_interval_ms INTEGER,
+            max_parallel_ops INTEGER,
+            stale_temp_secs INTEGER,
+            full_scan_secs INTEGER,
+            gitignore INTEGER,
             created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
             updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
             FOREIGN KEY (remote_profile_id) REFERENCES remote_profiles(id) ON DELETE SET NULL
@@ -187,21 +201,158 @@ async fn migrate_database(pool: &SqlitePool) -> Result<()> {
     )
     .execute(pool)
     .await?;
+    for (column, kind) in [
+        ("change_detection", "TEXT"),
+        ("deletion_policy", "TEXT"),
+        ("initial_sync", "TEXT"),
+        ("watcher_mode", "TEXT"),
+        ("poll_interval_ms", "INTEGER"),
+        ("max_parallel_ops", "INTEGER"),
+        ("stale_temp_secs", "INTEGER"),
+        ("gitignore", "INTEGER"),
+    ] {
+        add_column_if_missing(pool, "sync_tasks", column, kind).await?;
+    }
+    // Older versions scanned at most every 30 s whatever scan_ms said; keep
+    // that pace for their tasks now that short intervals are honoured.
+    if add_column_if_missing(pool, "sync_tasks", "full_scan_secs", "INTEGER").await? {
+        sqlx::query("UPDATE sync_tasks SET scan_ms = ?1 WHERE scan_ms < ?1")
+            .bind(30_000_i64)
+            .execute(pool)
+            .await?;
+    }
     crate::operation_logs::migrate(pool).await?;
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS sync_task_filters (
+        CREATE TABLE IF NOT EXISTS sync_task_rules (
+            task_id TEXT NOT NULL,
+            rule TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (task_id, position),
+            FOREIGN KEY (task_id) REFERENCES sync_tasks(id) ON DELETE CASCADE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    migrate_legacy_filters(pool).await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sync_task_priorities (
             task_id TEXT NOT NULL,
-            kind TEXT NOT NULL CHECK (kind IN ('include', 'exclude')),
             pattern TEXT NOT NULL,
+            priority INTEGER NOT NULL,
             position INTEGER NOT NULL,
-            PRIMARY KEY (task_id, kind, position),
+            PRIMARY KEY (task_id, position),
             FOREIGN KEY (task_id) REFERENCES sync_tasks(id) ON DELETE CASCADE
         )
         "#,
     )
     .execute(pool)
     .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sync_task_attributes (
+            task_id TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            target TEXT NOT NULL,
+            mode TEXT,
+            uid INTEGER,
+            gid INTEGER,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (task_id, position),
+            FOREIGN KEY (task_id) REFERENCES sync_tasks(id) ON DELETE CASCADE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // Not tied to sync_tasks by a foreign key: replace_state deletes and
+    // reinserts every task, which would wipe the totals.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_stats (
+            task_id TEXT PRIMARY KEY NOT NULL,
+            files_uploaded INTEGER NOT NULL,
+            files_removed INTEGER NOT NULL,
+            files_renamed INTEGER NOT NULL,
+            bytes_sent INTEGER NOT NULL,
+            failures INTEGER NOT NULL,
+            retries INTEGER NOT NULL,
+            transfer_ms INTEGER NOT NULL,
+            last_success INTEGER,
+            updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Add a column that older databases lack; new ones get it from `CREATE TABLE`.
+/// Returns whether the column was added.
+async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    kind: &str,
+) -> Result<bool> {
+    let present = sqlx::query_scalar::<_, i64>(&format!(
+        "SELECT COUNT(*) FROM pragma_table_info('{table}') WHERE name = ?1"
+    ))
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
+    if present == 0 {
+        sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))
+            .execute(pool)
+            .await?;
+    }
+    Ok(present == 0)
+}
+
+/// Turn the include/exclude lists of older databases into ordered rules.
+async fn migrate_legacy_filters(pool: &SqlitePool) -> Result<()> {
+    let legacy = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'sync_task_filters'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if legacy == 0 {
+        return Ok(());
+    }
+    let rows = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT task_id, kind, pattern FROM sync_task_filters ORDER BY task_id, kind, position",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut lists: HashMap<String, (Vec<Pattern>, Vec<Pattern>)> = HashMap::new();
+    for (task_id, kind, pattern) in rows {
+        let (include, exclude) = lists.entry(task_id).or_default();
+        match kind.as_str() {
+            "include" => include.push(Pattern(pattern)),
+            "exclude" => exclude.push(Pattern(pattern)),
+            _ => return Err(anyhow!("unsupported filter kind: {kind}")),
+        }
+    }
+    let mut tx = pool.begin().await?;
+    for (task_id, (include, exclude)) in lists {
+        for (position, rule) in legacy_filter_rules(&include, &exclude).iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO sync_task_rules (task_id, rule, position) VALUES (?1, ?2, ?3)",
+            )
+            .bind(&task_id)
+            .bind(&rule.0)
+            .bind(i64::try_from(position)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    sqlx::query("DROP TABLE sync_task_filters")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
     Ok(())
 }
 
@@ -294,25 +445,12 @@ async fn read_tasks(
     cache_root: &PathBuf,
     remote_profiles: &[RemoteProfile],
 ) -> Result<Vec<LoadedTask>> {
-    let rows = sqlx::query_as::<
-        _,
-        (
-            String,
-            String,
-            String,
-            String,
-            Option<String>,
-            Option<String>,
-            i64,
-            Option<String>,
-            i64,
-            i64,
-            i64,
-        ),
-    >(
+    let rows = sqlx::query(
         r#"
         SELECT id, name, local_path, remote_path, remote_profile_id, cache_dir, scan_ms,
-               size_filter, retry_max, retry_backoff_ms, debounce_ms
+               size_filter, retry_max, retry_backoff_ms, debounce_ms, change_detection,
+               deletion_policy, initial_sync, watcher_mode, poll_interval_ms, max_parallel_ops,
+               stale_temp_secs, full_scan_secs, gitignore
         FROM sync_tasks
         ORDER BY rowid
         "#,
@@ -325,36 +463,19 @@ async fn read_tasks(
         .map(|profile| (profile.id, profile.clone()))
         .collect::<HashMap<_, _>>();
     let mut tasks = Vec::with_capacity(rows.len());
-    for (
-        id,
-        name,
-        local_path,
-        remote_path,
-        remote_profile_id,
-        cache_dir,
-        scan_ms,
-        size_filter,
-        retry_max,
-        retry_backoff_ms,
-        debounce_ms,
-    ) in rows
-    {
-        let filter_rows = sqlx::query_as::<_, (String, String)>(
-            "SELECT kind, pattern FROM sync_task_filters WHERE task_id = ?1 ORDER BY kind, position",
+    for row in rows {
+        let id: String = row.try_get("id")?;
+        let filters = sqlx::query_scalar::<_, String>(
+            "SELECT rule FROM sync_task_rules WHERE task_id = ?1 ORDER BY position",
         )
         .bind(&id)
         .fetch_all(pool)
-        .await?;
-        let mut include = Vec::new();
-        let mut exclude = Vec::new();
-        for (kind, pattern) in filter_rows {
-            match kind.as_str() {
-                "include" => include.push(Pattern(pattern)),
-                "exclude" => exclude.push(Pattern(pattern)),
-                _ => return Err(anyhow!("unsupported filter kind: {kind}")),
-            }
-        }
-        let remote_profile_id = remote_profile_id
+        .await?
+        .into_iter()
+        .map(Pattern)
+        .collect();
+        let remote_profile_id = row
+            .try_get::<Option<String>, _>("remote_profile_id")?
             .as_deref()
             .map(Uuid::parse_str)
             .transpose()?;
@@ -362,34 +483,116 @@ async fn read_tasks(
             .and_then(|id| profiles_by_id.get(&id).cloned())
             .map(|profile| remote_cfg_from_profile(&profile))
             .unwrap_or_else(placeholder_remote_cfg);
-        let cache_dir = cache_dir
+        let cache_dir = row
+            .try_get::<Option<String>, _>("cache_dir")?
             .map(PathBuf::from)
             .unwrap_or_else(|| default_task_cache_dir(cache_root, &id));
+        // Columns added after the first release are NULL in older rows.
+        let optional = |column: &str| row.try_get::<Option<i64>, _>(column);
         tasks.push(LoadedTask {
             cfg: TaskConfig {
                 id: Uuid::parse_str(&id)?,
-                name,
-                local: PathBuf::from(local_path),
-                remote: remote_path,
+                name: row.try_get("name")?,
+                local: PathBuf::from(row.try_get::<String, _>("local_path")?),
+                remote: row.try_get("remote_path")?,
                 cache_dir: Some(cache_dir),
-                include,
-                exclude,
-                scan_ms: scan_ms.try_into()?,
-                size: size_filter,
-                retry_max: retry_max.try_into()?,
-                retry_backoff_ms: retry_backoff_ms.try_into()?,
-                debounce_ms: debounce_ms.try_into()?,
+                filters,
+                gitignore: optional("gitignore")?.is_some_and(|value| value != 0),
+                scan_ms: row.try_get::<i64, _>("scan_ms")?.try_into()?,
+                full_scan_secs: match optional("full_scan_secs")? {
+                    Some(value) => value.try_into()?,
+                    None => TaskConfig::default_full_scan_secs(),
+                },
+                size: row.try_get("size_filter")?,
+                change_detection: option_from_text(row.try_get("change_detection")?)?,
+                deletion_policy: option_from_text(row.try_get("deletion_policy")?)?,
+                initial_sync: option_from_text(row.try_get("initial_sync")?)?,
+                retry_max: row.try_get::<i64, _>("retry_max")?.try_into()?,
+                retry_backoff_ms: row.try_get::<i64, _>("retry_backoff_ms")?.try_into()?,
+                debounce_ms: row.try_get::<i64, _>("debounce_ms")?.try_into()?,
+                watcher_mode: option_from_text(row.try_get("watcher_mode")?)?,
+                poll_interval_ms: match optional("poll_interval_ms")? {
+                    Some(value) => value.try_into()?,
+                    None => TaskConfig::default_poll_interval_ms(),
+                },
+                max_parallel_ops: match optional("max_parallel_ops")? {
+                    Some(value) => value.try_into()?,
+                    None => TaskConfig::default_max_parallel_ops(),
+                },
+                stale_temp_secs: match optional("stale_temp_secs")? {
+                    Some(value) => value.try_into()?,
+                    None => TaskConfig::default_stale_temp_secs(),
+                },
+                priorities: read_task_priorities(pool, &id).await?,
+                attributes: read_task_attributes(pool, &id).await?,
                 remote_cfg,
             },
             remote_profile_id,
             recent_logs: OperationLogReader::new(pool.clone())
                 .read_recent(&id, 1_000)
                 .await?,
+            stats: read_task_stats(pool, &id).await?,
         });
     }
     Ok(tasks)
 }
 
+async fn read_task_priorities(pool: &SqlitePool, task_id: &str) -> Result<Vec<PriorityRule>> {
+    let rows = sqlx::query_as::<_, (String, i64)>(
+        "SELECT pattern, priority FROM sync_task_priorities WHERE task_id = ?1 ORDER BY position",
+    )
+    .bind(task_id)
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|(pattern, priority)| {
+            Ok(PriorityRule {
+                pattern: Pattern(pattern),
+                priority: priority.try_into()?,
+            })
+        })
+        .collect()
+}
+
+async fn read_task_attributes(pool: &SqlitePool, task_id: &str) -> Result<Vec<AttrRule>> {
+    let rows = sqlx::query_as::<_, (String, String, Option<String>, Option<i64>, Option<i64>)>(
+        r#"
+        SELECT pattern, target, mode, uid, gid FROM sync_task_attributes
+        WHERE task_id = ?1 ORDER BY position
+        "#,
+    )
+    .bind(task_id)
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|(pattern, target, mode, uid, gid)| {
+            Ok(AttrRule {
+                pattern: Pattern(pattern),
+                target: option_from_text(Some(target))?,
+                mode,
+                uid: uid.map(u32::try_from).transpose()?,
+                gid: gid.map(u32::try_from).transpose()?,
+            })
+        })
+        .collect()
+}
+
+/// Text stored for a task option enum: its config file spelling.
+fn option_text<T: Serialize>(value: &T) -> Result<String> {
+    match serde_yaml::to_value(value)? {
+        serde_yaml::Value::String(text) => Ok(text),
+        other => Err(anyhow!("unexpected task option value: {other:?}")),
+    }
+}
+
+/// Parse a stored task option enum, or its default when the column is NULL.
+fn option_from_text<T: DeserializeOwned + Default>(text: Option<String>) -> Result<T> {
+    match text {
+        Some(text) => Ok(serde_yaml::from_value(serde_yaml::Value::String(text))?),
+        None => Ok(T::default()),
+    }
+}
+
 pub(crate) async fn save_state(
     storage: &AppStorage,
     remote_profiles: &[RemoteProfile],
@@ -411,9 +614,15 @@ async fn replace_state(
     tasks: &[LoadedTask],
 ) -> Result<()> {
     let mut tx = pool.begin().await?;
-    sqlx::query("DELETE FROM sync_task_filters")
-        .execute(&mut *tx)
-        .await?;
+    for table in [
+        "sync_task_rules",
+        "sync_task_priorities",
+        "sync_task_attributes",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table}"))
+            .execute(&mut *tx)
+            .await?;
+    }
     sqlx::query("DELETE FROM sync_tasks")
         .execute(&mut *tx)
         .await?;
@@ -460,9 +669,14 @@ async fn replace_state(
             r#"
             INSERT INTO sync_tasks (
                 id, name, local_path, remote_path, remote_profile_id, cache_dir, scan_ms, size_filter,
-                retry_max, retry_backoff_ms, debounce_ms, updated_at
+                retry_max, retry_backoff_ms, debounce_ms, change_detection, deletion_policy,
+                initial_sync, watcher_mode, poll_interval_ms, max_parallel_ops, stale_temp_secs,
+                full_scan_secs, gitignore, updated_at
+            )
+            VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                ?19, ?20, CURRENT_TIMESTAMP
             )
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, CURRENT_TIMESTAMP)
             "#,
         )
         .bind(cfg.id.to_string())
@@ -476,39 +690,155 @@ async fn replace_state(
         .bind(i64::from(cfg.retry_max))
         .bind(i64::try_from(cfg.retry_backoff_ms)?)
         .bind(i64::try_from(cfg.debounce_ms)?)
+        .bind(option_text(&cfg.change_detection)?)
+        .bind(option_text(&cfg.deletion_policy)?)
+        .bind(option_text(&cfg.initial_sync)?)
+        .bind(option_text(&cfg.watcher_mode)?)
+        .bind(i64::try_from(cfg.poll_interval_ms)?)
+        .bind(i64::try_from(cfg.max_parallel_ops)?)
+        .bind(i64::try_from(cfg.stale_temp_secs)?)
+        .bind(i64::try_from(cfg.full_scan_secs)?)
+        .bind(i64::from(cfg.gitignore))
         .execute(&mut *tx)
         .await?;
 
-        for (position, pattern) in cfg.include.iter().enumerate() {
+        for (position, rule) in cfg.filters.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO sync_task_rules (task_id, rule, position) VALUES (?1, ?2, ?3)",
+            )
+            .bind(cfg.id.to_string())
+            .bind(&rule.0)
+            .bind(i64::try_from(position)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (position, rule) in cfg.priorities.iter().enumerate() {
             sqlx::query(
-                "INSERT INTO sync_task_filters (task_id, kind, pattern, position) VALUES (?1, 'include', ?2, ?3)",
+                "INSERT INTO sync_task_priorities (task_id, pattern, priority, position) VALUES (?1, ?2, ?3, ?4)",
             )
             .bind(cfg.id.to_string())
-            .bind(&pattern.0)
+            .bind(&rule.pattern.0)
+            .bind(i64::from(rule.priority))
             .bind(i64::try_from(position)?)
             .execute(&mut *tx)
             .await?;
         }
-        for (position, pattern) in cfg.exclude.iter().enumerate() {
+        for (position, rule) in cfg.attributes.iter().enumerate() {
             sqlx::query(
-                "INSERT INTO sync_task_filters (task_id, kind, pattern, position) VALUES (?1, 'exclude', ?2, ?3)",
+                r#"
+                INSERT INTO sync_task_attributes (task_id, pattern, target, mode, uid, gid, position)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                "#,
             )
             .bind(cfg.id.to_string())
-            .bind(&pattern.0)
+            .bind(&rule.pattern.0)
+            .bind(option_text(&rule.target)?)
+            .bind(&rule.mode)
+            .bind(rule.uid.map(i64::from))
+            .bind(rule.gid.map(i64::from))
             .bind(i64::try_from(position)?)
             .execute(&mut *tx)
             .await?;
         }
     }
 
+    for task in tasks {
+        upsert_task_stats(&mut tx, &task.cfg.id.to_string(), &task.stats).await?;
+    }
     sqlx::query("DELETE FROM task_operation_logs WHERE task_id NOT IN (SELECT id FROM sync_tasks)")
         .execute(&mut *tx)
         .await?;
+    sqlx::query("DELETE FROM task_stats WHERE task_id NOT IN (SELECT id FROM sync_tasks)")
+        .execute(&mut *tx)
+        .await?;
 
     tx.commit().await?;
     Ok(())
 }
 
+async fn read_task_stats(pool: &SqlitePool, task_id: &str) -> Result<TaskStats> {
+    let row = sqlx::query_as::<_, (i64, i64, i64, i64, i64, i64, i64, Option<i64>)>(
+        r#"
+        SELECT files_uploaded, files_removed, files_renamed, bytes_sent, failures, retries,
+               transfer_ms, last_success
+        FROM task_stats
+        WHERE task_id = ?1
+        "#,
+    )
+    .bind(task_id)
+    .fetch_optional(pool)
+    .await?;
+    let Some((uploaded, removed, renamed, bytes, failures, retries, transfer_ms, last_success)) =
+        row
+    else {
+        return Ok(TaskStats::default());
+    };
+    Ok(TaskStats {
+        files_uploaded: uploaded.try_into()?,
+        files_removed: removed.try_into()?,
+        files_renamed: renamed.try_into()?,
+        bytes_sent: bytes.try_into()?,
+        failures: failures.try_into()?,
+        retries: retries.try_into()?,
+        transfer_ms: transfer_ms.try_into()?,
+        last_success: last_success.map(u64::try_from).transpose()?,
+        queue_depth: 0,
+        outstanding_ops: 0,
+    })
+}
+
+/// Store the running totals of tasks outside a full [`save_state`], e.g.
+/// when a task stops.
+pub(crate) async fn save_task_stats(
+    storage: &AppStorage,
+    stats: &[(String, TaskStats)],
+) -> Result<()> {
+    let mut tx = storage.pool.begin().await?;
+    for (task_id, stats) in stats {
+        upsert_task_stats(&mut tx, task_id, stats).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn upsert_task_stats(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    task_id: &str,
+    stats: &TaskStats,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO task_stats (
+            task_id, files_uploaded, files_removed, files_renamed, bytes_sent, failures, retries,
+            transfer_ms, last_success, updated_at
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, CURRENT_TIMESTAMP)
+        ON CONFLICT (task_id) DO UPDATE SET
+            files_uploaded = excluded.files_uploaded,
+            files_removed = excluded.files_removed,
+            files_renamed = excluded.files_renamed,
+            bytes_sent = excluded.bytes_sent,
+            failures = excluded.failures,
+            retries = excluded.retries,
+            transfer_ms = excluded.transfer_ms,
+            last_success = excluded.last_success,
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(task_id)
+    .bind(i64::try_from(stats.files_uploaded)?)
+    .bind(i64::try_from(stats.files_removed)?)
+    .bind(i64::try_from(stats.files_renamed)?)
+    .bind(i64::try_from(stats.bytes_sent)?)
+    .bind(i64::try_from(stats.failures)?)
+    .bind(i64::try_from(stats.retries)?)
+    .bind(i64::try_from(stats.transfer_ms)?)
+    .bind(stats.last_success.map(i64::try_from).transpose()?)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
 pub(crate) fn open_local_dir(path: PathBuf) -> Result<()> {
     let path = path.canonicalize().unwrap_or(path);
     if !path.exists() {
@@ -62,6 +62,33 @@ pub(crate) fn edit_field(ui: &mut egui::Ui, label: &str, value: &mut String) {
         });
 }
 
+pub(crate) fn edit_choice<T: Copy + PartialEq>(
+    ui: &mut egui::Ui,
+    label: &str,
+    value: &mut T,
+    options: &[(T, &str)],
+) {
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().faint_bg_color)
+        .inner_margin(egui::Margin::symmetric(10, 7))
+        .show(ui, |ui| {
+            ui.set_min_height(58.0);
+            ui.label(egui::RichText::new(label).small().weak());
+            let selected = options
+                .iter()
+                .find(|(option, _)| option == value)
+                .map_or("", |(_, text)| text);
+            egui::ComboBox::from_id_salt(label)
+                .selected_text(selected)
+                .width(ui.available_width())
+                .show_ui(ui, |ui| {
+                    for (option, text) in options {
+                        ui.selectable_value(value, *option, *text);
+                    }
+                });
+        });
+}
+
 pub(crate) fn edit_password(
     ui: &mut egui::Ui,
     label: &str,
@@ -146,6 +173,7 @@ pub(crate) fn status_color(ui: &egui::Ui, state: &TaskState, starting: bool) ->
             TaskState::Idle => visuals.widgets.noninteractive.fg_stroke.color,
             TaskState::Starting(_) => visuals.warn_fg_color,
             TaskState::Running => visuals.hyperlink_color,
+            TaskState::Paused | TaskState::Offline(_) => visuals.warn_fg_color,
             TaskState::Error(_) => visuals.error_fg_color,
         }
     }